@@ -39,6 +39,9 @@ struct Args {
     /// output path
     #[argh(option, short='o', default = "PathBuf::from(\"output\")")]
     output: PathBuf,
+    /// compare the vertex factory layouts with the shader input signatures
+    #[argh(switch, short='l')]
+    check_layouts: bool,
 }
 
 fn get_template_data_path(base: &PathBuf, rel: &PathBuf) -> PathBuf {
@@ -59,6 +62,10 @@ fn main() -> anyhow::Result<()> {
     let cache = load_shader_cache(&args.engine.join(Path::new("shader_final.cache")))?;
     let man = Manager::from_dyn_cache(cache)?;
 
+    if args.check_layouts {
+        return vertexfactory::check_layouts(&man, &args.templates);
+    }
+
     let mat = man.materials.get(&CNameKey32::from(CName::new("metal_base"))).unwrap();
 
     println!("Name = {}", mat.name);
@@ -1,10 +1,13 @@
 use std::collections::HashSet;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 use serde::{de::Error, Deserialize, Serialize};
 
 use shaderpunk::rtti_types::enums::EMaterialVertexFactory;
 use shaderpunk::hashmap::CNameKey32;
+use shaderpunk::manager::Manager;
+use shaderpunk::signature::{check_layout, vertex_layouts};
 
 use crate::link::{Link, LinkList, Linkable};
 
@@ -39,6 +42,47 @@ impl VertexFactoryVM {
     }
 }
 
+/// Compares the hand written layouts against the input signatures of the vertex shaders,
+/// printing the generated `VS_INPUT` where there's no layout to compare against
+pub fn check_layouts(man: &Manager, templates: &Path) -> anyhow::Result<()> {
+    for layout in vertex_layouts(man) {
+        let path = templates.join(VertexFactoryVM::get_path_from_enum(layout.vertex_factory)).with_extension("toml");
+
+        println!("{}: {} techniques", layout.vertex_factory, layout.techniques);
+        for c in &layout.conflicts {
+            println!("  CONFLICT: {}", c);
+        }
+
+        // Without a usable template there's nothing to compare, same as an empty layout
+        let hand_written = match fs::read_to_string(&path) {
+            Ok(text) => match toml::from_str::<VertexFactoryVM>(&text) {
+                Ok(vm) => vm.layout,
+                Err(e) => {
+                    println!("  INVALID TEMPLATE: {}: {}", path.display(), e);
+                    String::new()
+                },
+            },
+            Err(_) => String::new(),
+        };
+
+        match check_layout(&layout, &hand_written) {
+            Ok(issues) => {
+                for i in issues {
+                    println!("  MISMATCH: {}", i);
+                }
+            },
+            Err(_) => {
+                println!("  no layout, generated:");
+                for line in layout.signature.to_hlsl("VS_INPUT").lines() {
+                    println!("    {}", line);
+                }
+            },
+        }
+    }
+
+    Ok(())
+}
+
 impl Linkable<VertexFactoryVM> for VertexFactoryVM {
     fn as_link(self: &Self) -> Link {
         Link {
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::bail;
+use argh::FromArgs;
+
+use shaderpunk::bundle::encode::encoded_size;
+use shaderpunk::glob::Glob;
+
+use crate::load_shader_cache;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListKind {
+    Materials,
+    Techniques,
+    Shaders,
+}
+
+impl FromStr for ListKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "materials" => Ok(ListKind::Materials),
+            "techniques" => Ok(ListKind::Techniques),
+            "shaders" => Ok(ListKind::Shaders),
+            _ => bail!("Unknown list {}, expected materials, techniques or shaders", s),
+        }
+    }
+}
+
+/// list the materials, techniques or shaders of a cache
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "list")]
+pub struct ListArgs {
+    /// materials, techniques or shaders
+    #[argh(positional)]
+    kind: ListKind,
+    /// shader cache
+    #[argh(positional)]
+    cache: PathBuf,
+    /// only matching materials
+    #[argh(option, short = 'm')]
+    material: Option<Glob>,
+}
+
+pub fn run(args: &ListArgs) -> anyhow::Result<()> {
+    let cache = load_shader_cache(&args.cache)?;
+
+    let materials = cache.materials.iter()
+        .filter(|m| args.material.as_ref().is_none_or(|g| g.is_match(m.material_name())));
+
+    match args.kind {
+        ListKind::Materials => {
+            // Techniques per material, in first-use order
+            let mut order: Vec<&str> = Vec::new();
+            let mut counts: HashMap<&str, usize> = HashMap::new();
+            for m in materials {
+                let count = counts.entry(m.material_name()).or_default();
+                if *count == 0 {
+                    order.push(m.material_name());
+                }
+                *count += 1;
+            }
+
+            for name in order {
+                println!("{:<64} {:>4} techniques", name, counts[name]);
+            }
+        },
+        ListKind::Techniques => {
+            for m in materials {
+                println!("[{:016X}] VS [{:016X}] PS [{:016X}] {}", m.hash, m.vs_hash, m.ps_hash, m.name);
+            }
+        },
+        ListKind::Shaders => {
+            // Shader hash -> (vertex users, pixel users)
+            let mut users: HashMap<u64, (usize, usize)> = HashMap::new();
+            for m in materials {
+                users.entry(m.vs_hash).or_default().0 += 1;
+                users.entry(m.ps_hash).or_default().1 += 1;
+            }
+
+            for s in &cache.shaders {
+                let Some((vs, ps)) = users.get(&s.hash) else {
+                    if args.material.is_none() {
+                        println!("[{:016X}] params [{:016X}] {:>10} bytes unused", s.hash, s.params, encoded_size(s));
+                    }
+                    continue;
+                };
+                println!(
+                    "[{:016X}] params [{:016X}] {:>10} bytes {:>5} VS {:>5} PS",
+                    s.hash, s.params, encoded_size(s), vs, ps
+                );
+            }
+        },
+    }
+
+    Ok(())
+}
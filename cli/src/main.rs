@@ -0,0 +1,501 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use argh::FromArgs;
+
+use shaderpunk::analysis::analyze;
+use shaderpunk::bundle::dyn_cache::{ChunkOrder, DynamicCacheFile, SaveOptions};
+use shaderpunk::debuginfo::DebugIndex;
+use shaderpunk::diff::diff_caches;
+use shaderpunk::export::dot::export_dot;
+use shaderpunk::export::sqlite::export_sqlite;
+use shaderpunk::gc::collect_garbage;
+use shaderpunk::glob::Glob;
+use shaderpunk::manager::Manager;
+use shaderpunk::material::TechniqueFilter;
+use shaderpunk::optimize::dedup_shaders;
+use shaderpunk::recipe::Recipe;
+use shaderpunk::renderstage::{check_passes, unknown_manager_stages, unknown_stages, RenderStage};
+use shaderpunk::repair::{self, RepairOptions};
+use shaderpunk::stats::stats_report;
+use shaderpunk::rtti_types::enums::EMaterialVertexFactory;
+use shaderpunk::shader::ShaderType;
+use shaderpunk::strip::{strip_techniques, StripRules};
+
+mod list;
+mod unpack;
+mod verify;
+
+/// headless shader cache tool
+#[derive(Debug, FromArgs)]
+#[argh(help_triggers("-h", "--help"))]
+struct Args {
+    #[argh(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Info(InfoArgs),
+    List(list::ListArgs),
+    Extract(ExtractArgs),
+    Unpack(unpack::UnpackArgs),
+    Pack(unpack::PackArgs),
+    Optimize(OptimizeArgs),
+    Replace(ReplaceArgs),
+    Analyze(AnalyzeArgs),
+    Stats(StatsArgs),
+    Sources(SourcesArgs),
+    Diff(DiffArgs),
+    Verify(verify::VerifyArgs),
+    Repair(RepairArgs),
+    Sqlite(SqliteArgs),
+    Dot(DotArgs),
+}
+
+/// print the footer of a cache
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "info")]
+struct InfoArgs {
+    /// shader cache
+    #[argh(positional)]
+    cache: PathBuf,
+}
+
+/// write compiled shader blobs to a directory
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "extract")]
+struct ExtractArgs {
+    /// shader cache
+    #[argh(positional)]
+    cache: PathBuf,
+    /// output directory
+    #[argh(option, short = 'o', default = "PathBuf::from(\"shaders\")")]
+    output: PathBuf,
+    /// only shaders used by matching materials
+    #[argh(option, short = 'm')]
+    material: Option<Glob>,
+    /// only the shader with this hash, can be repeated
+    #[argh(option)]
+    hash: Vec<String>,
+}
+
+/// deduplicate shaders, optionally after stripping debug techniques and applying a recipe
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "optimize")]
+struct OptimizeArgs {
+    /// shader cache
+    #[argh(positional)]
+    input: PathBuf,
+    /// output cache
+    #[argh(option, short = 'o')]
+    output: PathBuf,
+    /// recipe applied before optimizing
+    #[argh(option, short = 'r')]
+    recipe: Option<PathBuf>,
+    /// remove debug and editor-only techniques
+    #[argh(switch)]
+    strip_debug: bool,
+    /// chunk order: preserve, hash or first-use
+    #[argh(option, default = "ChunkOrder::Preserve")]
+    order: ChunkOrder,
+    /// recompute the digest of every shader blob that doesn't match its contents, edited or not
+    #[argh(switch)]
+    resign: bool,
+    /// write the current time into the footer instead of keeping the loaded one
+    #[argh(switch)]
+    stamp: bool,
+    /// print what would change without saving
+    #[argh(switch, short = 'n')]
+    dry_run: bool,
+    /// save even if a technique's VS outputs don't link with its PS inputs
+    #[argh(switch)]
+    force: bool,
+}
+
+/// swap the compiled VS or PS of one technique for a new blob
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "replace")]
+struct ReplaceArgs {
+    /// shader cache
+    #[argh(positional)]
+    input: PathBuf,
+    /// technique hash, as printed by `list techniques`
+    #[argh(positional)]
+    technique: String,
+    /// shader stage, Vertex or Pixel
+    #[argh(positional)]
+    stage: ShaderType,
+    /// compiled shader blob
+    #[argh(positional)]
+    file: PathBuf,
+    /// output cache
+    #[argh(option, short = 'o')]
+    output: PathBuf,
+    /// replace even if the blob doesn't line up with the original, and save despite
+    /// linkage errors
+    #[argh(switch)]
+    force: bool,
+    /// recompute the digest of every shader blob that doesn't match its contents, edited or not
+    #[argh(switch)]
+    resign: bool,
+    /// write the current time into the footer instead of keeping the loaded one
+    #[argh(switch)]
+    stamp: bool,
+}
+
+/// show where the bytes of a cache go
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "analyze")]
+struct AnalyzeArgs {
+    /// shader cache
+    #[argh(positional)]
+    cache: PathBuf,
+    /// rows per table
+    #[argh(option, default = "20")]
+    top: usize,
+    /// print JSON instead of tables
+    #[argh(switch)]
+    json: bool,
+}
+
+/// rank shaders by instruction counts, overall and per pass and material
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "stats")]
+struct StatsArgs {
+    /// shader cache
+    #[argh(positional)]
+    cache: PathBuf,
+    /// rows per table
+    #[argh(option, default = "20")]
+    top: usize,
+    /// print JSON instead of tables
+    #[argh(switch)]
+    json: bool,
+}
+
+/// map shaders to their debug names and source files
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "sources")]
+struct SourcesArgs {
+    /// shader cache
+    #[argh(positional)]
+    cache: PathBuf,
+    /// only list the shaders referencing this source file or debug name
+    #[argh(option, short = 'f')]
+    find: Option<String>,
+    /// print JSON instead of tables
+    #[argh(switch)]
+    json: bool,
+}
+
+/// compare two caches
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "diff")]
+struct DiffArgs {
+    /// original cache
+    #[argh(positional)]
+    old: PathBuf,
+    /// modified cache
+    #[argh(positional)]
+    new: PathBuf,
+}
+
+/// fix dangling references, duplicate chunks and a stale footer
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "repair")]
+struct RepairArgs {
+    /// shader cache
+    #[argh(positional)]
+    input: PathBuf,
+    /// output cache
+    #[argh(option, short = 'o')]
+    output: PathBuf,
+    /// redirect dangling techniques to their fallback's shaders instead of dropping them
+    #[argh(switch)]
+    redirect: bool,
+    /// re-sign shader blobs whose digest doesn't match their contents, instead of reporting them
+    #[argh(switch)]
+    resign: bool,
+    /// print the changelog without saving
+    #[argh(switch, short = 'n')]
+    dry_run: bool,
+    /// save even if a technique's VS outputs don't link with its PS inputs
+    #[argh(switch)]
+    force: bool,
+}
+
+/// export the cache model to a SQLite database
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "sqlite")]
+struct SqliteArgs {
+    /// shader cache
+    #[argh(positional)]
+    cache: PathBuf,
+    /// output database, replaced if it exists
+    #[argh(option, short = 'o', default = "PathBuf::from(\"shaders.db\")")]
+    output: PathBuf,
+}
+
+/// export the material, technique and shader graph as GraphViz DOT
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "dot")]
+struct DotArgs {
+    /// shader cache
+    #[argh(positional)]
+    cache: PathBuf,
+    /// output file, stdout if omitted
+    #[argh(option, short = 'o')]
+    output: Option<PathBuf>,
+    /// only matching materials, can be repeated
+    #[argh(option, short = 'm')]
+    material: Vec<Glob>,
+    /// only techniques of this pass, can be repeated
+    #[argh(option)]
+    pass: Vec<RenderStage>,
+    /// only techniques of this vertex factory, can be repeated
+    #[argh(option)]
+    vf: Vec<EMaterialVertexFactory>,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args: Args = argh::from_env();
+
+    match args.command {
+        Command::Info(args) => info(&args),
+        Command::List(args) => list::run(&args),
+        Command::Extract(args) => extract(&args),
+        Command::Unpack(args) => unpack::unpack(&args),
+        Command::Pack(args) => unpack::pack(&args),
+        Command::Optimize(args) => optimize(&args),
+        Command::Replace(args) => replace(&args),
+        Command::Analyze(args) => {
+            let report = analyze(&load_shader_cache(&args.cache)?, args.top)?;
+            if args.json {
+                println!("{}", report.to_json()?);
+            }
+            else {
+                print!("{}", report);
+            }
+            Ok(())
+        },
+        Command::Stats(args) => {
+            let manager = Manager::from_dyn_cache(load_shader_cache(&args.cache)?)?;
+            let report = stats_report(&manager, args.top);
+            if args.json {
+                println!("{}", report.to_json()?);
+            }
+            else {
+                print!("{}", report);
+            }
+            Ok(())
+        },
+        Command::Sources(args) => {
+            let manager = Manager::from_dyn_cache(load_shader_cache(&args.cache)?)?;
+            let index = DebugIndex::build(&manager);
+            if let Some(find) = &args.find {
+                let mut hashes = index.shaders_for_source(find);
+                hashes.extend(index.shaders_for_name(find));
+                for hash in hashes {
+                    println!("{:016X}", hash);
+                }
+            }
+            else if args.json {
+                println!("{}", index.to_json()?);
+            }
+            else {
+                print!("{}", index);
+            }
+            Ok(())
+        },
+        Command::Diff(args) => {
+            let diff = diff_caches(&load_shader_cache(&args.old)?, &load_shader_cache(&args.new)?);
+            if diff.is_empty() {
+                println!("No differences");
+            }
+            else {
+                print!("{}", diff);
+            }
+            Ok(())
+        },
+        Command::Verify(args) => verify::run(&args),
+        Command::Repair(args) => repair(&args),
+        Command::Sqlite(args) => {
+            let manager = Manager::from_dyn_cache(load_shader_cache(&args.cache)?)?;
+            export_sqlite(&manager, &args.output)
+        },
+        Command::Dot(args) => dot(args),
+    }
+}
+
+pub fn load_shader_cache(path: &Path) -> anyhow::Result<DynamicCacheFile> {
+    DynamicCacheFile::load_file(path)
+        .with_context(|| format!("Failed to load shader cache {}", path.display()))
+}
+
+/// VS to PS interface mismatches are easy to introduce by swapping blobs and only show up
+/// in game. Unless validation is off, the save checks linkage first and its error lists them.
+pub fn save_shader_cache(cache: &mut DynamicCacheFile, path: &Path, options: &SaveOptions) -> anyhow::Result<()> {
+    cache.save_file_with(path, options)
+        .with_context(|| if options.validate {
+            format!("Failed to save shader cache {}, --force skips the linkage check", path.display())
+        }
+        else {
+            format!("Failed to save shader cache {}", path.display())
+        })
+}
+
+pub fn parse_hash(s: &str) -> anyhow::Result<u64> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+    u64::from_str_radix(digits, 16).with_context(|| format!("Invalid hash {}", s))
+}
+
+fn info(args: &InfoArgs) -> anyhow::Result<()> {
+    let cache = load_shader_cache(&args.cache)?;
+    let info = &cache.info;
+
+    println!("Timestamp:    {}", info.timestamp);
+    println!("Unknown hash: {:016X}", info.unknown_hash);
+    println!();
+    println!("{:<12} {:>8} {:>12} {:>12}", "Section", "Count", "Offset", "Size");
+    println!("{:<12} {:>8} {:>12} {:>12}", "Shaders", info.shader_count, 0, info.shader_size);
+    println!("{:<12} {:>8} {:>12} {:>12}", "Techniques", info.material_count, info.material_offset, info.material_size);
+    println!("{:<12} {:>8} {:>12} {:>12}", "Params", info.param_count, info.param_offset, info.param_size);
+    println!("{:<12} {:>8} {:>12} {:>12}", "Timestamps", cache.timestamps.len(), info.time_offset, info.time_size);
+    println!("{:<12} {:>8} {:>12} {:>12}", "Includes", info.include_count, info.include_offset, info.include_size);
+
+    let unknown = unknown_stages(&cache)?;
+    if !unknown.is_empty() {
+        println!();
+        println!("Unregistered render stages:");
+        for (stage, count) in unknown {
+            println!("  {:<40} {:>8} techniques", stage, count);
+        }
+    }
+
+    Ok(())
+}
+
+fn extract(args: &ExtractArgs) -> anyhow::Result<()> {
+    let cache = load_shader_cache(&args.cache)?;
+
+    let hashes: Vec<u64> = args.hash.iter()
+        .map(|h| parse_hash(h))
+        .collect::<anyhow::Result<_>>()?;
+
+    let mut used: HashSet<u64> = HashSet::new();
+    if let Some(glob) = &args.material {
+        for m in cache.materials.iter().filter(|m| glob.is_match(m.material_name())) {
+            used.extend([m.vs_hash, m.ps_hash]);
+        }
+    }
+
+    fs::create_dir_all(&args.output)
+        .with_context(|| format!("Failed to create {}", args.output.display()))?;
+
+    let mut count: usize = 0;
+    for s in &cache.shaders {
+        if !hashes.is_empty() && !hashes.contains(&s.hash) {
+            continue;
+        }
+        if args.material.is_some() && !used.contains(&s.hash) {
+            continue;
+        }
+
+        let path = args.output.join(format!("{:016X}.bin", s.hash));
+        fs::write(&path, &s.compiled)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        count += 1;
+    }
+
+    println!("{} shaders extracted to {}", count, args.output.display());
+    Ok(())
+}
+
+fn optimize(args: &OptimizeArgs) -> anyhow::Result<()> {
+    let mut cache = load_shader_cache(&args.input)?;
+
+    if let Some(path) = &args.recipe {
+        let recipe = Recipe::load(path)?;
+        print!("{}", recipe.apply(&mut cache)?);
+    }
+
+    if args.strip_debug {
+        print!("{}", strip_techniques(&mut cache, &StripRules::debug())?);
+    }
+
+    print!("{}", dedup_shaders(&mut cache));
+    print!("{}", collect_garbage(&mut cache, false));
+
+    if args.dry_run {
+        return Ok(());
+    }
+
+    let options = SaveOptions {
+        order: args.order,
+        resign: args.resign,
+        stamp_now: args.stamp,
+        validate: !args.force,
+    };
+    save_shader_cache(&mut cache, &args.output, &options)
+}
+
+fn replace(args: &ReplaceArgs) -> anyhow::Result<()> {
+    let mut cache = load_shader_cache(&args.input)?;
+
+    let hash = parse_hash(&args.technique)?;
+    let technique = cache.materials.iter()
+        .find(|m| m.hash == hash)
+        .with_context(|| format!("No technique with hash {:016X}", hash))?;
+    let material = technique.material_name().to_string();
+    let desc = technique.decode_desc()?;
+
+    let compiled = fs::read(&args.file)
+        .with_context(|| format!("Failed to read shader {}", args.file.display()))?;
+
+    print!("{}", cache.replace_shader(&material, &desc, args.stage, compiled, args.force)?);
+
+    let options = SaveOptions { resign: args.resign, stamp_now: args.stamp, validate: !args.force, ..Default::default() };
+    save_shader_cache(&mut cache, &args.output, &options)
+}
+
+fn repair(args: &RepairArgs) -> anyhow::Result<()> {
+    // The footer offsets are exactly what may be broken
+    let file = File::open(&args.input)
+        .with_context(|| format!("Failed to open {}", args.input.display()))?;
+    let mut cache = DynamicCacheFile::load_lenient(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to load shader cache {}", args.input.display()))?;
+
+    let report = repair::repair(&mut cache, RepairOptions { redirect: args.redirect, resign: args.resign });
+    print!("{}", report);
+
+    if args.dry_run {
+        return Ok(());
+    }
+
+    let options = SaveOptions { validate: !args.force, ..Default::default() };
+    save_shader_cache(&mut cache, &args.output, &options)
+}
+
+fn dot(args: DotArgs) -> anyhow::Result<()> {
+    let manager = Manager::from_dyn_cache(load_shader_cache(&args.cache)?)?;
+    let filter = TechniqueFilter {
+        materials: args.material,
+        passes: args.pass,
+        vertex_factories: args.vf,
+    };
+    check_passes(&filter.passes, || Ok(unknown_manager_stages(&manager)))?;
+
+    let dot = export_dot(&manager, &filter);
+    match &args.output {
+        Some(path) => fs::write(path, dot).with_context(|| format!("Failed to write {}", path.display())),
+        None => {
+            print!("{}", dot);
+            Ok(())
+        },
+    }
+}
@@ -0,0 +1,230 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use argh::FromArgs;
+use serde::{Deserialize, Serialize};
+
+use shaderpunk::bundle::decode::{Decode, DecodeExt};
+use shaderpunk::bundle::dyn_cache::{DynamicCacheFile, InfoBlock, SaveOptions, ShaderChunk};
+use shaderpunk::bundle::encode::{Encode, EncodeExt};
+
+use crate::{load_shader_cache, parse_hash, save_shader_cache};
+
+const MANIFEST: &str = "manifest.toml";
+
+/// split a cache into a directory of shader blobs and section files
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "unpack")]
+pub struct UnpackArgs {
+    /// shader cache
+    #[argh(positional)]
+    cache: PathBuf,
+    /// output directory
+    #[argh(option, short = 'o')]
+    output: PathBuf,
+}
+
+/// rebuild a cache from an unpacked directory
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "pack")]
+pub struct PackArgs {
+    /// unpacked directory
+    #[argh(positional)]
+    input: PathBuf,
+    /// output cache
+    #[argh(option, short = 'o')]
+    output: PathBuf,
+    /// recompute the digest of every shader blob that doesn't match its contents, edited or not
+    #[argh(switch)]
+    resign: bool,
+    /// save even if a technique's VS outputs don't link with its PS inputs
+    #[argh(switch)]
+    force: bool,
+}
+
+/// Everything except the shader blobs is stored as the encoded chunks of each section,
+/// shaders are listed here in file order so their blobs can be edited in place.
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    materials: usize,
+    params: usize,
+    timestamps: usize,
+    includes: usize,
+    #[serde(rename = "shader")]
+    shaders: Vec<ShaderEntry>,
+}
+
+/// Hashes are hex strings, as TOML integers are signed 64-bit
+#[derive(Serialize, Deserialize)]
+struct ShaderEntry {
+    hash: String,
+    params: String,
+    file: PathBuf,
+}
+
+fn write_section<A: Encode>(dir: &Path, name: &str, chunks: &[A]) -> anyhow::Result<()> {
+    let mut data: Vec<u8> = Vec::new();
+    for chunk in chunks {
+        data.encode(chunk)?;
+    }
+    let path = dir.join(name);
+    fs::write(&path, data).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn read_section<A: Decode>(dir: &Path, name: &str, count: usize) -> anyhow::Result<Vec<A>> {
+    let path = dir.join(name);
+    let data = fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    let mut input = io::Cursor::new(data.as_slice());
+    let mut chunks: Vec<A> = Vec::with_capacity(count);
+    for i in 0..count {
+        chunks.push(input.decode().with_context(|| format!("Failed to decode chunk {} of {}", i, path.display()))?);
+    }
+
+    if input.position() != data.len() as u64 {
+        bail!("{} has {} trailing bytes", path.display(), data.len() as u64 - input.position());
+    }
+
+    Ok(chunks)
+}
+
+pub fn unpack(args: &UnpackArgs) -> anyhow::Result<()> {
+    let cache = load_shader_cache(&args.cache)?;
+    let dir = &args.output;
+
+    fs::create_dir_all(dir.join("shaders"))
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let mut shaders: Vec<ShaderEntry> = Vec::with_capacity(cache.shaders.len());
+    for (i, s) in cache.shaders.iter().enumerate() {
+        // The same hash can be stored more than once
+        let mut file = PathBuf::from(format!("shaders/{:016X}.bin", s.hash));
+        if shaders.iter().any(|e| e.file == file) {
+            file = PathBuf::from(format!("shaders/{:016X}_{}.bin", s.hash, i));
+        }
+
+        fs::write(dir.join(&file), &s.compiled)
+            .with_context(|| format!("Failed to write {}", file.display()))?;
+
+        shaders.push(ShaderEntry {
+            hash: format!("{:016X}", s.hash),
+            params: format!("{:016X}", s.params),
+            file,
+        });
+    }
+
+    write_section(dir, "materials.bin", &cache.materials)?;
+    write_section(dir, "params.bin", &cache.params)?;
+    write_section(dir, "timestamps.bin", &cache.timestamps)?;
+    write_section(dir, "includes.bin", &cache.includes)?;
+    write_section(dir, "footer.bin", std::slice::from_ref(&cache.info))?;
+
+    let manifest = Manifest {
+        materials: cache.materials.len(),
+        params: cache.params.len(),
+        timestamps: cache.timestamps.len(),
+        includes: cache.includes.len(),
+        shaders,
+    };
+    fs::write(dir.join(MANIFEST), toml::to_string(&manifest)?)
+        .with_context(|| format!("Failed to write {}", MANIFEST))?;
+
+    println!("{} shaders unpacked to {}", cache.shaders.len(), dir.display());
+    Ok(())
+}
+
+pub fn pack(args: &PackArgs) -> anyhow::Result<()> {
+    let dir = &args.input;
+
+    let path = dir.join(MANIFEST);
+    let manifest: Manifest = toml::from_str(
+        &fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?
+    ).with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    let mut shaders: Vec<ShaderChunk> = Vec::with_capacity(manifest.shaders.len());
+    for e in &manifest.shaders {
+        let path = dir.join(&e.file);
+        shaders.push(ShaderChunk {
+            hash: parse_hash(&e.hash)?,
+            params: parse_hash(&e.params)?,
+            compiled: fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?,
+        });
+    }
+
+    let info: Vec<InfoBlock> = read_section(dir, "footer.bin", 1)?;
+
+    let mut cache = DynamicCacheFile {
+        info: info.into_iter().next().unwrap_or_default(),
+        shaders,
+        materials: read_section(dir, "materials.bin", manifest.materials)?,
+        params: read_section(dir, "params.bin", manifest.params)?,
+        timestamps: read_section(dir, "timestamps.bin", manifest.timestamps)?,
+        includes: read_section(dir, "includes.bin", manifest.includes)?,
+    };
+
+    // Loaded hashes and the footer are kept, an unchanged directory only differs from the
+    // unpacked cache in the material fields the game ignores, which are written as zero
+    let options = SaveOptions { resign: args.resign, validate: !args.force, ..Default::default() };
+    save_shader_cache(&mut cache, &args.output, &options)?;
+
+    println!("{} shaders packed to {}", cache.shaders.len(), args.output.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use shaderpunk::bundle::dyn_cache::{IncludesChecksumChunk, MaterialChunk, ParamsChunk, TimestampChunk};
+    use shaderpunk::rtti_types::cname::CName;
+    use shaderpunk::rtti_types::timestamp::TimestampTD;
+
+    use super::*;
+
+    fn fixture() -> DynamicCacheFile {
+        let timestamp = TimestampTD::new().with_year(2077).with_month(12).with_day(10);
+        let shader = |hash: u64| ShaderChunk { hash, params: 0xA, compiled: vec![hash as u8; 4] };
+        let material = |hash: u64, name: &str| MaterialChunk {
+            hash,
+            name: CName::new(name),
+            vs_hash: 1,
+            ps_hash: 2,
+            timestamp,
+            vs_samplers: Vec::new(),
+            ps_samplers: Vec::new(),
+        };
+
+        let mut cache = DynamicCacheFile {
+            info: InfoBlock::default(),
+            // Duplicate hashes unpack to separate files
+            shaders: vec![ shader(1), shader(2), shader(2) ],
+            materials: vec![ material(0x10, "metal_base"), material(0x11, "glass") ],
+            params: vec![ ParamsChunk { hash: 0xA, mat_mod_mask: 0, params: Vec::new() } ],
+            timestamps: vec![ TimestampChunk { hash: 0x20, timestamp } ],
+            includes: vec![ IncludesChecksumChunk { path: CName::new("common.hlsl"), hash: 0x30 } ],
+        };
+        cache.info = cache.layout();
+        cache.info.unknown_hash = 0x1234_5678_9ABC_DEF0;
+        cache.info.timestamp = timestamp;
+        cache
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = std::env::temp_dir().join("shaderpunk_cli_unpack");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let original = dir.join("original.cache");
+        fixture().save_file_with(&original, &SaveOptions::default()).unwrap();
+
+        let unpacked = dir.join("unpacked");
+        unpack(&UnpackArgs { cache: original.clone(), output: unpacked.clone() }).unwrap();
+        assert!(unpacked.join("shaders/0000000000000002_2.bin").exists());
+
+        let packed = dir.join("packed.cache");
+        pack(&PackArgs { input: unpacked, output: packed.clone(), resign: false, force: false }).unwrap();
+
+        assert_eq!(fs::read(&original).unwrap(), fs::read(&packed).unwrap());
+    }
+}
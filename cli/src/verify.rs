@@ -0,0 +1,51 @@
+use std::io;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use argh::FromArgs;
+
+use shaderpunk::bundle::dyn_cache::{DynamicCacheFile, SaveOptions};
+use shaderpunk::validate::validate;
+
+use crate::load_shader_cache;
+
+/// check references between sections and that the cache survives a save and reload
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "verify")]
+pub struct VerifyArgs {
+    /// shader cache
+    #[argh(positional)]
+    cache: PathBuf,
+}
+
+fn encode(cache: &DynamicCacheFile) -> io::Result<Vec<u8>> {
+    let mut out = io::Cursor::new(Vec::new());
+    // Linkage errors are part of the report, not a reason to stop
+    let options = SaveOptions { validate: false, ..Default::default() };
+    cache.clone().save_with(&mut out, &options)?;
+    Ok(out.into_inner())
+}
+
+pub fn run(args: &VerifyArgs) -> anyhow::Result<()> {
+    let cache = load_shader_cache(&args.cache)?;
+    let report = validate(&cache);
+
+    // Saving is deterministic, so a reloaded copy must save to the same bytes
+    let saved = encode(&cache)?;
+    let reloaded = DynamicCacheFile::load(&mut io::Cursor::new(saved.as_slice()))?;
+    let stable = encode(&reloaded)? == saved;
+
+    println!(
+        "{} shaders, {} techniques, {} params checked",
+        cache.shaders.len(), cache.materials.len(), cache.params.len()
+    );
+    print!("{}", report);
+
+    if !stable {
+        println!("ERROR: cache changes after a save and reload");
+    }
+    if !report.is_ok() || !stable {
+        bail!("Validation failed");
+    }
+    Ok(())
+}
@@ -0,0 +1,330 @@
+use anyhow::Result;
+use hashbrown::{HashMap, HashSet};
+use serde::{Serialize, Serializer};
+
+use crate::bundle::dyn_cache::{DynamicCacheFile, InfoBlock};
+use crate::bundle::encode::{encoded_size, Encode};
+use crate::optimize::find_duplicates;
+
+/// Hashes are written as hex strings, JSON consumers can't be trusted with 64-bit integers
+pub(crate) fn ser_hash<S: Serializer>(hash: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:016X}", hash))
+}
+
+pub(crate) fn ser_hashes<S: Serializer>(hashes: &[u64], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(hashes.iter().map(|h| format!("{:016X}", h)))
+}
+
+#[derive(Serialize)]
+pub struct SectionSize {
+    pub name: &'static str,
+    pub count: usize,
+    /// Size as encoded from the current chunks
+    pub size: u64,
+    /// Size recorded in the loaded `InfoBlock`
+    pub stored_size: u64,
+}
+
+/// Bytes attributed to a material, pass, vertex factory or shader stage.
+///
+/// Shaders shared between groups are counted in full by each of them,
+/// so the groups don't add up to the section totals.
+#[derive(Default, Serialize)]
+pub struct GroupSize {
+    pub name: String,
+    pub techniques: usize,
+    /// Distinct shaders referenced
+    pub shaders: usize,
+    pub technique_bytes: u64,
+    pub shader_bytes: u64,
+}
+
+impl GroupSize {
+    pub fn total_bytes(&self) -> u64 {
+        self.technique_bytes + self.shader_bytes
+    }
+}
+
+#[derive(Serialize)]
+pub struct ShaderSize {
+    #[serde(serialize_with = "ser_hash")]
+    pub hash: u64,
+    pub size: u64,
+    /// Techniques referencing the shader
+    pub techniques: usize,
+    /// Distinct materials referencing the shader
+    pub materials: usize,
+}
+
+#[derive(Default, Serialize)]
+pub struct DedupEstimate {
+    pub shaders: usize,
+    pub bytes: u64,
+}
+
+#[derive(Serialize)]
+pub struct SizeReport {
+    pub total: u64,
+    pub sections: Vec<SectionSize>,
+    pub materials: Vec<GroupSize>,
+    pub passes: Vec<GroupSize>,
+    pub vertex_factories: Vec<GroupSize>,
+    pub stages: Vec<GroupSize>,
+    pub largest_shaders: Vec<ShaderSize>,
+    pub dedup: DedupEstimate,
+    /// Rows printed per table, JSON output always includes every row
+    #[serde(skip)]
+    pub top: usize,
+}
+
+impl SizeReport {
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[derive(Default)]
+struct GroupAcc {
+    techniques: usize,
+    technique_bytes: u64,
+    shaders: HashSet<u64>,
+}
+
+impl GroupAcc {
+    fn add(&mut self, technique_bytes: u64, shaders: &[u64]) {
+        self.techniques += 1;
+        self.technique_bytes += technique_bytes;
+        self.shaders.extend(shaders.iter().filter(|h| **h != 0));
+    }
+}
+
+/// Largest first, ties broken by name
+fn into_groups(groups: HashMap<String, GroupAcc>, sizes: &HashMap<u64, u64>) -> Vec<GroupSize> {
+    let mut groups: Vec<GroupSize> = groups.into_iter()
+        .map(|(name, acc)| GroupSize {
+            name,
+            techniques: acc.techniques,
+            shaders: acc.shaders.len(),
+            technique_bytes: acc.technique_bytes,
+            shader_bytes: acc.shaders.iter().filter_map(|h| sizes.get(h)).sum(),
+        })
+        .collect();
+
+    groups.sort_by(|a, b| b.total_bytes().cmp(&a.total_bytes()).then_with(|| a.name.cmp(&b.name)));
+    groups
+}
+
+/// `prefix` is the size of the chunk count stored at the start of the section, if any
+fn section<'a, T, I>(name: &'static str, chunks: I, prefix: u64, stored_size: u64) -> SectionSize
+    where
+        T: Encode + 'a,
+        I: ExactSizeIterator<Item = &'a T> {
+    let count = chunks.len();
+    SectionSize { name, count, size: prefix + chunks.map(encoded_size).sum::<u64>(), stored_size }
+}
+
+/// Breaks down where the bytes of a cache go, `top` limits the rows printed per table
+pub fn analyze(cache: &DynamicCacheFile, top: usize) -> Result<SizeReport> {
+    let info = &cache.info;
+
+    let sections = vec![
+        section("Shaders", cache.shaders.iter(), 0, info.shader_size),
+        section("Techniques", cache.materials.iter(), 0, info.material_size),
+        section("Params", cache.params.iter(), 0, info.param_size),
+        section("Timestamps", cache.timestamps.iter(), 4, info.time_size),
+        section("Includes", cache.includes.iter(), 4, info.include_size),
+        SectionSize { name: "Footer", count: 1, size: InfoBlock::SIZE as u64, stored_size: InfoBlock::SIZE as u64 },
+    ];
+    let total = sections.iter().map(|s| s.size).sum();
+
+    let sizes: HashMap<u64, u64> = cache.shaders.iter().map(|s| (s.hash, encoded_size(s))).collect();
+
+    let mut materials: HashMap<String, GroupAcc> = HashMap::new();
+    let mut passes: HashMap<String, GroupAcc> = HashMap::new();
+    let mut vertex_factories: HashMap<String, GroupAcc> = HashMap::new();
+    let mut stages: HashMap<String, GroupAcc> = HashMap::new();
+
+    // Shader hash -> (techniques, materials)
+    let mut users: HashMap<u64, (usize, HashSet<&str>)> = HashMap::new();
+
+    for m in &cache.materials {
+        let desc = m.decode_desc()?;
+        let size = encoded_size(m);
+        let shaders = [m.vs_hash, m.ps_hash];
+
+        materials.entry_ref(m.material_name()).or_default().add(size, &shaders);
+        passes.entry(desc.pass.to_string()).or_default().add(size, &shaders);
+        vertex_factories.entry(desc.vertex_factory.to_string()).or_default().add(size, &shaders);
+        stages.entry_ref("Vertex").or_default().add(0, &[m.vs_hash]);
+        stages.entry_ref("Pixel").or_default().add(0, &[m.ps_hash]);
+
+        for hash in shaders.into_iter().filter(|h| *h != 0) {
+            let (techniques, mats) = users.entry(hash).or_default();
+            *techniques += 1;
+            mats.insert(m.material_name());
+        }
+    }
+
+    let unused: HashSet<u64> = cache.shaders.iter().map(|s| s.hash).filter(|h| !users.contains_key(h)).collect();
+    if !unused.is_empty() {
+        stages.entry_ref("Unreferenced").or_default().shaders = unused;
+    }
+
+    let mut largest_shaders: Vec<ShaderSize> = cache.shaders.iter()
+        .map(|s| {
+            let (techniques, materials) = users.get(&s.hash).map(|(t, m)| (*t, m.len())).unwrap_or_default();
+            ShaderSize { hash: s.hash, size: sizes[&s.hash], techniques, materials }
+        })
+        .collect();
+    largest_shaders.sort_by(|a, b| b.size.cmp(&a.size).then(a.hash.cmp(&b.hash)));
+
+    let mut dedup = DedupEstimate::default();
+    for (i, _) in find_duplicates(cache) {
+        dedup.shaders += 1;
+        dedup.bytes += encoded_size(&cache.shaders[i]);
+    }
+
+    Ok(SizeReport {
+        total,
+        sections,
+        materials: into_groups(materials, &sizes),
+        passes: into_groups(passes, &sizes),
+        vertex_factories: into_groups(vertex_factories, &sizes),
+        stages: into_groups(stages, &sizes),
+        largest_shaders,
+        dedup,
+        top,
+    })
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 { 0.0 } else { part as f64 * 100.0 / total as f64 }
+}
+
+impl SizeReport {
+    fn fmt_groups(&self, f: &mut std::fmt::Formatter<'_>, title: &str, groups: &[GroupSize]) -> std::fmt::Result {
+        writeln!(f)?;
+        writeln!(f, "{:<48} {:>10} {:>8} {:>12} {:>12} {:>6}", title, "Techniques", "Shaders", "Tech bytes", "Shader bytes", "%")?;
+        for g in groups.iter().take(self.top) {
+            writeln!(
+                f, "{:<48} {:>10} {:>8} {:>12} {:>12} {:>5.1}%",
+                g.name, g.techniques, g.shaders, g.technique_bytes, g.shader_bytes, percent(g.total_bytes(), self.total)
+            )?;
+        }
+        if groups.len() > self.top {
+            writeln!(f, "  ... {} more", groups.len() - self.top)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:<12} {:>8} {:>12} {:>12} {:>6}", "Section", "Count", "Bytes", "Stored", "%")?;
+        for s in &self.sections {
+            writeln!(
+                f, "{:<12} {:>8} {:>12} {:>12} {:>5.1}%",
+                s.name, s.count, s.size, s.stored_size, percent(s.size, self.total)
+            )?;
+        }
+        writeln!(f, "{:<12} {:>8} {:>12}", "Total", "", self.total)?;
+
+        self.fmt_groups(f, "Stage", &self.stages)?;
+        self.fmt_groups(f, "Pass", &self.passes)?;
+        self.fmt_groups(f, "Vertex factory", &self.vertex_factories)?;
+        self.fmt_groups(f, "Material", &self.materials)?;
+
+        writeln!(f)?;
+        writeln!(f, "{:<18} {:>12} {:>10} {:>10}", "Shader", "Bytes", "Techniques", "Materials")?;
+        for s in self.largest_shaders.iter().take(self.top) {
+            writeln!(f, "[{:016X}] {:>12} {:>10} {:>10}", s.hash, s.size, s.techniques, s.materials)?;
+        }
+        if self.largest_shaders.len() > self.top {
+            writeln!(f, "  ... {} more", self.largest_shaders.len() - self.top)?;
+        }
+
+        writeln!(f)?;
+        writeln!(
+            f, "Dedup: {} duplicate shaders, {} bytes ({:.1}%)",
+            self.dedup.shaders, self.dedup.bytes, percent(self.dedup.bytes, self.total)
+        )
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::bundle::dyn_cache::*;
+    use crate::material::TechniqueDesc;
+    use crate::renderstage::RenderStage;
+    use crate::test_util::{desc, shader, technique, technique_with, test_cache};
+
+    use super::*;
+
+    /// The shared fixture with shaders of different sizes, a depth prepass technique and
+    /// a second material using a copy of shader 1
+    fn sized_cache() -> DynamicCacheFile {
+        let sized = |hash: u64, size: usize| ShaderChunk { compiled: vec![hash as u8; size], ..shader(hash) };
+        let depth = TechniqueDesc { pass: RenderStage::DepthPrepass, ..desc(1) };
+
+        let mut cache = test_cache();
+        cache.shaders = vec![
+            sized(1, 100),
+            sized(2, 200),
+            sized(3, 300),
+            // Duplicate of 1
+            ShaderChunk { compiled: vec![1u8; 100], ..shader(4) },
+        ];
+        cache.materials[1] = technique_with("metal_base", &depth, 1, 0);
+        cache.materials.push(technique("glass", 0, 4, 3));
+        cache
+    }
+
+    #[test]
+    fn breakdown() {
+        let cache = sized_cache();
+        let report = analyze(&cache, 2).unwrap();
+
+        let shader_bytes = |size: u64| 8 + 8 + 4 + size;
+        assert_eq!(report.sections[0].size, shader_bytes(100) * 2 + shader_bytes(200) + shader_bytes(300));
+        assert_eq!(report.total, report.sections.iter().map(|s| s.size).sum::<u64>());
+
+        let metal = report.materials.iter().find(|g| g.name == "metal_base").unwrap();
+        assert_eq!(metal.techniques, 2);
+        assert_eq!(metal.shaders, 2);
+        assert_eq!(metal.shader_bytes, shader_bytes(100) + shader_bytes(200));
+
+        let gbuffer = report.passes.iter().find(|g| g.name == "renderstage_gbuffer_regular").unwrap();
+        assert_eq!(gbuffer.techniques, 2);
+        assert_eq!(gbuffer.shaders, 4);
+
+        let vertex = report.stages.iter().find(|g| g.name == "Vertex").unwrap();
+        assert_eq!(vertex.shaders, 2);
+        assert!(!report.stages.iter().any(|g| g.name == "Unreferenced"));
+
+        // Every shader is kept for JSON, only the printed table is cut to `top`
+        assert_eq!(report.largest_shaders.len(), 4);
+        assert_eq!(report.largest_shaders[0].hash, 3);
+        assert_eq!(report.largest_shaders[1].hash, 2);
+        let printed = report.to_string();
+        assert!(printed.contains("[0000000000000002]"));
+        assert!(!printed.contains("[0000000000000001]"));
+        assert!(printed.contains("  ... 2 more\n\nDedup"));
+
+        assert_eq!(report.dedup.shaders, 1);
+        assert_eq!(report.dedup.bytes, shader_bytes(100));
+    }
+
+    #[test]
+    fn sharing_counts() {
+        let cache = sized_cache();
+        let report = analyze(&cache, 10).unwrap();
+
+        let shared = report.largest_shaders.iter().find(|s| s.hash == 1).unwrap();
+        assert_eq!(shared.techniques, 2);
+        assert_eq!(shared.materials, 1);
+
+        let json = report.to_json().unwrap();
+        assert!(json.contains("\"hash\": \"0000000000000001\""));
+    }
+}
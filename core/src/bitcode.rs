@@ -0,0 +1,492 @@
+use std::io;
+
+use hashbrown::HashMap;
+
+/// Reader for the LLVM bitstream container, enough to walk the blocks and
+/// records of a DXIL module. Nothing is interpreted beyond the abbreviations.
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub code: u32,
+    pub ops: Vec<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Item {
+    Record(Record),
+    Block(Block),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Block {
+    pub id: u32,
+    /// Records and sub-blocks in stream order
+    pub items: Vec<Item>,
+}
+
+impl Block {
+    pub fn records(&self) -> impl Iterator<Item = &Record> {
+        self.items.iter().filter_map(|i| match i {
+            Item::Record(r) => Some(r),
+            _ => None,
+        })
+    }
+
+    pub fn blocks(&self, id: u32) -> impl Iterator<Item = &Block> {
+        self.items.iter().filter_map(move |i| match i {
+            Item::Block(b) if b.id == id => Some(b),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+enum AbbrevOp {
+    Literal(u64),
+    Fixed(u32),
+    Vbr(u32),
+    Array,
+    Char6,
+    Blob,
+}
+
+type Abbrev = Vec<AbbrevOp>;
+
+// Reserved abbreviation ids
+const END_BLOCK: u64 = 0;
+const ENTER_SUBBLOCK: u64 = 1;
+const DEFINE_ABBREV: u64 = 2;
+const UNABBREV_RECORD: u64 = 3;
+
+const BLOCKINFO_BLOCK: u32 = 0;
+const BLOCKINFO_SETBID: u32 = 1;
+
+/// Real modules nest a handful of blocks deep, anything past this is corrupt
+const MAX_BLOCK_DEPTH: u32 = 32;
+
+const MAGIC: [u8; 4] = [b'B', b'C', 0xC0, 0xDE];
+const WRAPPER_MAGIC: u32 = 0x0B17C0DE;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// In bits
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn at_end(&self) -> bool {
+        self.pos >= self.data.len() * 8
+    }
+
+    fn read(&mut self, width: u32) -> io::Result<u64> {
+        if width > 64 || self.pos + width as usize > self.data.len() * 8 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Bitstream ended early"));
+        }
+        let mut value: u64 = 0;
+        for i in 0..width as usize {
+            let bit = self.pos + i;
+            value |= u64::from((self.data[bit / 8] >> (bit % 8)) & 1) << i;
+        }
+        self.pos += width as usize;
+        Ok(value)
+    }
+
+    fn vbr(&mut self, width: u32) -> io::Result<u64> {
+        if !(2..=64).contains(&width) {
+            return Err(invalid("Invalid VBR width"));
+        }
+        let hi = 1u64 << (width - 1);
+        let mut value: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let chunk = self.read(width)?;
+            if shift < 64 {
+                value |= (chunk & (hi - 1)) << shift;
+            }
+            if chunk & hi == 0 {
+                return Ok(value);
+            }
+            shift += width - 1;
+        }
+    }
+
+    /// A VBR element count, checked against the bits left so a corrupt
+    /// count fails here rather than allocating or looping
+    fn count(&mut self, width: u32, bits_each: usize) -> io::Result<usize> {
+        let count = self.vbr(width)?;
+        let remaining = (self.data.len() * 8).saturating_sub(self.pos);
+        match usize::try_from(count) {
+            Ok(count) if count.saturating_mul(bits_each) <= remaining => Ok(count),
+            _ => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Bitstream count past the end")),
+        }
+    }
+
+    fn align32(&mut self) {
+        self.pos = self.pos.next_multiple_of(32);
+    }
+}
+
+fn char6(value: u64) -> u64 {
+    match value {
+        0..=25 => u64::from(b'a') + value,
+        26..=51 => u64::from(b'A') + value - 26,
+        52..=61 => u64::from(b'0') + value - 52,
+        62 => u64::from(b'.'),
+        _ => u64::from(b'_'),
+    }
+}
+
+struct Parser<'a> {
+    reader: BitReader<'a>,
+    /// Abbreviations registered through BLOCKINFO, per block id
+    block_info: HashMap<u32, Vec<Abbrev>>,
+}
+
+impl Parser<'_> {
+    fn read_scalar(&mut self, op: &AbbrevOp) -> io::Result<u64> {
+        match op {
+            AbbrevOp::Literal(v) => Ok(*v),
+            AbbrevOp::Fixed(w) => self.reader.read(*w),
+            AbbrevOp::Vbr(w) => self.reader.vbr(*w),
+            AbbrevOp::Char6 => Ok(char6(self.reader.read(6)?)),
+            AbbrevOp::Array | AbbrevOp::Blob => Err(invalid("Array or blob used as an element")),
+        }
+    }
+
+    fn define_abbrev(&mut self) -> io::Result<Abbrev> {
+        let count = self.reader.count(5, 1)?;
+        let mut ops: Abbrev = Vec::with_capacity(count);
+        for _ in 0..count {
+            if self.reader.read(1)? == 1 {
+                ops.push(AbbrevOp::Literal(self.reader.vbr(8)?));
+                continue;
+            }
+            ops.push(match self.reader.read(3)? {
+                1 => AbbrevOp::Fixed(self.reader.vbr(5)? as u32),
+                2 => AbbrevOp::Vbr(self.reader.vbr(5)? as u32),
+                3 => AbbrevOp::Array,
+                4 => AbbrevOp::Char6,
+                5 => AbbrevOp::Blob,
+                _ => return Err(invalid("Unknown abbreviation encoding")),
+            });
+        }
+        Ok(ops)
+    }
+
+    fn read_abbreviated(&mut self, abbrev: &Abbrev) -> io::Result<Record> {
+        let mut values: Vec<u64> = Vec::new();
+        let mut i = 0;
+        while i < abbrev.len() {
+            match &abbrev[i] {
+                AbbrevOp::Array => {
+                    let element = abbrev.get(i + 1).ok_or_else(|| invalid("Array without an element type"))?;
+                    // Every element has to take bits for the length check to hold
+                    if matches!(element, AbbrevOp::Literal(_) | AbbrevOp::Fixed(0)) {
+                        return Err(invalid("Array of zero width elements"));
+                    }
+                    let len = self.reader.count(6, 1)?;
+                    for _ in 0..len {
+                        values.push(self.read_scalar(element)?);
+                    }
+                    i += 2;
+                    continue;
+                },
+                AbbrevOp::Blob => {
+                    let len = self.reader.count(6, 8)?;
+                    self.reader.align32();
+                    for _ in 0..len {
+                        values.push(self.reader.read(8)?);
+                    }
+                    self.reader.align32();
+                },
+                op => values.push(self.read_scalar(op)?),
+            }
+            i += 1;
+        }
+
+        if values.is_empty() {
+            return Err(invalid("Record without a code"));
+        }
+        let code = values.remove(0) as u32;
+        Ok(Record { code, ops: values })
+    }
+
+    fn read_block(&mut self, id: u32, abbrev_width: u32, depth: u32) -> io::Result<Block> {
+        if depth > MAX_BLOCK_DEPTH {
+            return Err(invalid("Blocks nested too deep"));
+        }
+        let mut block = Block { id, items: Vec::new() };
+        let mut abbrevs: Vec<Abbrev> = self.block_info.get(&id).cloned().unwrap_or_default();
+        // Inside BLOCKINFO, the block that DEFINE_ABBREV applies to
+        let mut info_target: Option<u32> = None;
+
+        loop {
+            match self.reader.read(abbrev_width)? {
+                END_BLOCK => {
+                    self.reader.align32();
+                    return Ok(block);
+                },
+                ENTER_SUBBLOCK => {
+                    let sub_id = self.reader.vbr(8)? as u32;
+                    let width = self.reader.vbr(4)? as u32;
+                    self.reader.align32();
+                    let _words = self.reader.read(32)?;
+                    let sub = self.read_block(sub_id, width, depth + 1)?;
+                    block.items.push(Item::Block(sub));
+                },
+                DEFINE_ABBREV => {
+                    let abbrev = self.define_abbrev()?;
+                    match (id, info_target) {
+                        (BLOCKINFO_BLOCK, Some(target)) => self.block_info.entry(target).or_default().push(abbrev),
+                        (BLOCKINFO_BLOCK, None) => return Err(invalid("Abbreviation before SETBID")),
+                        _ => abbrevs.push(abbrev),
+                    }
+                },
+                UNABBREV_RECORD => {
+                    let code = self.reader.vbr(6)? as u32;
+                    let count = self.reader.count(6, 6)?;
+                    let mut ops: Vec<u64> = Vec::with_capacity(count);
+                    for _ in 0..count {
+                        ops.push(self.reader.vbr(6)?);
+                    }
+                    if id == BLOCKINFO_BLOCK && code == BLOCKINFO_SETBID {
+                        info_target = ops.first().map(|v| *v as u32);
+                    }
+                    block.items.push(Item::Record(Record { code, ops }));
+                },
+                n => {
+                    let abbrev = abbrevs.get((n - 4) as usize)
+                        .cloned()
+                        .ok_or_else(|| invalid("Undefined abbreviation"))?;
+                    let record = self.read_abbreviated(&abbrev)?;
+                    block.items.push(Item::Record(record));
+                },
+            }
+        }
+    }
+}
+
+/// Parses a bitcode file, optionally wrapped, into its top level blocks
+pub fn parse(data: &[u8]) -> io::Result<Vec<Block>> {
+    let mut data = data;
+    if data.len() >= 20 && u32::from_le_bytes(data[0..4].try_into().unwrap()) == WRAPPER_MAGIC {
+        let offset = u32::from_le_bytes(data[8..12].try_into().unwrap()) as usize;
+        let size = u32::from_le_bytes(data[12..16].try_into().unwrap()) as usize;
+        data = data.get(offset..offset + size).ok_or_else(|| invalid("Invalid bitcode wrapper"))?;
+    }
+
+    if !data.starts_with(&MAGIC) {
+        return Err(invalid("Invalid bitcode magic"));
+    }
+
+    let mut parser = Parser {
+        reader: BitReader { data: &data[4..], pos: 0 },
+        block_info: HashMap::new(),
+    };
+
+    let mut blocks: Vec<Block> = Vec::new();
+    // Trailing padding is allowed, up to a word
+    while parser.reader.data.len() * 8 - parser.reader.pos >= 32 {
+        match parser.reader.read(2)? {
+            ENTER_SUBBLOCK => {
+                let id = parser.reader.vbr(8)? as u32;
+                let width = parser.reader.vbr(4)? as u32;
+                parser.reader.align32();
+                let _words = parser.reader.read(32)?;
+                let block = parser.read_block(id, width, 0)?;
+                if id != BLOCKINFO_BLOCK {
+                    blocks.push(block);
+                }
+            },
+            _ => return Err(invalid("Expected a block at the top level")),
+        }
+        if parser.reader.at_end() {
+            break;
+        }
+    }
+
+    Ok(blocks)
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Writes unabbreviated bitstreams, with an abbreviation width of 3 in every block
+    #[derive(Default)]
+    pub(crate) struct Writer {
+        bits: Vec<bool>,
+        /// Bit positions of the length words of open blocks
+        open: Vec<usize>,
+    }
+
+    impl Writer {
+        pub(crate) fn new() -> Self {
+            let mut w = Writer::default();
+            for b in MAGIC {
+                w.fixed(b.into(), 8);
+            }
+            w
+        }
+
+        fn fixed(&mut self, value: u64, width: u32) {
+            for i in 0..width {
+                self.bits.push((value >> i) & 1 == 1);
+            }
+        }
+
+        fn vbr(&mut self, mut value: u64, width: u32) {
+            let hi = 1u64 << (width - 1);
+            loop {
+                if value < hi {
+                    self.fixed(value, width);
+                    return;
+                }
+                self.fixed((value & (hi - 1)) | hi, width);
+                value >>= width - 1;
+            }
+        }
+
+        fn align32(&mut self) {
+            while !self.bits.len().is_multiple_of(32) {
+                self.bits.push(false);
+            }
+        }
+
+        fn width(&self) -> u32 {
+            if self.open.is_empty() { 2 } else { 3 }
+        }
+
+        pub(crate) fn enter(&mut self, id: u32) {
+            self.fixed(ENTER_SUBBLOCK, self.width());
+            self.vbr(id.into(), 8);
+            self.vbr(3, 4);
+            self.align32();
+            self.open.push(self.bits.len());
+            self.fixed(0, 32);
+        }
+
+        pub(crate) fn end(&mut self) {
+            self.fixed(END_BLOCK, 3);
+            self.align32();
+            let start = self.open.pop().unwrap();
+            let words = ((self.bits.len() - start - 32) / 32) as u64;
+            for i in 0..32 {
+                self.bits[start + i] = (words >> i) & 1 == 1;
+            }
+        }
+
+        pub(crate) fn record(&mut self, code: u32, ops: &[u64]) {
+            self.fixed(UNABBREV_RECORD, 3);
+            self.vbr(code.into(), 6);
+            self.vbr(ops.len() as u64, 6);
+            for op in ops {
+                self.vbr(*op, 6);
+            }
+        }
+
+        /// Record through a new abbreviation of a fixed(8) code and a char6 array
+        pub(crate) fn char6_record(&mut self, code: u32, s: &str) {
+            self.fixed(DEFINE_ABBREV, 3);
+            self.vbr(3, 5);
+            self.fixed(0, 1);
+            self.fixed(1, 3);
+            self.vbr(8, 5);
+            self.fixed(0, 1);
+            self.fixed(3, 3);
+            self.fixed(0, 1);
+            self.fixed(4, 3);
+
+            // Abbreviations are numbered from 4 within the block
+            self.fixed(4, 3);
+            self.fixed(code.into(), 8);
+            self.vbr(s.len() as u64, 6);
+            for c in s.bytes() {
+                let v = match c {
+                    b'a'..=b'z' => c - b'a',
+                    b'A'..=b'Z' => c - b'A' + 26,
+                    b'0'..=b'9' => c - b'0' + 52,
+                    b'.' => 62,
+                    _ => 63,
+                };
+                self.fixed(v.into(), 6);
+            }
+        }
+
+        pub(crate) fn finish(mut self) -> Vec<u8> {
+            self.align32();
+            self.bits.chunks(8)
+                .map(|byte| byte.iter().enumerate().fold(0u8, |acc, (i, b)| acc | (u8::from(*b) << i)))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut w = Writer::new();
+        w.enter(8);
+        w.record(1, &[1]);
+        w.enter(17);
+        w.record(7, &[32, 1000]);
+        w.end();
+        w.char6_record(5, "dx.op_Sample9");
+        w.end();
+
+        let blocks = parse(&w.finish()).unwrap();
+        assert_eq!(blocks.len(), 1);
+        let module = &blocks[0];
+        assert_eq!(module.id, 8);
+        assert_eq!(module.records().count(), 2);
+
+        let types = module.blocks(17).next().unwrap();
+        assert_eq!(types.records().next().unwrap(), &Record { code: 7, ops: vec![32, 1000] });
+
+        let name = module.records().nth(1).unwrap();
+        assert_eq!(name.code, 5);
+        assert_eq!(String::from_utf8(name.ops.iter().map(|c| *c as u8).collect()).unwrap(), "dx.op_Sample9");
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse(b"BC\xC0").is_err());
+        assert!(parse(b"XXXXXXXX").is_err());
+
+        // A record claiming more operands than there are bits left
+        let mut w = Writer::new();
+        w.enter(8);
+        w.fixed(UNABBREV_RECORD, 3);
+        w.vbr(1, 6);
+        w.vbr(u64::MAX, 6);
+        w.end();
+        assert!(parse(&w.finish()).is_err());
+
+        // A VBR abbreviation wider than 64 bits, used by a record
+        let mut w = Writer::new();
+        w.enter(8);
+        w.fixed(DEFINE_ABBREV, 3);
+        w.vbr(2, 5);
+        w.fixed(1, 1);
+        w.vbr(1, 8);
+        w.fixed(0, 1);
+        w.fixed(2, 3);
+        w.vbr(65, 5);
+        w.fixed(4, 3);
+        w.end();
+        assert!(parse(&w.finish()).is_err());
+
+        // Blocks nested past the limit
+        let mut w = Writer::new();
+        for _ in 0..=MAX_BLOCK_DEPTH + 1 {
+            w.enter(8);
+        }
+        for _ in 0..=MAX_BLOCK_DEPTH + 1 {
+            w.end();
+        }
+        assert!(parse(&w.finish()).is_err());
+    }
+}
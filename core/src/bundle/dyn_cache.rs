@@ -1,16 +1,26 @@
-use std::io;
-
-//use hashbrown::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
 
+use anyhow::{anyhow, bail};
 use chrono::Utc;
+use fnv_rs::{Fnv64, FnvHasher};
+use hashbrown::HashMap;
+use serde::Deserialize;
 
 use crate::bundle::decode::{Decode, DecodeExt};
-use crate::bundle::encode::{Encode, EncodeExt};
+use crate::bundle::encode::{encoded_size, Encode, EncodeExt};
+use crate::container::Container;
+use crate::digest;
+use crate::material::TechniqueDesc;
+use crate::replace::{self, ReplaceReport, ReplaceTarget};
+use crate::shader::{ShaderParam, ShaderType};
+use crate::validate;
 use crate::rtti_types::cname::CName;
 use crate::rtti_types::structs::SampleStateInfo;
 use crate::rtti_types::timestamp::TimestampTD;
 
-
+#[derive(Clone)]
 pub struct DynamicCacheFile {
     pub info: InfoBlock,
     pub shaders: Vec<ShaderChunk>,
@@ -22,6 +32,16 @@ pub struct DynamicCacheFile {
 
 impl DynamicCacheFile {
     pub fn load<I: io::Read + io::Seek>(input: &mut I) -> io::Result<Self> {
+        DynamicCacheFile::load_checked(input, true)
+    }
+
+    /// Loads a cache whose footer offsets and sizes don't match its contents.
+    /// Sections are read back to back using the footer counts, so those must still be correct.
+    pub fn load_lenient<I: io::Read + io::Seek>(input: &mut I) -> io::Result<Self> {
+        DynamicCacheFile::load_checked(input, false)
+    }
+
+    fn load_checked<I: io::Read + io::Seek>(input: &mut I, strict: bool) -> io::Result<Self> {
         // Info block is stored as a fixed-size footer
         let info_start = input.seek(io::SeekFrom::End(-InfoBlock::SIZE))?;
         let info: InfoBlock = input.decode()?;
@@ -38,7 +58,7 @@ impl DynamicCacheFile {
         }
 
         // Sanity check
-        if input.stream_position().unwrap() != info.material_offset {
+        if strict && input.stream_position().unwrap() != info.material_offset {
             return Err(io::Error::new(io::ErrorKind::InvalidData, "ShaderChunk size mismatch"));
         }
 
@@ -51,7 +71,7 @@ impl DynamicCacheFile {
         }
 
         // Sanity check
-        if input.stream_position().unwrap() != info.param_offset {
+        if strict && input.stream_position().unwrap() != info.param_offset {
             return Err(io::Error::new(io::ErrorKind::InvalidData, "MaterialChunk size mismatch"));
         }
 
@@ -64,7 +84,7 @@ impl DynamicCacheFile {
         }
 
         // Sanity check
-        if input.stream_position().unwrap() != info.time_offset {
+        if strict && input.stream_position().unwrap() != info.time_offset {
             return Err(io::Error::new(io::ErrorKind::InvalidData, "ParamsChunk size mismatch"));
         }
 
@@ -78,7 +98,7 @@ impl DynamicCacheFile {
         }
 
         // Sanity check
-        if input.stream_position().unwrap() != info.include_offset {
+        if strict && input.stream_position().unwrap() != info.include_offset {
             return Err(io::Error::new(io::ErrorKind::InvalidData, "TimestampChunk size mismatch"));
         }
 
@@ -91,8 +111,9 @@ impl DynamicCacheFile {
             includes.push(input.decode()?);
         }
 
-        // Sanity check
-        if input.stream_position().unwrap() != info_start {
+        // Sanity check, even a lenient load can't read into the footer
+        let end = input.stream_position().unwrap();
+        if end > info_start || (strict && end != info_start) {
             return Err(io::Error::new(io::ErrorKind::InvalidData, "IncludesChecksumChunk size mismatch"));
         }
 
@@ -108,26 +129,54 @@ impl DynamicCacheFile {
         Ok(cache)
     }
 
-    pub fn save<O: io::Write + io::Seek>(&self, output: &mut O) -> io::Result<()> {
-        
-        let mut info: InfoBlock = InfoBlock {
-            timestamp: TimestampTD::from(Utc::now()),
-            unknown_hash: 0,
-            shader_count: self.shaders.len() as u32,
-            shader_size: 0,
-            material_count: self.materials.len() as u32,
-            material_size: 0,
-            material_offset: 0,
-            param_count: self.params.len() as u32,
-            param_size: 0,
-            param_offset: 0,
-            include_count: self.includes.len() as u32,
-            include_size: 0,
-            include_offset: 0,
-            time_size: 0,
-            time_offset: 0,
+    pub fn load_file(path: &Path) -> io::Result<Self> {
+        let mut reader = io::BufReader::new(File::open(path)?);
+        DynamicCacheFile::load(&mut reader)
+    }
+
+    pub fn save_file(&mut self, path: &Path) -> io::Result<()> {
+        self.save_file_with(path, &SaveOptions::default())
+    }
+
+    pub fn save_file_with(&mut self, path: &Path, options: &SaveOptions) -> io::Result<()> {
+        // Checked before the file is created, so a refused save leaves it untouched
+        self.check_save(options)?;
+        let options = SaveOptions { validate: false, ..options.clone() };
+
+        let mut writer = io::BufWriter::new(File::create(path)?);
+        self.save_with(&mut writer, &options)?;
+        writer.flush()
+    }
+
+    pub fn save<O: io::Write + io::Seek>(&mut self, output: &mut O) -> io::Result<()> {
+        self.save_with(output, &SaveOptions::default())
+    }
+
+    /// Params stored twice are merged, see `merge_params`, then the chunks are
+    /// reordered as requested before writing.
+    /// Fails without writing anything if validation is on and finds errors.
+    pub fn save_with<O: io::Write + io::Seek>(&mut self, output: &mut O, options: &SaveOptions) -> io::Result<()> {
+        self.check_save(options)?;
+        self.merge_params()?;
+        self.sort_chunks(options.order);
+
+        let timestamp = if options.stamp_now {
+            TimestampTD::from(Utc::now())
+        }
+        else {
+            self.info.timestamp
         };
 
+        if options.resign {
+            for shader in &mut self.shaders {
+                digest::resign(&mut shader.compiled);
+            }
+        }
+
+        let mut info = self.layout();
+        info.timestamp = timestamp;
+        info.unknown_hash = self.info.unknown_hash;
+
         //----------------------------------------------------------------------
         // Shaders
 
@@ -135,35 +184,23 @@ impl DynamicCacheFile {
             output.encode(shader)?;
         }
 
-        info.shader_size = output.stream_position()?;
-
         //----------------------------------------------------------------------
         // Material Techniques
 
-        info.material_offset = info.shader_size; // output.stream_position()?;
-
         for material in &self.materials {
             output.encode(material)?;
         }
-        
-        info.material_size = output.stream_position()? - info.material_offset;
 
         //----------------------------------------------------------------------
         // Material Params
 
-        info.param_offset = output.stream_position()?;
-
         for param in &self.params {
             output.encode(param)?;
         }
 
-        info.param_size = output.stream_position()? - info.param_offset;
-
         //----------------------------------------------------------------------
         // Material Timestamps
 
-        info.time_offset = output.stream_position()?;
-        
         let timestamp_count: u32 = self.timestamps.len() as u32;
         output.encode(&timestamp_count)?;
 
@@ -171,13 +208,9 @@ impl DynamicCacheFile {
             output.encode(timestamp)?;
         }
 
-        info.time_size = output.stream_position()? - info.time_offset;
-
         //----------------------------------------------------------------------
         // Include Checksums
 
-        info.include_offset = output.stream_position()?;
-        
         let includes_count: u32 = self.includes.len() as u32;
         output.encode(&includes_count)?;
 
@@ -185,8 +218,6 @@ impl DynamicCacheFile {
             output.encode(include)?;
         }
 
-        info.include_size = output.stream_position()? - info.include_offset;
-
         //----------------------------------------------------------------------
         // Info block
 
@@ -194,32 +225,380 @@ impl DynamicCacheFile {
 
         Ok(())
     }
+
+    /// Runs the validation requested by `options`, see `SaveOptions::validate`
+    fn check_save(&self, options: &SaveOptions) -> io::Result<()> {
+        if !options.validate {
+            return Ok(());
+        }
+
+        let report = validate::linkage(self);
+        if !report.is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Refusing to save a cache with linkage errors\n{}", report)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Footer describing the current chunks, as written by `save`, with a zero timestamp
+    pub fn layout(&self) -> InfoBlock {
+        fn size<'a, A: Encode + 'a>(chunks: impl Iterator<Item = &'a A>) -> u64 {
+            chunks.map(encoded_size).sum()
+        }
+
+        let shader_size = size(self.shaders.iter());
+        let material_size = size(self.materials.iter());
+        let param_size = size(self.params.iter());
+        // Timestamps and includes are prefixed with their count
+        let time_size = 4 + size(self.timestamps.iter());
+        let include_size = 4 + size(self.includes.iter());
+
+        let material_offset = shader_size;
+        let param_offset = material_offset + material_size;
+        let time_offset = param_offset + param_size;
+        let include_offset = time_offset + time_size;
+
+        InfoBlock {
+            timestamp: TimestampTD::default(),
+            unknown_hash: 0,
+            shader_count: self.shaders.len() as u32,
+            shader_size,
+            material_count: self.materials.len() as u32,
+            material_size,
+            material_offset,
+            param_count: self.params.len() as u32,
+            param_size,
+            param_offset,
+            include_count: self.includes.len() as u32,
+            include_size,
+            include_offset,
+            time_size,
+            time_offset,
+        }
+    }
+}
+
+impl DynamicCacheFile {
+    /// Reorders every section according to the policy. All sorts are stable, so
+    /// identical inputs always produce the same order.
+    pub fn sort_chunks(&mut self, order: ChunkOrder) {
+        match order {
+            ChunkOrder::Preserve => {},
+            ChunkOrder::Hash => {
+                self.shaders.sort_by_key(|s| s.hash);
+                self.materials.sort_by_key(|m| m.hash);
+                self.params.sort_by_key(|p| p.hash);
+                self.timestamps.sort_by_key(|t| t.hash);
+                self.includes.sort_by_key(|i| i.hash);
+            },
+            ChunkOrder::MaterialFirstUse => {
+                // Shaders follow the first material technique to use them, then params
+                // follow the first shader to use them. Unused chunks go last, by hash.
+                let mut shader_rank: HashMap<u64, usize> = HashMap::new();
+                for m in &self.materials {
+                    for hash in [m.vs_hash, m.ps_hash] {
+                        let rank = shader_rank.len();
+                        shader_rank.entry(hash).or_insert(rank);
+                    }
+                }
+                self.shaders.sort_by_key(|s| (shader_rank.get(&s.hash).copied().unwrap_or(usize::MAX), s.hash));
+
+                let mut params_rank: HashMap<u64, usize> = HashMap::new();
+                for s in &self.shaders {
+                    let rank = params_rank.len();
+                    params_rank.entry(s.params).or_insert(rank);
+                }
+                self.params.sort_by_key(|p| (params_rank.get(&p.hash).copied().unwrap_or(usize::MAX), p.hash));
+            },
+        }
+    }
+
+    fn find_technique(&self, material: &str, desc: &TechniqueDesc) -> Option<usize> {
+        let hash = desc.encode_material_hash(material);
+        self.materials.iter().position(|m| m.hash == hash)
+    }
+
+    /// Adds a new technique to a material by copying the shaders and samplers of an existing one.
+    /// The copy is inserted directly after the technique it was copied from.
+    pub fn add_technique(&mut self, material: &str, from: &TechniqueDesc, to: &TechniqueDesc) -> anyhow::Result<()> {
+        if self.find_technique(material, to).is_some() {
+            bail!("Material {} already has technique {}", material, to);
+        }
+
+        let pos = self.find_technique(material, from)
+            .ok_or_else(|| anyhow!("Material {} has no technique {}", material, from))?;
+
+        let mut chunk = self.materials[pos].clone();
+        chunk.encode_desc(to);
+        self.materials.insert(pos + 1, chunk);
+
+        Ok(())
+    }
+
+    pub fn remove_technique(&mut self, material: &str, desc: &TechniqueDesc) -> Option<MaterialChunk> {
+        let pos = self.find_technique(material, desc)?;
+        Some(self.materials.remove(pos))
+    }
+
+    /// Removes every technique matching the predicate, returning how many were removed
+    pub fn remove_techniques<F>(&mut self, mut f: F) -> anyhow::Result<usize>
+    where F: FnMut(&str, &TechniqueDesc) -> bool {
+        let mut keep: Vec<bool> = Vec::with_capacity(self.materials.len());
+        for m in &self.materials {
+            keep.push(!f(m.material_name(), &m.decode_desc()?));
+        }
+
+        let count = self.materials.len();
+        let mut keep = keep.into_iter();
+        self.materials.retain(|_| keep.next().unwrap());
+
+        Ok(count - self.materials.len())
+    }
+
+    /// Copies every technique of a material under a new material name.
+    /// Returns the number of techniques copied.
+    pub fn clone_material(&mut self, from: &str, to: &str) -> anyhow::Result<usize> {
+        if self.materials.iter().any(|m| m.material_name() == to) {
+            bail!("Material {} already exists", to);
+        }
+
+        let mut clones: Vec<MaterialChunk> = Vec::new();
+        for m in self.materials.iter().filter(|m| m.material_name() == from) {
+            let mut chunk = m.clone();
+            chunk.encode_technique(to, &m.decode_desc()?);
+            clones.push(chunk);
+        }
+
+        if clones.is_empty() {
+            bail!("Material {} does not exist", from);
+        }
+
+        let count = clones.len();
+        self.materials.extend(clones);
+
+        Ok(count)
+    }
+
+    pub fn shader_params(&self, shader: u64) -> Option<&ParamsChunk> {
+        let params = self.shaders.iter().find(|s| s.hash == shader)?.params;
+        self.params.iter().find(|p| p.hash == params)
+    }
+
+    /// Replaces the param bindings of a shader, keeping its modifier mask.
+    /// The new params are shared with any existing chunk of the same layout.
+    /// Returns the new params hash.
+    ///
+    /// A new chunk gets a derived hash, which hasn't been checked against the game's
+    /// own, see [`ParamsChunk::compute_hash`].
+    pub fn set_shader_params(&mut self, shader: u64, params: &[ShaderParam]) -> anyhow::Result<u64> {
+        let index = self.shaders.iter()
+            .position(|s| s.hash == shader)
+            .ok_or_else(|| anyhow!("No shader with hash {:016X}", shader))?;
+        let mat_mod_mask = self.shader_params(shader)
+            .map(|p| p.mat_mod_mask)
+            .unwrap_or(0);
+
+        let chunk = ParamsChunk::new(mat_mod_mask, params.iter().map(ParamChunk::from).collect());
+        let hash = match self.params.iter().find(|p| p.same_layout(&chunk) || p.hash == chunk.hash) {
+            Some(p) if p.same_layout(&chunk) => p.hash,
+            Some(_) => bail!("Params hash {:016X} collides with a different layout", chunk.hash),
+            None => {
+                let hash = chunk.hash;
+                self.params.push(chunk);
+                hash
+            },
+        };
+
+        self.shaders[index].params = hash;
+        Ok(hash)
+    }
+
+    /// Merges params chunks stored twice under one hash, returning the number merged.
+    ///
+    /// Loaded hashes are kept as they are. A chunk without a hash, or reusing the
+    /// hash of a different layout, e.g. after being copied and edited in place, is
+    /// given its derived hash. Shaders referencing a reused hash keep the first chunk.
+    pub fn merge_params(&mut self) -> io::Result<usize> {
+        let count = self.params.len();
+        let mut kept: HashMap<u64, usize> = HashMap::with_capacity(count);
+        let mut params: Vec<ParamsChunk> = Vec::with_capacity(count);
+
+        for mut p in self.params.drain(..) {
+            let stale = p.hash == 0 || kept.get(&p.hash).is_some_and(|&i| !params[i].same_layout(&p));
+            if stale {
+                p.hash = p.compute_hash();
+            }
+
+            match kept.get(&p.hash) {
+                Some(&i) if params[i].same_layout(&p) => {},
+                Some(_) => return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Params hash {:016X} collides with a different layout", p.hash)
+                )),
+                None => {
+                    kept.insert(p.hash, params.len());
+                    params.push(p);
+                },
+            }
+        }
+
+        self.params = params;
+        Ok(count - self.params.len())
+    }
+
+    /// Replaces the description of an existing technique, e.g. to change the pass,
+    /// pass index, fallback index or vertex factory
+    pub fn retarget_technique(&mut self, material: &str, from: &TechniqueDesc, to: &TechniqueDesc) -> anyhow::Result<()> {
+        if !from.is_same_technique(to) && self.find_technique(material, to).is_some() {
+            bail!("Material {} already has technique {}", material, to);
+        }
+
+        let pos = self.find_technique(material, from)
+            .ok_or_else(|| anyhow!("Material {} has no technique {}", material, from))?;
+
+        self.materials[pos].encode_desc(to);
+
+        Ok(())
+    }
+
+    /// Swaps the compiled VS or PS of one technique for a new blob.
+    ///
+    /// The blob is checked against the original and the technique's other shader,
+    /// and refused on errors unless `force` is set. A shader shared with other
+    /// techniques is copied under a new hash, otherwise it's replaced in place.
+    pub fn replace_shader(&mut self, material: &str, desc: &TechniqueDesc, stage: ShaderType, compiled: Vec<u8>, force: bool) -> anyhow::Result<ReplaceReport> {
+        let pos = self.find_technique(material, desc)
+            .ok_or_else(|| anyhow!("Material {} has no technique {}", material, desc))?;
+
+        let technique = &self.materials[pos];
+        let (hash, other) = match stage {
+            ShaderType::Vertex => (technique.vs_hash, technique.ps_hash),
+            ShaderType::Pixel => (technique.ps_hash, technique.vs_hash),
+            _ => bail!("Only Vertex and Pixel shaders can be replaced, not {}", stage),
+        };
+        if hash == 0 {
+            bail!("Technique {} of {} has no {} shader", desc, material, stage);
+        }
+
+        let find = |hash: u64| self.shaders.iter().position(|s| s.hash == hash);
+        let index = find(hash).ok_or_else(|| anyhow!("No shader with hash {:016X}", hash))?;
+
+        let mut vertex_shaders: Vec<&[u8]> = Vec::new();
+        if stage == ShaderType::Vertex {
+            for m in &self.materials {
+                if m.decode_desc().is_ok_and(|d| d.vertex_factory == desc.vertex_factory) {
+                    vertex_shaders.extend(find(m.vs_hash).map(|i| self.shaders[i].compiled.as_slice()));
+                }
+            }
+        }
+
+        let target = ReplaceTarget {
+            stage,
+            original: &self.shaders[index].compiled,
+            counterpart: find(other).map(|i| self.shaders[i].compiled.as_slice()),
+            vertex_shaders,
+        };
+        let findings = replace::check_replacement(&target, &compiled);
+        replace::refuse(&findings, force)?;
+
+        let shared = self.materials.iter().enumerate()
+            .any(|(i, m)| i != pos && (m.vs_hash == hash || m.ps_hash == hash));
+
+        if !shared {
+            self.shaders[index].compiled = compiled;
+            return Ok(ReplaceReport { hash, copied: false, findings });
+        }
+
+        let copy = replace::copy_hash(&compiled, |h| self.shaders.iter().any(|s| s.hash == h));
+        let params = self.shaders[index].params;
+        self.shaders.insert(index + 1, ShaderChunk { hash: copy, params, compiled });
+
+        let technique = &mut self.materials[pos];
+        match stage {
+            ShaderType::Vertex => technique.vs_hash = copy,
+            _ => technique.ps_hash = copy,
+        }
+
+        Ok(ReplaceReport { hash: copy, copied: true, findings })
+    }
+}
+
+/// Order of the chunks within each section when saving
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum ChunkOrder {
+    /// Keep the current order
+    #[default]
+    Preserve,
+    /// Sort every section by hash
+    Hash,
+    /// Keep the material order, and place shaders and params in the order they're first used
+    MaterialFirstUse,
+}
+
+impl std::str::FromStr for ChunkOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "preserve" => Ok(ChunkOrder::Preserve),
+            "hash" => Ok(ChunkOrder::Hash),
+            "materialfirstuse" | "first-use" => Ok(ChunkOrder::MaterialFirstUse),
+            _ => bail!("Unknown chunk order {}, expected preserve, hash or first-use", s),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SaveOptions {
+    pub order: ChunkOrder,
+    /// Write the current time into the footer rather than the loaded timestamp.
+    /// Off by default, so identical inputs produce byte-identical files.
+    pub stamp_now: bool,
+    /// Recompute the digest of every shader container that no longer matches its
+    /// contents. Edits aren't tracked, so this covers parts stripped or bytes patched
+    /// here as well as blobs that were already stale or corrupt when loaded.
+    pub resign: bool,
+    /// Check that the VS outputs of each technique link with its PS inputs, see
+    /// `validate::linkage`, and refuse to save on errors. On by default.
+    pub validate: bool,
+}
+
+impl Default for SaveOptions {
+    fn default() -> Self {
+        SaveOptions {
+            order: ChunkOrder::default(),
+            stamp_now: false,
+            resign: false,
+            validate: true,
+        }
+    }
 }
 
 
-#[allow(dead_code)]
 #[derive(Debug, Default, Clone)]
 pub struct InfoBlock {
-    timestamp: TimestampTD,
-    unknown_hash: u64,
+    pub timestamp: TimestampTD,
+    pub unknown_hash: u64,
 
-    shader_count: u32,
-    shader_size: u64,
+    pub shader_count: u32,
+    pub shader_size: u64,
     
-    material_count: u32,
-    material_size: u64,
-    material_offset: u64,
+    pub material_count: u32,
+    pub material_size: u64,
+    pub material_offset: u64,
 
-    param_count: u32,
-    param_size: u64,
-    param_offset: u64,
+    pub param_count: u32,
+    pub param_size: u64,
+    pub param_offset: u64,
 
-    include_count: u32,
-    include_size: u64,
-    include_offset: u64,
+    pub include_count: u32,
+    pub include_size: u64,
+    pub include_offset: u64,
     
-    time_size: u64,
-    time_offset: u64,
+    pub time_size: u64,
+    pub time_offset: u64,
 }
 
 impl InfoBlock {
@@ -228,7 +607,7 @@ impl InfoBlock {
     // Known supported file version
     const VERSION: u32 = 10;
     // Fixed size footer block
-    const SIZE: i64 = 0x70;
+    pub const SIZE: i64 = 0x70;
 }
 
 impl Decode for InfoBlock {
@@ -324,6 +703,12 @@ pub struct ShaderChunk {
     pub compiled: Vec<u8>
 }
 
+impl ShaderChunk {
+    pub fn container(&self) -> io::Result<Container> {
+        Container::parse(&self.compiled)
+    }
+}
+
 impl Decode for ShaderChunk {
     fn decode<I: io::Read>(input: &mut I) -> io::Result<Self> {
         let hash: u64 = input.decode()?;
@@ -363,6 +748,33 @@ pub struct MaterialChunk {
     pub ps_samplers: Vec<SampleStateInfo>
 }
 
+impl MaterialChunk {
+    /// Splits the stored name into the material name and technique string
+    pub fn split_name(&self) -> (&str, &str) {
+        self.name.as_str().split_once(' ').unwrap_or((self.name.as_str(), ""))
+    }
+
+    pub fn material_name(&self) -> &str {
+        self.split_name().0
+    }
+
+    pub fn decode_desc(&self) -> anyhow::Result<TechniqueDesc> {
+        TechniqueDesc::decode_string(self.split_name().1.to_string())
+    }
+
+    /// Replaces the technique, recomputing the composite hash and name
+    pub fn encode_desc(&mut self, desc: &TechniqueDesc) {
+        let material = self.material_name().to_string();
+        self.encode_technique(&material, desc);
+    }
+
+    /// Replaces both the material name and technique, recomputing the composite hash and name
+    pub fn encode_technique(&mut self, material: &str, desc: &TechniqueDesc) {
+        self.hash = desc.encode_material_hash(material);
+        self.name = CName::new(&desc.encode_material_name(material));
+    }
+}
+
 impl Decode for MaterialChunk {
     fn decode<I: io::Read>(input: &mut I) -> io::Result<Self> {
         let mut _u32: u32;
@@ -450,13 +862,12 @@ impl Encode for MaterialChunk {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
 pub struct ParamChunk {
     pub name: CName,
-    // Value? Lookup? Shader register?
+    /// Shader register slot
     pub value: u8,
-    // Always 1 or 4, memory size? scalar vs vec vs matrix?
-    // 4 == Matrix
+    /// Always 1 or 4, see `ShaderParamType`
     pub size: u8,
 }
 
@@ -480,14 +891,58 @@ impl Encode for ParamChunk {
     }
 }
 
+impl From<&ShaderParam> for ParamChunk {
+    fn from(value: &ShaderParam) -> Self {
+        ParamChunk {
+            name: value.name.clone(),
+            value: value.slot,
+            size: value.kind as u8,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct ParamsChunk {
     pub hash: u64,
     pub mat_mod_mask: u32,
-    pub param_count: u32,
     pub params: Vec<ParamChunk>,
 }
 
+impl ParamsChunk {
+    pub fn new(mat_mod_mask: u32, params: Vec<ParamChunk>) -> Self {
+        let mut chunk = ParamsChunk { hash: 0, mat_mod_mask, params };
+        chunk.hash = chunk.compute_hash();
+        chunk
+    }
+
+    pub fn param_count(&self) -> u32 {
+        self.params.len() as u32
+    }
+
+    /// FNV1a64 chained over the modifier mask then each param's name, slot and size.
+    ///
+    /// This mirrors the technique hash and is unconfirmed: no hash from the shipped cache
+    /// has been reproduced with it, so there's no known-answer test. Loaded hashes are
+    /// never replaced, only chunks without a usable one are given this.
+    pub fn compute_hash(&self) -> u64 {
+        let mut hasher = Fnv64::new();
+
+        hasher.update(&self.mat_mod_mask.to_le_bytes());
+        for p in &self.params {
+            hasher.update(p.name.as_str().as_bytes());
+            hasher.update(&p.value.to_le_bytes());
+            hasher.update(&p.size.to_le_bytes());
+        }
+
+        hasher.into()
+    }
+
+    /// Same modifier mask and params, ignoring the stored hash
+    pub fn same_layout(&self, other: &ParamsChunk) -> bool {
+        self.mat_mod_mask == other.mat_mod_mask && self.params == other.params
+    }
+}
+
 impl Decode for ParamsChunk {
     fn decode<I: io::Read>(input: &mut I) -> io::Result<Self> {
         let hash: u64 = input.decode()?;
@@ -501,7 +956,6 @@ impl Decode for ParamsChunk {
         Ok(ParamsChunk {
             hash,
             mat_mod_mask,
-            param_count,
             params
         })
     }
@@ -511,7 +965,7 @@ impl Encode for ParamsChunk {
     fn encode<O: io::Write>(&self, output: &mut O) -> io::Result<()> {
         output.encode(&self.hash)?;
         output.encode(&self.mat_mod_mask)?;
-        output.encode(&self.param_count)?;
+        output.encode(&self.param_count())?;
 
         for p in &self.params {
             output.encode(p)?;
@@ -568,3 +1022,222 @@ impl Encode for IncludesChecksumChunk {
         Ok(())
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::shader::ShaderParamType;
+    use crate::test_util::{self, shader, technique};
+
+    use super::*;
+
+    fn test_params(names: &[&str]) -> Vec<ParamChunk> {
+        names.iter().enumerate().map(|(i, n)| ParamChunk {
+            name: CName::new(n),
+            value: i as u8,
+            size: 1
+        }).collect()
+    }
+
+    /// The shared fixture with a third shader, and two params chunks of the same layout
+    fn test_cache() -> DynamicCacheFile {
+        let mut cache = test_util::test_cache();
+        cache.shaders[1].params = 0xB;
+        cache.shaders.push(ShaderChunk { params: 0xC, ..shader(3) });
+        cache.params = vec![
+            ParamsChunk { hash: 0xA, mat_mod_mask: 0x200, params: test_params(&["MaterialParams"]) },
+            ParamsChunk { hash: 0xB, mat_mod_mask: 0x200, params: test_params(&["MaterialParams"]) },
+            ParamsChunk { hash: 0xC, mat_mod_mask: 0x001, params: test_params(&["HitProxy", "WindData"]) },
+        ];
+        cache
+    }
+
+    #[test]
+    fn params_count_roundtrip() {
+        let chunk = ParamsChunk::new(0x200, test_params(&["A", "B", "C"]));
+
+        let mut writer = Cursor::new(Vec::new());
+        writer.encode(&chunk).unwrap();
+
+        let mut reader = Cursor::new(writer.into_inner());
+        let decoded: ParamsChunk = reader.decode().unwrap();
+
+        assert_eq!(decoded.param_count(), 3);
+        assert_eq!(decoded.hash, chunk.hash);
+        assert!(decoded.same_layout(&chunk));
+    }
+
+    #[test]
+    fn params_hash_layout() {
+        let a = ParamsChunk::new(0x200, test_params(&["A", "B"]));
+        let b = ParamsChunk::new(0x200, test_params(&["A", "B"]));
+        let c = ParamsChunk::new(0x201, test_params(&["A", "B"]));
+        let d = ParamsChunk::new(0x200, test_params(&["B", "A"]));
+
+        assert_eq!(a.hash, b.hash);
+        assert_ne!(a.hash, c.hash);
+        assert_ne!(a.hash, d.hash);
+    }
+
+    #[test]
+    fn merge_params() {
+        let mut cache = test_cache();
+
+        // Same layout under different loaded hashes, both are kept
+        assert_eq!(cache.merge_params().unwrap(), 0);
+        assert_eq!(cache.params.len(), 3);
+        assert_eq!(cache.shaders[1].params, 0xB);
+
+        // An exact copy merges, an edited copy gets its derived hash
+        let mut edited = cache.params[2].clone();
+        edited.params.pop();
+        cache.params.push(cache.params[0].clone());
+        cache.params.push(edited);
+        assert_eq!(cache.merge_params().unwrap(), 1);
+        assert_eq!(cache.params.len(), 4);
+        assert_eq!(cache.params[2].hash, 0xC);
+        assert_eq!(cache.params[3].hash, cache.params[3].compute_hash());
+
+        // Reusing a hash for a new layout can't be resolved
+        let mut collision = ParamsChunk::new(0x200, test_params(&["Other"]));
+        collision.hash = cache.params[3].hash;
+        cache.params.insert(0, collision);
+        assert!(cache.merge_params().is_err());
+    }
+
+    #[test]
+    fn set_shader_params_loaded() {
+        let mut cache = test_cache();
+
+        let param = |name: &str| ShaderParam {
+            name: CName::new(name),
+            kind: ShaderParamType::try_from(1).unwrap(),
+            slot: 0,
+        };
+
+        // Reuses the first loaded chunk with the same layout
+        assert_eq!(cache.set_shader_params(2, &[param("MaterialParams")]).unwrap(), 0xA);
+        assert_eq!(cache.params.len(), 3);
+
+        let hash = cache.set_shader_params(2, &[param("Other")]).unwrap();
+        assert_eq!(cache.params.len(), 4);
+        assert_eq!(cache.params[3].hash, hash);
+        assert_eq!(cache.shaders[1].params, hash);
+    }
+
+    fn save_bytes(cache: &mut DynamicCacheFile, order: ChunkOrder) -> Vec<u8> {
+        let options = SaveOptions { order, ..Default::default() };
+        let mut writer = Cursor::new(Vec::new());
+        cache.save_with(&mut writer, &options).unwrap();
+        writer.into_inner()
+    }
+
+    #[test]
+    fn save_resign() {
+        let mut cache = test_cache();
+        let container = Container {
+            parts: vec![ crate::container::Part { fourcc: crate::container::FourCC::SFI0, data: vec![0u8; 8] } ],
+            ..Default::default()
+        };
+        cache.shaders[0].compiled = container.to_bytes();
+
+        save_bytes(&mut cache, ChunkOrder::Preserve);
+        assert!(!digest::verify_digest(&cache.shaders[0].compiled));
+
+        let options = SaveOptions { resign: true, ..Default::default() };
+        cache.save_with(&mut Cursor::new(Vec::new()), &options).unwrap();
+        assert!(digest::verify_digest(&cache.shaders[0].compiled));
+    }
+
+    #[test]
+    fn sort_by_hash() {
+        let mut cache = test_cache();
+        cache.shaders.reverse();
+        cache.params.reverse();
+
+        cache.sort_chunks(ChunkOrder::Hash);
+
+        assert!(cache.shaders.windows(2).all(|w| w[0].hash <= w[1].hash));
+        assert!(cache.params.windows(2).all(|w| w[0].hash <= w[1].hash));
+    }
+
+    #[test]
+    fn sort_by_first_use() {
+        let mut cache = test_cache();
+        cache.materials = vec![ technique("metal_base", 0, 3, 0), technique("metal_base", 1, 1, 3) ];
+
+        cache.sort_chunks(ChunkOrder::MaterialFirstUse);
+
+        let shaders: Vec<u64> = cache.shaders.iter().map(|s| s.hash).collect();
+        let params: Vec<u64> = cache.params.iter().map(|p| p.hash).collect();
+        // Shader 2 is unused so goes last
+        assert_eq!(shaders, vec![ 3, 1, 2 ]);
+        assert_eq!(params, vec![ 0xC, 0xA, 0xB ]);
+    }
+
+    #[test]
+    fn save_deterministic() {
+        for order in [ChunkOrder::Preserve, ChunkOrder::Hash, ChunkOrder::MaterialFirstUse] {
+            let mut a = test_cache();
+            let mut b = test_cache();
+            b.shaders.reverse();
+            b.params.reverse();
+
+            let bytes = save_bytes(&mut a, order);
+            assert_eq!(bytes, save_bytes(&mut a, order));
+
+            if order != ChunkOrder::Preserve {
+                assert_eq!(bytes, save_bytes(&mut b, order));
+            }
+        }
+    }
+
+    #[test]
+    fn save_keeps_footer() {
+        let mut cache = test_cache();
+        cache.info.unknown_hash = 0x1234;
+        let bytes = save_bytes(&mut cache, ChunkOrder::Preserve);
+
+        let mut loaded = DynamicCacheFile::load(&mut Cursor::new(bytes.as_slice())).unwrap();
+        assert_eq!(loaded.info.unknown_hash, 0x1234);
+        assert_eq!(save_bytes(&mut loaded, ChunkOrder::Preserve), bytes);
+
+        // Stamping is opt-in
+        let options = SaveOptions { stamp_now: true, ..Default::default() };
+        let mut writer = Cursor::new(Vec::new());
+        loaded.save_with(&mut writer, &options).unwrap();
+        assert_ne!(writer.into_inner(), bytes);
+    }
+
+    #[test]
+    fn set_shader_params() {
+        let mut cache = test_cache();
+        let params = [ShaderParam {
+            name: CName::new("HitProxy"),
+            kind: ShaderParamType::Matrix,
+            slot: 3
+        }];
+
+        let hash = cache.set_shader_params(1, &params).unwrap();
+        let chunk = cache.shader_params(1).unwrap();
+
+        assert_eq!(chunk.hash, hash);
+        assert_eq!(chunk.mat_mod_mask, 0x200);
+        assert_eq!(chunk.param_count(), 1);
+        assert_eq!(chunk.params[0].value, 3);
+        assert_eq!(chunk.params[0].size, 4);
+
+        // Same layout is shared rather than duplicated
+        let count = cache.params.len();
+        cache.set_shader_params(2, &params).unwrap();
+        assert_eq!(cache.params.len(), count);
+        assert_eq!(cache.shaders[1].params, hash);
+
+        // An unknown shader leaves no orphaned chunk behind
+        let other = [ShaderParam { slot: 4, ..params[0].clone() }];
+        assert!(cache.set_shader_params(42, &other).is_err());
+        assert_eq!(cache.params.len(), count);
+    }
+}
@@ -100,3 +100,24 @@ pub trait EncodeExt: io::Write + Sized {
 }
 
 impl<O: io::Write> EncodeExt for O {}
+
+struct CountingWriter(u64);
+
+impl io::Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Number of bytes the value occupies once encoded
+pub fn encoded_size<A: Encode>(value: &A) -> u64 {
+    let mut counter = CountingWriter(0);
+    // Writing to the counter can't fail
+    value.encode(&mut counter).unwrap();
+    counter.0
+}
@@ -0,0 +1,476 @@
+use std::io::{self, Cursor};
+
+use crate::bundle::decode::{Decode, DecodeExt};
+use crate::bundle::encode::{Encode, EncodeExt};
+
+/// Four character code identifying a container or part
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FourCC(pub [u8; 4]);
+
+impl FourCC {
+    pub const DXBC: FourCC = FourCC(*b"DXBC");
+    /// DXIL program, LLVM bitcode behind a program header
+    pub const DXIL: FourCC = FourCC(*b"DXIL");
+    /// DXBC program, shader model 5.0 and earlier
+    pub const SHEX: FourCC = FourCC(*b"SHEX");
+    pub const SHDR: FourCC = FourCC(*b"SHDR");
+    /// Resource definitions
+    pub const RDEF: FourCC = FourCC(*b"RDEF");
+    /// Input and output signatures
+    pub const ISGN: FourCC = FourCC(*b"ISGN");
+    pub const OSGN: FourCC = FourCC(*b"OSGN");
+    pub const ISG1: FourCC = FourCC(*b"ISG1");
+    pub const OSG1: FourCC = FourCC(*b"OSG1");
+    pub const PSG1: FourCC = FourCC(*b"PSG1");
+    /// Pipeline state validation
+    pub const PSV0: FourCC = FourCC(*b"PSV0");
+    /// Shader feature info
+    pub const SFI0: FourCC = FourCC(*b"SFI0");
+    /// Statistics, a DXIL program with reflection metadata
+    pub const STAT: FourCC = FourCC(*b"STAT");
+    /// Shader hash
+    pub const HASH: FourCC = FourCC(*b"HASH");
+    /// Debug name
+    pub const ILDN: FourCC = FourCC(*b"ILDN");
+    /// Debug program
+    pub const ILDB: FourCC = FourCC(*b"ILDB");
+    /// Root signature
+    pub const RTS0: FourCC = FourCC(*b"RTS0");
+
+    pub fn as_u32(&self) -> u32 {
+        u32::from_le_bytes(self.0)
+    }
+}
+
+impl From<u32> for FourCC {
+    fn from(value: u32) -> Self {
+        FourCC(value.to_le_bytes())
+    }
+}
+
+impl std::fmt::Display for FourCC {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for b in self.0 {
+            let c = if b.is_ascii_graphic() { b as char } else { '?' };
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for FourCC {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FourCC({})", self)
+    }
+}
+
+impl Decode for FourCC {
+    fn decode<I: io::Read>(input: &mut I) -> io::Result<Self> {
+        Ok(FourCC(input.decode()?))
+    }
+}
+
+impl Encode for FourCC {
+    fn encode<O: io::Write>(&self, output: &mut O) -> io::Result<()> {
+        output.write_all(&self.0)
+    }
+}
+
+/// Program type, from the version token shared by DXBC and DXIL programs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgramKind {
+    Pixel,
+    Vertex,
+    Geometry,
+    Hull,
+    Domain,
+    Compute,
+    Library,
+    RayGeneration,
+    Intersection,
+    AnyHit,
+    ClosestHit,
+    Miss,
+    Callable,
+    Mesh,
+    Amplification,
+    Unknown(u16),
+}
+
+impl From<u16> for ProgramKind {
+    fn from(value: u16) -> Self {
+        match value {
+            0  => ProgramKind::Pixel,
+            1  => ProgramKind::Vertex,
+            2  => ProgramKind::Geometry,
+            3  => ProgramKind::Hull,
+            4  => ProgramKind::Domain,
+            5  => ProgramKind::Compute,
+            6  => ProgramKind::Library,
+            7  => ProgramKind::RayGeneration,
+            8  => ProgramKind::Intersection,
+            9  => ProgramKind::AnyHit,
+            10 => ProgramKind::ClosestHit,
+            11 => ProgramKind::Miss,
+            12 => ProgramKind::Callable,
+            13 => ProgramKind::Mesh,
+            14 => ProgramKind::Amplification,
+            v  => ProgramKind::Unknown(v),
+        }
+    }
+}
+
+/// `kind << 16 | major << 4 | minor`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProgramVersion {
+    pub kind: ProgramKind,
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl From<u32> for ProgramVersion {
+    fn from(value: u32) -> Self {
+        ProgramVersion {
+            kind: ProgramKind::from((value >> 16) as u16),
+            major: ((value >> 4) & 0xF) as u8,
+            minor: (value & 0xF) as u8,
+        }
+    }
+}
+
+impl std::fmt::Display for ProgramVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} {}.{}", self.kind, self.major, self.minor)
+    }
+}
+
+/// Header of a DXIL or STAT part, followed by the LLVM bitcode
+#[derive(Clone, Debug)]
+pub struct DxilProgram<'a> {
+    pub version: ProgramVersion,
+    /// `major << 8 | minor`
+    pub dxil_version: u32,
+    pub bitcode: &'a [u8],
+}
+
+impl<'a> DxilProgram<'a> {
+    // 'DXIL'
+    pub const MAGIC: u32 = 0x4C495844;
+
+    pub fn parse(data: &'a [u8]) -> io::Result<Self> {
+        let mut input = Cursor::new(data);
+        let version: u32 = input.decode()?;
+        let _size_in_dwords: u32 = input.decode()?;
+        let magic: u32 = input.decode()?;
+        let dxil_version: u32 = input.decode()?;
+        let bitcode_offset: u32 = input.decode()?;
+        let bitcode_size: u32 = input.decode()?;
+
+        if magic != DxilProgram::MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid DXIL program magic"));
+        }
+
+        // The offset is relative to the magic
+        let start = 8 + bitcode_offset as usize;
+        let bitcode = data.get(start..start + bitcode_size as usize)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "DXIL bitcode out of bounds"))?;
+
+        Ok(DxilProgram { version: ProgramVersion::from(version), dxil_version, bitcode })
+    }
+}
+
+/// Contents of a HASH part
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShaderHash {
+    /// 1 if the source was included in the hash
+    pub flags: u32,
+    pub digest: [u8; 16],
+}
+
+impl ShaderHash {
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        let mut input = Cursor::new(data);
+        Ok(ShaderHash { flags: input.decode()?, digest: input.decode()? })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::with_capacity(20);
+        data.extend_from_slice(&self.flags.to_le_bytes());
+        data.extend_from_slice(&self.digest);
+        data
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Part {
+    pub fourcc: FourCC,
+    pub data: Vec<u8>,
+}
+
+/// A D3D shader container, a header followed by a table of tagged parts
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Container {
+    /// Stored as is, rebuilding doesn't update it
+    pub digest: [u8; 16],
+    pub major: u16,
+    pub minor: u16,
+    pub parts: Vec<Part>,
+}
+
+impl Default for Container {
+    fn default() -> Self {
+        Container { digest: [0u8; 16], major: 1, minor: 0, parts: Vec::new() }
+    }
+}
+
+impl Container {
+    /// Magic, digest, version, total size and part count
+    pub const HEADER_SIZE: usize = 32;
+    /// Offset of the digest, everything after it is covered by the hash
+    pub const DIGEST_OFFSET: usize = 4;
+
+    /// True if the data starts with a container header
+    pub fn is_container(data: &[u8]) -> bool {
+        data.starts_with(&FourCC::DXBC.0)
+    }
+
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let mut input = Cursor::new(data);
+        let magic: FourCC = input.decode()?;
+        if magic != FourCC::DXBC {
+            return Err(invalid("Invalid container magic"));
+        }
+
+        let digest: [u8; 16] = input.decode()?;
+        let major: u16 = input.decode()?;
+        let minor: u16 = input.decode()?;
+        let size: u32 = input.decode()?;
+        let count: u32 = input.decode()?;
+
+        if size as usize != data.len() {
+            return Err(invalid("Container size mismatch"));
+        }
+        // Checked before allocating anything, the blob may be corrupt
+        if (count as usize).saturating_mul(4) > data.len() - Container::HEADER_SIZE {
+            return Err(invalid("Container part count out of bounds"));
+        }
+
+        let mut offsets: Vec<u32> = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            offsets.push(input.decode()?);
+        }
+
+        let mut parts: Vec<Part> = Vec::with_capacity(count as usize);
+        for offset in offsets {
+            let start = (offset as usize).checked_add(8)
+                .filter(|end| *end <= data.len())
+                .ok_or_else(|| invalid("Container part out of bounds"))?;
+            input.set_position(offset as u64);
+            let fourcc: FourCC = input.decode()?;
+            let size: u32 = input.decode()?;
+
+            let part = start.checked_add(size as usize)
+                .and_then(|end| data.get(start..end))
+                .ok_or_else(|| invalid("Container part out of bounds"))?;
+
+            parts.push(Part { fourcc, data: part.to_vec() });
+        }
+
+        Ok(Container { digest, major, minor, parts })
+    }
+
+    /// Lays the parts out back to back, each starting on a 4 byte boundary
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+        // Writing to a Vec can't fail
+        self.encode(&mut data).unwrap();
+        data
+    }
+
+    pub fn part(&self, fourcc: FourCC) -> Option<&Part> {
+        self.parts.iter().find(|p| p.fourcc == fourcc)
+    }
+
+    pub fn part_mut(&mut self, fourcc: FourCC) -> Option<&mut Part> {
+        self.parts.iter_mut().find(|p| p.fourcc == fourcc)
+    }
+
+    /// Replaces the first part with the same code, or appends a new one
+    pub fn set_part(&mut self, fourcc: FourCC, data: Vec<u8>) {
+        match self.part_mut(fourcc) {
+            Some(p) => p.data = data,
+            None => self.parts.push(Part { fourcc, data }),
+        }
+    }
+
+    /// Removes every part with the code, returning how many were removed
+    pub fn remove_part(&mut self, fourcc: FourCC) -> usize {
+        let count = self.parts.len();
+        self.parts.retain(|p| p.fourcc != fourcc);
+        count - self.parts.len()
+    }
+
+    /// The DXIL program, or the DXBC version token for older shader models
+    pub fn program_version(&self) -> Option<ProgramVersion> {
+        if let Some(p) = self.part(FourCC::DXIL) {
+            return DxilProgram::parse(&p.data).ok().map(|p| p.version);
+        }
+        self.part(FourCC::SHEX).or_else(|| self.part(FourCC::SHDR))
+            .and_then(|p| p.data.get(0..4))
+            .map(|v| ProgramVersion::from(u32::from_le_bytes(v.try_into().unwrap())))
+    }
+
+    pub fn dxil(&self) -> Option<io::Result<DxilProgram<'_>>> {
+        self.part(FourCC::DXIL).map(|p| DxilProgram::parse(&p.data))
+    }
+
+    pub fn stat(&self) -> Option<io::Result<DxilProgram<'_>>> {
+        self.part(FourCC::STAT).map(|p| DxilProgram::parse(&p.data))
+    }
+
+    pub fn hash(&self) -> Option<io::Result<ShaderHash>> {
+        self.part(FourCC::HASH).map(|p| ShaderHash::parse(&p.data))
+    }
+}
+
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+impl Encode for Container {
+    fn encode<O: io::Write>(&self, output: &mut O) -> io::Result<()> {
+        let table = Container::HEADER_SIZE + 4 * self.parts.len();
+
+        let mut offsets: Vec<u32> = Vec::with_capacity(self.parts.len());
+        let mut offset = align4(table);
+        for p in &self.parts {
+            offsets.push(offset as u32);
+            offset = align4(offset + 8 + p.data.len());
+        }
+        // The last part isn't padded
+        let size = self.parts.last().map_or(table, |p| *offsets.last().unwrap() as usize + 8 + p.data.len());
+
+        output.encode(&FourCC::DXBC)?;
+        output.encode(&self.digest)?;
+        output.encode(&self.major)?;
+        output.encode(&self.minor)?;
+        output.encode(&(size as u32))?;
+        output.encode(&(self.parts.len() as u32))?;
+        for o in &offsets {
+            output.encode(o)?;
+        }
+
+        let mut position = table;
+        for (p, o) in self.parts.iter().zip(&offsets) {
+            output.write_all(&vec![0u8; *o as usize - position])?;
+            output.encode(&p.fourcc)?;
+            output.encode(&(p.data.len() as u32))?;
+            output.write_all(&p.data)?;
+            position = *o as usize + 8 + p.data.len();
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dxil_part(version: u32) -> Vec<u8> {
+        let bitcode = [0x42, 0x43, 0xC0, 0xDE];
+        let mut data: Vec<u8> = Vec::new();
+        data.extend_from_slice(&version.to_le_bytes());
+        data.extend_from_slice(&(((24 + bitcode.len()) / 4) as u32).to_le_bytes());
+        data.extend_from_slice(&DxilProgram::MAGIC.to_le_bytes());
+        data.extend_from_slice(&0x106u32.to_le_bytes());
+        data.extend_from_slice(&16u32.to_le_bytes());
+        data.extend_from_slice(&(bitcode.len() as u32).to_le_bytes());
+        data.extend_from_slice(&bitcode);
+        data
+    }
+
+    fn test_container() -> Container {
+        Container {
+            digest: [7u8; 16],
+            major: 1,
+            minor: 0,
+            parts: vec![
+                Part { fourcc: FourCC::SFI0, data: vec![0u8; 8] },
+                Part { fourcc: FourCC::ISG1, data: vec![1u8; 6] },
+                Part { fourcc: FourCC::DXIL, data: dxil_part(0x0001_0066) },
+            ],
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let container = test_container();
+        let data = container.to_bytes();
+
+        assert!(Container::is_container(&data));
+        assert_eq!(u32::from_le_bytes(data[24..28].try_into().unwrap()) as usize, data.len());
+        // Offsets follow the table, parts start on 4 byte boundaries
+        assert_eq!(u32::from_le_bytes(data[32..36].try_into().unwrap()), 44);
+        assert_eq!(u32::from_le_bytes(data[36..40].try_into().unwrap()), 44 + 16);
+        assert_eq!(u32::from_le_bytes(data[40..44].try_into().unwrap()), 44 + 16 + 16);
+
+        let parsed = Container::parse(&data).unwrap();
+        assert_eq!(parsed, container);
+        assert_eq!(parsed.to_bytes(), data);
+    }
+
+    #[test]
+    fn edit_parts() {
+        let mut container = test_container();
+        container.set_part(FourCC::ISG1, vec![2u8; 12]);
+        container.set_part(FourCC::HASH, ShaderHash { flags: 0, digest: [9u8; 16] }.to_bytes());
+        assert_eq!(container.remove_part(FourCC::SFI0), 1);
+
+        let parsed = Container::parse(&container.to_bytes()).unwrap();
+        let codes: Vec<FourCC> = parsed.parts.iter().map(|p| p.fourcc).collect();
+        assert_eq!(codes, vec![ FourCC::ISG1, FourCC::DXIL, FourCC::HASH ]);
+        assert_eq!(parsed.part(FourCC::ISG1).unwrap().data, vec![2u8; 12]);
+        assert_eq!(parsed.hash().unwrap().unwrap().digest, [9u8; 16]);
+    }
+
+    #[test]
+    fn program() {
+        let container = test_container();
+        let dxil = container.dxil().unwrap().unwrap();
+
+        assert_eq!(dxil.version, ProgramVersion { kind: ProgramKind::Vertex, major: 6, minor: 6 });
+        assert_eq!(dxil.dxil_version, 0x106);
+        assert_eq!(dxil.bitcode, &[0x42, 0x43, 0xC0, 0xDE]);
+        assert_eq!(container.program_version().unwrap().kind, ProgramKind::Vertex);
+    }
+
+    #[test]
+    fn invalid() {
+        let mut data = test_container().to_bytes();
+        assert!(Container::parse(&data[..data.len() - 1]).is_err());
+
+        data[0] = b'X';
+        assert!(!Container::is_container(&data));
+        assert!(Container::parse(&data).is_err());
+    }
+
+    #[test]
+    fn corrupt_sizes() {
+        let data = test_container().to_bytes();
+        let corrupt = |offset: usize, value: u32| {
+            let mut data = data.clone();
+            data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            Container::parse(&data)
+        };
+
+        // Part count, first part offset and first part size
+        assert!(corrupt(28, u32::MAX).is_err());
+        assert!(corrupt(32, u32::MAX - 4).is_err());
+        let first = u32::from_le_bytes(data[32..36].try_into().unwrap()) as usize;
+        assert!(corrupt(first + 4, u32::MAX).is_err());
+    }
+}
@@ -0,0 +1,403 @@
+use std::io::{self, Cursor};
+
+use anyhow::Result;
+use hashbrown::HashMap;
+use serde::Serialize;
+
+use crate::analysis::ser_hashes;
+use crate::bitcode::{self, Block};
+use crate::bundle::decode::DecodeExt;
+use crate::container::{Container, DxilProgram, FourCC};
+use crate::manager::Manager;
+
+/// Debug name and source files of a DXIL shader.
+///
+/// The name comes from the ILDN part, the source files from the debug module in
+/// the ILDB part. DXBC debug data lives in an embedded PDB and isn't read.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DebugInfo {
+    /// Usually the PDB file name written by the compiler, `<hash>.pdb` unless one was given
+    pub name: Option<String>,
+    /// File of the compile unit, the `.fx` the shader was compiled from
+    pub main_file: Option<String>,
+    /// Every file referenced by the debug info, main file included, in module order
+    pub source_files: Vec<String>,
+}
+
+// LLVM 3.7 metadata records
+const MODULE_BLOCK: u32 = 8;
+const METADATA_BLOCK: u32 = 15;
+
+const METADATA_STRING: u32 = 1;
+const METADATA_NAME: u32 = 4;
+const METADATA_KIND: u32 = 6;
+const METADATA_NAMED_NODE: u32 = 10;
+const METADATA_FILE: u32 = 16;
+const METADATA_COMPILE_UNIT: u32 = 20;
+
+/// Contents of an ILDN part
+pub fn parse_debug_name(data: &[u8]) -> io::Result<String> {
+    let mut input = Cursor::new(data);
+    let _flags: u16 = input.decode()?;
+    let length: u16 = input.decode()?;
+    let name = data.get(4..4 + length as usize)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "ILDN name out of bounds"))?;
+    Ok(String::from_utf8_lossy(name).into_owned())
+}
+
+fn is_absolute(path: &str) -> bool {
+    path.starts_with(['/', '\\']) || path.as_bytes().get(1) == Some(&b':')
+}
+
+/// Source files of the DIFile records, and the one of the compile unit
+fn source_files(module: &Block) -> (Option<String>, Vec<String>) {
+    // Strings and nodes share the numbering, names and kinds don't take an id
+    let mut strings: HashMap<u64, String> = HashMap::new();
+    let mut files: Vec<(u64, String)> = Vec::new();
+    let mut main_file: Option<u64> = None;
+
+    let mut id: u64 = 0;
+    for r in module.blocks(METADATA_BLOCK).flat_map(Block::records) {
+        // Operands referring to metadata are offset by one, zero is null
+        let string = |op: usize| r.ops.get(op)
+            .and_then(|v| v.checked_sub(1))
+            .and_then(|v| strings.get(&v))
+            .cloned()
+            .unwrap_or_default();
+
+        match r.code {
+            METADATA_NAME | METADATA_KIND | METADATA_NAMED_NODE => continue,
+            METADATA_STRING => {
+                strings.insert(id, r.ops.iter().map(|c| *c as u8 as char).collect());
+            },
+            METADATA_FILE => {
+                let (name, dir) = (string(1), string(2));
+                let path = if dir.is_empty() || dir == "." || is_absolute(&name) {
+                    name
+                }
+                else {
+                    format!("{}/{}", dir.trim_end_matches(['/', '\\']), name)
+                };
+                files.push((id, path));
+            },
+            METADATA_COMPILE_UNIT => {
+                main_file = r.ops.get(2).and_then(|v| v.checked_sub(1));
+            },
+            _ => {},
+        }
+        id += 1;
+    }
+
+    let main_file = main_file.and_then(|m| files.iter().find(|(id, _)| *id == m)).map(|(_, p)| p.clone());
+    let mut paths: Vec<String> = Vec::new();
+    for (_, p) in files {
+        if !p.is_empty() && !paths.contains(&p) {
+            paths.push(p);
+        }
+    }
+    (main_file, paths)
+}
+
+impl DebugInfo {
+    /// None if the blob has no debug name or debug module
+    pub fn from_blob(data: &[u8]) -> Option<io::Result<Self>> {
+        if !Container::is_container(data) {
+            return None;
+        }
+        match Container::parse(data) {
+            Ok(c) => DebugInfo::from_container(&c),
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    pub fn from_container(container: &Container) -> Option<io::Result<Self>> {
+        let name = container.part(FourCC::ILDN);
+        let program = container.part(FourCC::ILDB);
+        if name.is_none() && program.is_none() {
+            return None;
+        }
+
+        let mut info = DebugInfo::default();
+        if let Some(p) = name {
+            match parse_debug_name(&p.data) {
+                Ok(n) => info.name = Some(n),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        if let Some(p) = program {
+            let blocks = match DxilProgram::parse(&p.data).and_then(|p| bitcode::parse(p.bitcode)) {
+                Ok(b) => b,
+                Err(e) => return Some(Err(e)),
+            };
+            if let Some(module) = blocks.iter().find(|b| b.id == MODULE_BLOCK) {
+                (info.main_file, info.source_files) = source_files(module);
+            }
+        }
+        Some(Ok(info))
+    }
+}
+
+/// Lowercase with forward slashes, cache and debug paths disagree on both
+pub fn normalize_path(path: &str) -> String {
+    let path = path.replace('\\', "/").to_lowercase();
+    path.trim_start_matches("./").to_string()
+}
+
+/// True if `path` is `suffix` or ends with it on a directory boundary.
+/// Debug paths are usually absolute where include paths are relative to the depot.
+fn ends_with_path(path: &str, suffix: &str) -> bool {
+    path == suffix || path.strip_suffix(suffix).is_some_and(|p| p.ends_with('/'))
+}
+
+
+#[derive(Serialize)]
+pub struct DebugName {
+    pub name: String,
+    #[serde(serialize_with = "ser_hashes")]
+    pub shaders: Vec<u64>,
+}
+
+#[derive(Serialize)]
+pub struct SourceFile {
+    /// Normalized path from the debug info
+    pub path: String,
+    /// Shaders compiled from this file
+    #[serde(serialize_with = "ser_hashes")]
+    pub main_of: Vec<u64>,
+    /// Shaders referencing this file, the ones in `main_of` included
+    #[serde(serialize_with = "ser_hashes")]
+    pub shaders: Vec<u64>,
+    /// Path of the matching `IncludesChecksumChunk`
+    pub include: Option<String>,
+}
+
+/// Shaders by debug name and source file, sorted by name and path
+#[derive(Serialize)]
+pub struct DebugIndex {
+    pub names: Vec<DebugName>,
+    pub sources: Vec<SourceFile>,
+    /// Include chunks no debug info refers to
+    pub unmatched_includes: Vec<String>,
+    /// Shaders without any debug info
+    pub without_debug: usize,
+}
+
+impl DebugIndex {
+    pub fn build(manager: &Manager) -> Self {
+        let mut names: HashMap<String, Vec<u64>> = HashMap::new();
+        let mut sources: HashMap<String, SourceFile> = HashMap::new();
+        let mut without_debug = 0;
+
+        let mut shaders: Vec<_> = manager.shaders.values().collect();
+        shaders.sort_by_key(|s| s.hash);
+
+        for s in shaders {
+            let Some(debug) = &s.debug else {
+                without_debug += 1;
+                continue;
+            };
+            if let Some(name) = &debug.name {
+                names.entry_ref(name.as_str()).or_default().push(s.hash);
+            }
+
+            let main_file = debug.main_file.as_deref().map(normalize_path);
+            for path in debug.source_files.iter().map(|p| normalize_path(p)) {
+                let is_main = main_file.as_ref() == Some(&path);
+                let file = sources.entry(path.clone()).or_insert_with(|| SourceFile {
+                    path,
+                    main_of: Vec::new(),
+                    shaders: Vec::new(),
+                    include: None,
+                });
+                file.shaders.push(s.hash);
+                if is_main {
+                    file.main_of.push(s.hash);
+                }
+            }
+        }
+
+        let mut unmatched_includes = Vec::new();
+        for include in &manager.includes {
+            let path = normalize_path(include.path.as_str());
+            let mut matched = false;
+            for file in sources.values_mut().filter(|f| ends_with_path(&f.path, &path)) {
+                file.include = Some(include.path.to_string());
+                matched = true;
+            }
+            if !matched {
+                unmatched_includes.push(include.path.to_string());
+            }
+        }
+        unmatched_includes.sort();
+
+        let mut names: Vec<DebugName> = names.into_iter().map(|(name, shaders)| DebugName { name, shaders }).collect();
+        names.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut sources: Vec<SourceFile> = sources.into_values().collect();
+        sources.sort_by(|a, b| a.path.cmp(&b.path));
+
+        DebugIndex { names, sources, unmatched_includes, without_debug }
+    }
+
+    /// Shaders referencing a source file, matched on the trailing path components
+    pub fn shaders_for_source(&self, path: &str) -> Vec<u64> {
+        let path = normalize_path(path);
+        let mut hashes: Vec<u64> = self.sources.iter()
+            .filter(|f| ends_with_path(&f.path, &path))
+            .flat_map(|f| f.shaders.iter().copied())
+            .collect();
+        hashes.sort();
+        hashes.dedup();
+        hashes
+    }
+
+    pub fn shaders_for_name(&self, name: &str) -> &[u64] {
+        self.names.iter()
+            .find(|n| n.name.eq_ignore_ascii_case(name))
+            .map(|n| n.shaders.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+impl std::fmt::Display for DebugIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:<64} {:>8} {:>8}  Include", "Source", "Main of", "Shaders")?;
+        for s in &self.sources {
+            writeln!(
+                f, "{:<64} {:>8} {:>8}  {}",
+                s.path, s.main_of.len(), s.shaders.len(), s.include.as_deref().unwrap_or("-")
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{:<64} {:>8}", "Debug name", "Shaders")?;
+        for n in &self.names {
+            writeln!(f, "{:<64} {:>8}", n.name, n.shaders.len())?;
+        }
+
+        if !self.unmatched_includes.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Includes without debug info:")?;
+            for i in &self.unmatched_includes {
+                writeln!(f, "  {}", i)?;
+            }
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{} shaders without debug info", self.without_debug)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::bitcode::tests::Writer;
+    use crate::bundle::dyn_cache::IncludesChecksumChunk;
+    use crate::container::Part;
+    use crate::rtti_types::cname::CName;
+
+    use super::*;
+
+    fn ildn(name: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(0u16.to_le_bytes());
+        data.extend((name.len() as u16).to_le_bytes());
+        data.extend(name.as_bytes());
+        data.push(0);
+        data.resize(data.len().next_multiple_of(4), 0);
+        data
+    }
+
+    fn string(w: &mut Writer, s: &str) {
+        let ops: Vec<u64> = s.bytes().map(u64::from).collect();
+        w.record(METADATA_STRING, &ops);
+    }
+
+    /// Debug module with two files, the compile unit pointing at the second
+    fn ildb() -> Vec<u8> {
+        let mut w = Writer::new();
+        w.enter(MODULE_BLOCK);
+        w.enter(METADATA_BLOCK);
+        w.record(METADATA_KIND, &[0, 100]);
+        // Ids 0 to 3
+        string(&mut w, "include/common.fxh");
+        string(&mut w, "D:\\depot\\engine\\shaders");
+        string(&mut w, "D:\\depot\\engine\\shaders\\metal_base.fx");
+        string(&mut w, "");
+        // Ids 4 and 5
+        w.record(METADATA_FILE, &[0, 1, 2]);
+        w.record(METADATA_FILE, &[0, 3, 4]);
+        w.record(METADATA_NAME, &[100]);
+        w.record(METADATA_NAMED_NODE, &[6]);
+        // Id 6
+        w.record(METADATA_COMPILE_UNIT, &[1, 4, 6]);
+        w.end();
+        w.end();
+        let bitcode = w.finish();
+
+        let mut data = Vec::new();
+        data.extend((6u32 << 4).to_le_bytes());
+        data.extend((((24 + bitcode.len()) / 4) as u32).to_le_bytes());
+        data.extend(DxilProgram::MAGIC.to_le_bytes());
+        data.extend(0x100u32.to_le_bytes());
+        data.extend(16u32.to_le_bytes());
+        data.extend((bitcode.len() as u32).to_le_bytes());
+        data.extend(bitcode);
+        data
+    }
+
+    fn blob() -> Vec<u8> {
+        Container {
+            parts: vec![
+                Part { fourcc: FourCC::ILDN, data: ildn("metal_base_ps.pdb") },
+                Part { fourcc: FourCC::ILDB, data: ildb() },
+            ],
+            ..Default::default()
+        }.to_bytes()
+    }
+
+    #[test]
+    fn extract() {
+        assert_eq!(parse_debug_name(&ildn("abc.pdb")).unwrap(), "abc.pdb");
+        assert!(DebugInfo::from_blob(b"not a container").is_none());
+
+        let info = DebugInfo::from_blob(&blob()).unwrap().unwrap();
+        assert_eq!(info.name.as_deref(), Some("metal_base_ps.pdb"));
+        assert_eq!(info.main_file.as_deref(), Some("D:\\depot\\engine\\shaders\\metal_base.fx"));
+        assert_eq!(info.source_files, vec![
+            "D:\\depot\\engine\\shaders/include/common.fxh".to_string(),
+            "D:\\depot\\engine\\shaders\\metal_base.fx".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn index() {
+        let mut cache = crate::test_util::test_cache();
+        cache.shaders[1].compiled = blob();
+        cache.includes = vec![
+            IncludesChecksumChunk { path: CName::new("engine\\shaders\\include\\common.fxh"), hash: 1 },
+            IncludesChecksumChunk { path: CName::new("engine\\shaders\\include\\lighting.fxh"), hash: 2 },
+        ];
+        cache.info = cache.layout();
+        let hash = cache.shaders[1].hash;
+
+        let manager = Manager::from_dyn_cache(cache).unwrap();
+        let index = DebugIndex::build(&manager);
+        assert_eq!(index.without_debug, 1);
+        assert_eq!(index.shaders_for_name("METAL_BASE_PS.pdb"), &[hash]);
+
+        assert_eq!(index.sources.len(), 2);
+        let common = &index.sources[0];
+        assert_eq!(common.path, "d:/depot/engine/shaders/include/common.fxh");
+        assert_eq!(common.include.as_deref(), Some("engine\\shaders\\include\\common.fxh"));
+        assert!(common.main_of.is_empty());
+        assert_eq!(index.sources[1].main_of, vec![hash]);
+        assert_eq!(index.unmatched_includes, vec!["engine\\shaders\\include\\lighting.fxh".to_string()]);
+
+        assert_eq!(index.shaders_for_source("shaders/metal_base.fx"), vec![hash]);
+        assert!(index.shaders_for_source("base.fx").is_empty());
+    }
+}
@@ -0,0 +1,153 @@
+use hashbrown::HashMap;
+
+use crate::bundle::dyn_cache::{DynamicCacheFile, MaterialChunk};
+
+/// Differences between two caches, chunks are matched by hash
+#[derive(Default)]
+pub struct CacheDiff {
+    pub shaders_added: Vec<u64>,
+    pub shaders_removed: Vec<u64>,
+    /// Same hash with a different blob or params
+    pub shaders_changed: Vec<u64>,
+    pub techniques_added: Vec<String>,
+    pub techniques_removed: Vec<String>,
+    /// Same technique with different shaders or samplers
+    pub techniques_changed: Vec<String>,
+    pub params_added: Vec<u64>,
+    pub params_removed: Vec<u64>,
+}
+
+impl CacheDiff {
+    pub fn is_empty(&self) -> bool {
+        self.shaders_added.is_empty() && self.shaders_removed.is_empty() && self.shaders_changed.is_empty()
+        && self.techniques_added.is_empty() && self.techniques_removed.is_empty() && self.techniques_changed.is_empty()
+        && self.params_added.is_empty() && self.params_removed.is_empty()
+    }
+}
+
+impl std::fmt::Display for CacheDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for hash in &self.shaders_removed {
+            writeln!(f, "- shader    [{:016X}]", hash)?;
+        }
+        for hash in &self.shaders_added {
+            writeln!(f, "+ shader    [{:016X}]", hash)?;
+        }
+        for hash in &self.shaders_changed {
+            writeln!(f, "~ shader    [{:016X}]", hash)?;
+        }
+        for name in &self.techniques_removed {
+            writeln!(f, "- technique {}", name)?;
+        }
+        for name in &self.techniques_added {
+            writeln!(f, "+ technique {}", name)?;
+        }
+        for name in &self.techniques_changed {
+            writeln!(f, "~ technique {}", name)?;
+        }
+        for hash in &self.params_removed {
+            writeln!(f, "- params    [{:016X}]", hash)?;
+        }
+        for hash in &self.params_added {
+            writeln!(f, "+ params    [{:016X}]", hash)?;
+        }
+        Ok(())
+    }
+}
+
+fn same_technique(a: &MaterialChunk, b: &MaterialChunk) -> bool {
+    a.vs_hash == b.vs_hash
+    && a.ps_hash == b.ps_hash
+    && a.vs_samplers == b.vs_samplers
+    && a.ps_samplers == b.ps_samplers
+}
+
+/// Compares `new` against `old`, each list is in the file order of the cache it was found in
+pub fn diff_caches(old: &DynamicCacheFile, new: &DynamicCacheFile) -> CacheDiff {
+    let mut diff = CacheDiff::default();
+
+    //--------------------------------------------------------------------------
+    // Shaders
+
+    let old_shaders: HashMap<u64, _> = old.shaders.iter().map(|s| (s.hash, s)).collect();
+    let new_shaders: HashMap<u64, _> = new.shaders.iter().map(|s| (s.hash, s)).collect();
+
+    for s in &old.shaders {
+        if !new_shaders.contains_key(&s.hash) {
+            diff.shaders_removed.push(s.hash);
+        }
+    }
+    for s in &new.shaders {
+        match old_shaders.get(&s.hash) {
+            None => diff.shaders_added.push(s.hash),
+            Some(o) if o.params != s.params || o.compiled != s.compiled => diff.shaders_changed.push(s.hash),
+            _ => {},
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    // Techniques
+
+    let old_materials: HashMap<u64, _> = old.materials.iter().map(|m| (m.hash, m)).collect();
+    let new_materials: HashMap<u64, _> = new.materials.iter().map(|m| (m.hash, m)).collect();
+
+    for m in &old.materials {
+        if !new_materials.contains_key(&m.hash) {
+            diff.techniques_removed.push(m.name.to_string());
+        }
+    }
+    for m in &new.materials {
+        match old_materials.get(&m.hash) {
+            None => diff.techniques_added.push(m.name.to_string()),
+            Some(o) if !same_technique(o, m) => diff.techniques_changed.push(m.name.to_string()),
+            _ => {},
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    // Params
+
+    let old_params: HashMap<u64, _> = old.params.iter().map(|p| (p.hash, p)).collect();
+    let new_params: HashMap<u64, _> = new.params.iter().map(|p| (p.hash, p)).collect();
+
+    diff.params_removed = old.params.iter().map(|p| p.hash).filter(|h| !new_params.contains_key(h)).collect();
+    diff.params_added = new.params.iter().map(|p| p.hash).filter(|h| !old_params.contains_key(h)).collect();
+
+    diff
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{shader, technique, test_cache};
+
+    use super::*;
+
+    #[test]
+    fn identical() {
+        let cache = test_cache();
+        assert!(diff_caches(&cache, &cache.clone()).is_empty());
+    }
+
+    #[test]
+    fn changes() {
+        let old = test_cache();
+        let mut new = old.clone();
+
+        new.shaders[1].compiled = b"pixel2".to_vec();
+        new.shaders.push(shader(3));
+        new.materials[0].ps_hash = 3;
+        new.materials.remove(1);
+        new.materials.push(technique("glass", 0, 1, 2));
+
+        let diff = diff_caches(&old, &new);
+
+        assert_eq!(diff.shaders_added, vec![ 3 ]);
+        assert!(diff.shaders_removed.is_empty());
+        assert_eq!(diff.shaders_changed, vec![ 2 ]);
+        assert_eq!(diff.techniques_added, vec![ new.materials[1].name.to_string() ]);
+        assert_eq!(diff.techniques_removed, vec![ old.materials[1].name.to_string() ]);
+        assert_eq!(diff.techniques_changed, vec![ old.materials[0].name.to_string() ]);
+        assert!(diff.params_added.is_empty() && diff.params_removed.is_empty());
+    }
+}
@@ -0,0 +1,190 @@
+use crate::container::Container;
+
+// Per-round shift amounts and sine constants, as in RFC 1321
+const S: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22,
+    5,  9, 14, 20, 5,  9, 14, 20, 5,  9, 14, 20, 5,  9, 14, 20,
+    4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23,
+    6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+const K: [u32; 64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+    0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+    0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+    0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+    0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+    0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+    0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+    0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+
+fn md5_block(state: &mut [u32; 4], block: &[u8; 64]) {
+    let mut x = [0u32; 16];
+    for (i, word) in block.chunks_exact(4).enumerate() {
+        x[i] = u32::from_le_bytes(word.try_into().unwrap());
+    }
+
+    let [mut a, mut b, mut c, mut d] = *state;
+    for i in 0..64 {
+        let (f, g) = match i / 16 {
+            0 => ((b & c) | (!b & d), i),
+            1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+            2 => (b ^ c ^ d, (3 * i + 5) % 16),
+            _ => (c ^ (b | !d), (7 * i) % 16),
+        };
+        let rotated = a.wrapping_add(f).wrapping_add(K[i]).wrapping_add(x[g]).rotate_left(S[i]);
+        a = d;
+        d = c;
+        c = b;
+        b = b.wrapping_add(rotated);
+    }
+
+    state[0] = state[0].wrapping_add(a);
+    state[1] = state[1].wrapping_add(b);
+    state[2] = state[2].wrapping_add(c);
+    state[3] = state[3].wrapping_add(d);
+}
+
+/// MD5 with the length moved around, as used for DXBC and DXIL container digests.
+///
+/// The last block starts with the bit count instead of ending with it, and ends
+/// with `byte_count << 1 | 1`.
+///
+/// Not yet checked against a compiler-signed container, so there's no known-answer
+/// test beyond signing and verifying our own.
+pub fn container_hash(data: &[u8]) -> [u8; 16] {
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+
+    let mut blocks = data.chunks_exact(64);
+    for block in &mut blocks {
+        md5_block(&mut state, block.try_into().unwrap());
+    }
+    let rest = blocks.remainder();
+
+    let bits = (data.len() as u32) << 3;
+    let tail = (data.len() as u32) << 1 | 1;
+
+    let mut last = [0u8; 64];
+    if rest.len() < 56 {
+        last[0..4].copy_from_slice(&bits.to_le_bytes());
+        last[4..4 + rest.len()].copy_from_slice(rest);
+        last[4 + rest.len()] = 0x80;
+    }
+    else {
+        // Doesn't fit with the length, pad out this block and add another
+        let mut block = [0u8; 64];
+        block[..rest.len()].copy_from_slice(rest);
+        block[rest.len()] = 0x80;
+        md5_block(&mut state, &block);
+
+        last[0..4].copy_from_slice(&bits.to_le_bytes());
+    }
+    last[60..64].copy_from_slice(&tail.to_le_bytes());
+    md5_block(&mut state, &last);
+
+    let mut digest = [0u8; 16];
+    for (i, s) in state.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&s.to_le_bytes());
+    }
+    digest
+}
+
+/// Everything after the magic and digest
+const HASHED_OFFSET: usize = Container::DIGEST_OFFSET + 16;
+
+/// Digest of a serialized container, None if it isn't one
+pub fn compute_digest(data: &[u8]) -> Option<[u8; 16]> {
+    if !Container::is_container(data) || data.len() < Container::HEADER_SIZE {
+        return None;
+    }
+    Some(container_hash(&data[HASHED_OFFSET..]))
+}
+
+/// True if the stored digest matches the contents
+pub fn verify_digest(data: &[u8]) -> bool {
+    compute_digest(data).is_some_and(|d| data[Container::DIGEST_OFFSET..HASHED_OFFSET] == d)
+}
+
+/// Recomputes the stored digest, returning true if it changed
+pub fn resign(data: &mut [u8]) -> bool {
+    let Some(digest) = compute_digest(data) else {
+        return false;
+    };
+    let stored = &mut data[Container::DIGEST_OFFSET..HASHED_OFFSET];
+    if *stored == digest {
+        return false;
+    }
+    stored.copy_from_slice(&digest);
+    true
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::container::{FourCC, Part};
+
+    use super::*;
+
+    /// Plain MD5 over the same block function, for the RFC 1321 test suite
+    fn md5(data: &[u8]) -> String {
+        let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+        let mut padded = data.to_vec();
+        padded.push(0x80);
+        while padded.len() % 64 != 56 {
+            padded.push(0);
+        }
+        padded.extend_from_slice(&((data.len() as u64) << 3).to_le_bytes());
+        for block in padded.chunks_exact(64) {
+            md5_block(&mut state, block.try_into().unwrap());
+        }
+        state.iter().flat_map(|s| s.to_le_bytes()).map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn md5_known_answers() {
+        assert_eq!(md5(b""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(md5(b"abc"), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(md5(b"message digest"), "f96b697d7cb7938d525a2f31aaf161d0");
+        assert_eq!(md5("1234567890".repeat(8).as_bytes()), "57edf4a22be3c955ac49da2e2107b67a");
+    }
+
+    #[test]
+    fn md5_layout() {
+        // With the length moved the digests differ from plain MD5, but every
+        // padding case must still be distinct
+        let digests: Vec<[u8; 16]> = [0usize, 1, 55, 56, 63, 64, 119, 120]
+            .iter()
+            .map(|n| container_hash(&vec![0xAB; *n]))
+            .collect();
+
+        for (i, a) in digests.iter().enumerate() {
+            for b in &digests[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+        assert_eq!(container_hash(b"abc"), container_hash(b"abc"));
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let container = Container {
+            parts: vec![ Part { fourcc: FourCC::SFI0, data: vec![1, 2, 3, 4, 5, 6, 7, 8] } ],
+            ..Default::default()
+        };
+        let mut data = container.to_bytes();
+
+        assert!(!verify_digest(&data));
+        assert!(resign(&mut data));
+        assert!(verify_digest(&data));
+        assert!(!resign(&mut data));
+
+        // Any change after the digest invalidates it
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(!verify_digest(&data));
+
+        assert!(!verify_digest(b"not a container"));
+        assert!(!resign(&mut [0u8; 8]));
+    }
+}
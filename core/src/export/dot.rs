@@ -0,0 +1,182 @@
+use std::fmt::Write;
+
+use hashbrown::{HashMap, HashSet};
+
+use crate::manager::Manager;
+use crate::material::{Material, TechniqueFilter};
+use crate::shader::Shader;
+
+/// Params blocks are inlined into each `Shader`, so shaders sharing one are
+/// found by comparing the mask and every param
+#[derive(PartialEq, Eq, Hash)]
+struct ParamsKey {
+    mat_mod_mask: u32,
+    params: Vec<(String, u8, u8)>,
+}
+
+impl From<&Shader> for ParamsKey {
+    fn from(value: &Shader) -> Self {
+        ParamsKey {
+            mat_mod_mask: value.mat_mod_mask,
+            params: value.params.iter().map(|p| (p.name.to_string(), p.kind as u8, p.slot)).collect(),
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Writes the material → technique → shader → params graph as GraphViz DOT.
+///
+/// Techniques are clustered by material and edges to shaders are labelled VS/PS.
+/// Shaders used by more than one material are filled, as are params blocks shared
+/// by more than one shader.
+pub fn export_dot(manager: &Manager, filter: &TechniqueFilter) -> String {
+    let mut materials: Vec<&Material> = manager.materials.values()
+        .map(|m| m.as_ref())
+        .filter(|m| filter.matches_material(&m.name))
+        .collect();
+    materials.sort_by(|a, b| a.name.cmp(&b.name));
+
+    // Shaders in first-use order, and the materials using each
+    let mut shaders: Vec<&Shader> = Vec::new();
+    let mut users: HashMap<u64, HashSet<&str>> = HashMap::new();
+    let mut edges: Vec<(String, u64, &str)> = Vec::new();
+
+    let mut out = String::new();
+    writeln!(out, "digraph shaders {{").unwrap();
+    writeln!(out, "    rankdir=LR;").unwrap();
+    writeln!(out, "    node [fontname=\"monospace\", fontsize=10];").unwrap();
+
+    for (mi, m) in materials.iter().enumerate() {
+        let techniques: Vec<_> = m.techniques.iter()
+            .filter(|t| filter.matches_desc(&t.desc))
+            .collect();
+        if techniques.is_empty() {
+            continue;
+        }
+
+        writeln!(out).unwrap();
+        writeln!(out, "    subgraph \"cluster_{}\" {{", mi).unwrap();
+        writeln!(out, "        label=\"{}\";", escape(&m.name)).unwrap();
+
+        for (ti, t) in techniques.into_iter().enumerate() {
+            let id = format!("t_{}_{}", mi, ti);
+            writeln!(
+                out, "        \"{}\" [shape=ellipse, label=\"{} {}\\n{}\"];",
+                id, t.desc.index, escape(t.desc.pass.as_str()), t.desc.vertex_factory
+            ).unwrap();
+
+            for (stage, shader) in [("VS", &t.vs), ("PS", &t.ps)] {
+                let Some(s) = shader else {
+                    continue;
+                };
+                let u = users.entry(s.hash).or_default();
+                if u.is_empty() {
+                    shaders.push(s);
+                }
+                u.insert(m.name.as_str());
+                edges.push((id.clone(), s.hash, stage));
+            }
+        }
+
+        writeln!(out, "    }}").unwrap();
+    }
+
+    //--------------------------------------------------------------------------
+    // Shaders and params
+
+    writeln!(out).unwrap();
+
+    let mut params: HashMap<ParamsKey, usize> = HashMap::new();
+    let mut params_users: Vec<usize> = Vec::new();
+    let mut params_edges: Vec<(u64, usize)> = Vec::new();
+
+    for s in &shaders {
+        let shared = users[&s.hash].len() > 1;
+        writeln!(
+            out, "    \"s_{:016X}\" [shape=box{}, label=\"{} {:016X}\\n{} bytes\"];",
+            s.hash, if shared { ", style=filled, fillcolor=\"#f9e2af\"" } else { "" },
+            s.kind, s.hash, s.compiled.len()
+        ).unwrap();
+
+        let next = params.len();
+        let p = *params.entry(ParamsKey::from(*s)).or_insert(next);
+        if p == params_users.len() {
+            params_users.push(0);
+        }
+        params_users[p] += 1;
+        params_edges.push((s.hash, p));
+    }
+
+    for (p, count) in params_users.iter().enumerate() {
+        writeln!(
+            out, "    \"p_{}\" [shape=note{}, label=\"params {}\\n{} shaders\"];",
+            p, if *count > 1 { ", style=filled, fillcolor=\"#a6e3a1\"" } else { "" }, p, count
+        ).unwrap();
+    }
+
+    writeln!(out).unwrap();
+
+    for (technique, hash, stage) in edges {
+        writeln!(out, "    \"{}\" -> \"s_{:016X}\" [label=\"{}\"];", technique, hash, stage).unwrap();
+    }
+    for (hash, p) in params_edges {
+        writeln!(out, "    \"s_{:016X}\" -> \"p_{}\" [style=dashed];", hash, p).unwrap();
+    }
+
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::glob::Glob;
+    use crate::material::TechniqueDesc;
+    use crate::renderstage::RenderStage;
+    use crate::test_util::{desc, shader, technique_with, test_cache};
+
+    use super::*;
+
+    /// The shared fixture's first technique and a transparent glass technique sharing its VS
+    fn test_manager() -> Manager {
+        let transparent = TechniqueDesc { pass: RenderStage::Transparent, ..desc(0) };
+
+        let mut cache = test_cache();
+        cache.shaders.push(shader(3));
+        cache.materials[1] = technique_with("glass", &transparent, 1, 3);
+
+        Manager::from_dyn_cache(cache).unwrap()
+    }
+
+    #[test]
+    fn graph() {
+        let dot = export_dot(&test_manager(), &TechniqueFilter::default());
+
+        assert!(dot.starts_with("digraph shaders {"));
+        assert!(dot.contains("label=\"glass\";"));
+        assert!(dot.contains("label=\"metal_base\";"));
+        // Shared by both materials
+        assert!(dot.contains("\"s_0000000000000001\" [shape=box, style=filled"));
+        assert!(dot.contains("\"s_0000000000000002\" [shape=box, label"));
+        assert!(dot.contains("-> \"s_0000000000000003\" [label=\"PS\"];"));
+        // One params block shared by all three shaders
+        assert!(dot.contains("label=\"params 0\\n3 shaders\""));
+        assert!(!dot.contains("\"p_1\""));
+    }
+
+    #[test]
+    fn filtered() {
+        let filter = TechniqueFilter {
+            materials: vec![ Glob::new("metal_*").unwrap() ],
+            ..Default::default()
+        };
+        let dot = export_dot(&test_manager(), &filter);
+
+        assert!(!dot.contains("glass"));
+        assert!(!dot.contains("s_0000000000000003"));
+        assert!(!dot.contains("style=filled, fillcolor=\"#f9e2af\""));
+    }
+}
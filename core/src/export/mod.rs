@@ -0,0 +1,3 @@
+pub mod dot;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
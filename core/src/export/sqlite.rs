@@ -0,0 +1,253 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use hashbrown::HashMap;
+use rusqlite::{params, Connection};
+
+use crate::manager::Manager;
+use crate::material::Material;
+use crate::rtti_types::structs::SampleStateInfo;
+
+/// Hashes are stored as hex text, SQLite integers are signed 64-bit
+const SCHEMA: &str = "
+CREATE TABLE materials (
+    id      INTEGER PRIMARY KEY,
+    name    TEXT NOT NULL UNIQUE,
+    hash    TEXT NOT NULL
+);
+
+CREATE TABLE shaders (
+    hash            TEXT PRIMARY KEY,
+    kind            TEXT NOT NULL,
+    size            INTEGER NOT NULL,
+    mat_mod_mask    INTEGER NOT NULL
+);
+
+CREATE TABLE shader_params (
+    shader  TEXT NOT NULL REFERENCES shaders(hash),
+    name    TEXT NOT NULL,
+    kind    TEXT NOT NULL,
+    slot    INTEGER NOT NULL
+);
+
+CREATE TABLE techniques (
+    id              INTEGER PRIMARY KEY,
+    material        INTEGER NOT NULL REFERENCES materials(id),
+    idx             INTEGER NOT NULL,
+    pass            TEXT NOT NULL,
+    pass_index      INTEGER NOT NULL,
+    fallback_index  INTEGER NOT NULL,
+    vertex_factory  TEXT NOT NULL,
+    is_dismembered  INTEGER NOT NULL,
+    is_discarded    INTEGER NOT NULL,
+    is_preskinned   INTEGER NOT NULL,
+    vs_hash         TEXT REFERENCES shaders(hash),
+    ps_hash         TEXT REFERENCES shaders(hash)
+);
+
+CREATE TABLE samplers (
+    technique       INTEGER NOT NULL REFERENCES techniques(id),
+    stage           TEXT NOT NULL,
+    register        INTEGER NOT NULL,
+    filtering_min   TEXT NOT NULL,
+    filtering_mag   TEXT NOT NULL,
+    filtering_mip   TEXT NOT NULL,
+    address_u       TEXT NOT NULL,
+    address_v       TEXT NOT NULL,
+    address_w       TEXT NOT NULL,
+    comparison_func TEXT NOT NULL
+);
+
+CREATE TABLE timestamps (
+    hash        TEXT NOT NULL,
+    material    INTEGER REFERENCES materials(id),
+    timestamp   TEXT NOT NULL
+);
+
+CREATE TABLE includes (
+    path    TEXT NOT NULL,
+    hash    TEXT NOT NULL
+);
+
+CREATE INDEX techniques_material ON techniques(material);
+CREATE INDEX samplers_technique ON samplers(technique);
+CREATE INDEX shader_params_shader ON shader_params(shader);
+";
+
+fn hex64(hash: u64) -> String {
+    format!("{:016X}", hash)
+}
+
+/// Writes the model into a new SQLite database, replacing any existing file
+pub fn export_sqlite(manager: &Manager, path: &Path) -> Result<()> {
+    if path.exists() {
+        fs::remove_file(path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    }
+
+    let mut conn = Connection::open(path)
+        .with_context(|| format!("Failed to create database {}", path.display()))?;
+
+    write(&mut conn, manager)
+}
+
+fn write(conn: &mut Connection, manager: &Manager) -> Result<()> {
+    conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+    conn.execute_batch(SCHEMA)?;
+
+    let tx = conn.transaction()?;
+
+    //--------------------------------------------------------------------------
+    // Shaders
+
+    {
+        let mut shader = tx.prepare("INSERT INTO shaders VALUES (?1, ?2, ?3, ?4)")?;
+        let mut param = tx.prepare("INSERT INTO shader_params VALUES (?1, ?2, ?3, ?4)")?;
+
+        let mut shaders: Vec<_> = manager.shaders.values().collect();
+        shaders.sort_by_key(|s| s.hash);
+
+        for s in shaders {
+            shader.execute(params![hex64(s.hash), s.kind.to_string(), s.compiled.len(), s.mat_mod_mask])?;
+            for p in &s.params {
+                param.execute(params![hex64(s.hash), p.name.as_str(), format!("{:?}", p.kind), p.slot])?;
+            }
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    // Materials and techniques
+
+    // Material name hash -> row id, for timestamps
+    let mut material_ids: HashMap<u32, i64> = HashMap::new();
+
+    {
+        let mut material = tx.prepare("INSERT INTO materials (name, hash) VALUES (?1, ?2)")?;
+        let mut technique = tx.prepare(
+            "INSERT INTO techniques (material, idx, pass, pass_index, fallback_index, vertex_factory,
+                is_dismembered, is_discarded, is_preskinned, vs_hash, ps_hash)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)"
+        )?;
+        let mut sampler = tx.prepare("INSERT INTO samplers VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)")?;
+
+        let mut materials: Vec<(u32, &Material)> = manager.materials.iter()
+            .map(|(k, m)| (k.hash, m.as_ref()))
+            .collect();
+        materials.sort_by(|a, b| a.1.name.cmp(&b.1.name));
+
+        for (hash, m) in materials {
+            material.execute(params![m.name, format!("{:08X}", hash)])?;
+            let material_id = tx.last_insert_rowid();
+            material_ids.insert(hash, material_id);
+
+            for t in &m.techniques {
+                let d = &t.desc;
+                technique.execute(params![
+                    material_id,
+                    d.index,
+                    d.pass.as_str(),
+                    d.pass_index,
+                    d.fallback_index,
+                    d.vertex_factory.to_string(),
+                    d.is_dismembered,
+                    d.is_discarded,
+                    d.is_preskinned,
+                    t.vs.as_ref().map(|s| hex64(s.hash)),
+                    t.ps.as_ref().map(|s| hex64(s.hash)),
+                ])?;
+                let technique_id = tx.last_insert_rowid();
+
+                let stages: [(&str, &Vec<SampleStateInfo>); 2] = [("Vertex", &t.vs_samplers), ("Pixel", &t.ps_samplers)];
+                for (stage, samplers) in stages {
+                    for s in samplers {
+                        sampler.execute(params![
+                            technique_id,
+                            stage,
+                            s.register,
+                            format!("{:?}", s.filteringMin),
+                            format!("{:?}", s.filteringMag),
+                            format!("{:?}", s.filteringMip),
+                            format!("{:?}", s.addressU),
+                            format!("{:?}", s.addressV),
+                            format!("{:?}", s.addressW),
+                            format!("{:?}", s.comparisonFunc),
+                        ])?;
+                    }
+                }
+            }
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    // Timestamps and includes
+
+    {
+        let mut timestamp = tx.prepare("INSERT INTO timestamps VALUES (?1, ?2, ?3)")?;
+        for t in &manager.timestamps {
+            timestamp.execute(params![format!("{:08X}", t.hash), material_ids.get(&t.hash), t.timestamp.to_string()])?;
+        }
+
+        let mut include = tx.prepare("INSERT INTO includes VALUES (?1, ?2)")?;
+        for i in &manager.includes {
+            include.execute(params![i.path.as_str(), hex64(i.hash)])?;
+        }
+    }
+
+    tx.commit()?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::bundle::dyn_cache::*;
+    use crate::material::TechniqueDesc;
+    use crate::renderstage::RenderStage;
+    use crate::rtti_types::cname::CName;
+    use crate::rtti_types::enums::*;
+    use crate::rtti_types::timestamp::TimestampTD;
+    use crate::test_util::{desc, sampler, shader, technique_with, test_cache};
+
+    use super::*;
+
+    /// A transparent glass technique with a PS sampler state, a params chunk with one
+    /// param and a timestamp
+    fn test_manager() -> Manager {
+        let desc = TechniqueDesc { pass: RenderStage::Transparent, ..desc(0) };
+
+        let mut cache = test_cache();
+        cache.shaders[1] = shader(0xFFFF_0000_0000_0002);
+        cache.materials = vec![ MaterialChunk {
+            ps_samplers: vec![ SampleStateInfo { addressU: ETextureAddressing::Border, ..sampler(3) } ],
+            ..technique_with("glass", &desc, 1, 0xFFFF_0000_0000_0002)
+        } ];
+        cache.params[0].params = vec![ ParamChunk { name: CName::new("Color"), value: 0, size: 1 } ];
+        cache.timestamps = vec![ TimestampChunk { hash: CName::new("glass").as_hash32(), timestamp: TimestampTD::default() } ];
+
+        Manager::from_dyn_cache(cache).unwrap()
+    }
+
+    #[test]
+    fn export() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        write(&mut conn, &test_manager()).unwrap();
+
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM samplers s
+             JOIN techniques t ON t.id = s.technique
+             JOIN materials m ON m.id = t.material
+             WHERE s.address_u = 'Border' AND s.register = 3 AND t.pass = 'renderstage_transparent'",
+            [], |r| r.get(0)
+        ).unwrap();
+        assert_eq!(count, 1);
+
+        let ps: String = conn.query_row("SELECT ps_hash FROM techniques", [], |r| r.get(0)).unwrap();
+        assert_eq!(ps, "FFFF000000000002");
+
+        let material: Option<i64> = conn.query_row("SELECT material FROM timestamps", [], |r| r.get(0)).unwrap();
+        assert!(material.is_some());
+
+        let params: i64 = conn.query_row("SELECT COUNT(*) FROM shader_params", [], |r| r.get(0)).unwrap();
+        assert_eq!(params, 2);
+    }
+}
@@ -0,0 +1,174 @@
+use hashbrown::HashSet;
+
+use crate::bundle::dyn_cache::DynamicCacheFile;
+use crate::bundle::encode::encoded_size;
+
+/// Bytes reclaimed and, optionally, the hashes of everything removed
+#[derive(Default)]
+pub struct GcReport {
+    pub shader_bytes: u64,
+    pub param_bytes: u64,
+    pub timestamp_bytes: u64,
+    pub removed_shaders: Vec<u64>,
+    pub removed_params: Vec<u64>,
+    pub removed_timestamps: Vec<u32>,
+    pub shader_count: usize,
+    pub param_count: usize,
+    pub timestamp_count: usize,
+}
+
+impl GcReport {
+    pub fn total_bytes(&self) -> u64 {
+        self.shader_bytes + self.param_bytes + self.timestamp_bytes
+    }
+}
+
+impl std::fmt::Display for GcReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Shaders:    {:>6} removed, {:>10} bytes", self.shader_count, self.shader_bytes)?;
+        writeln!(f, "Params:     {:>6} removed, {:>10} bytes", self.param_count, self.param_bytes)?;
+        writeln!(f, "Timestamps: {:>6} removed, {:>10} bytes", self.timestamp_count, self.timestamp_bytes)?;
+        writeln!(f, "Total:      {:>25} bytes", self.total_bytes())?;
+
+        for hash in &self.removed_shaders {
+            writeln!(f, "  shader    [{:016X}]", hash)?;
+        }
+        for hash in &self.removed_params {
+            writeln!(f, "  params    [{:016X}]", hash)?;
+        }
+        for hash in &self.removed_timestamps {
+            writeln!(f, "  timestamp [{:08X}]", hash)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Removes every shader, params and timestamp chunk that can't be reached from a material.
+///
+/// Materials reference shaders through `vs_hash`/`ps_hash`, shaders reference params
+/// through `ShaderChunk::params`. Timestamps are keyed by the material name hash, the upper
+/// 32 bits of `MaterialChunk::hash`, and are only collected if at least one of them matches
+/// a material so an unexpected keying scheme never empties the section.
+///
+/// With `list` set the hashes of the removed chunks are included in the report.
+pub fn collect_garbage(cache: &mut DynamicCacheFile, list: bool) -> GcReport {
+    let mut report = GcReport::default();
+
+    //--------------------------------------------------------------------------
+    // Shaders
+
+    let used_shaders: HashSet<u64> = cache.materials.iter()
+        .flat_map(|m| [m.vs_hash, m.ps_hash])
+        .filter(|h| *h != 0)
+        .collect();
+
+    cache.shaders.retain(|s| {
+        if used_shaders.contains(&s.hash) {
+            return true;
+        }
+        report.shader_count += 1;
+        report.shader_bytes += encoded_size(s);
+        if list { report.removed_shaders.push(s.hash); }
+        false
+    });
+
+    //--------------------------------------------------------------------------
+    // Params
+
+    let used_params: HashSet<u64> = cache.shaders.iter()
+        .map(|s| s.params)
+        .collect();
+
+    cache.params.retain(|p| {
+        if used_params.contains(&p.hash) {
+            return true;
+        }
+        report.param_count += 1;
+        report.param_bytes += encoded_size(p);
+        if list { report.removed_params.push(p.hash); }
+        false
+    });
+
+    //--------------------------------------------------------------------------
+    // Timestamps
+
+    let used_names: HashSet<u32> = cache.materials.iter()
+        .map(|m| (m.hash >> 32) as u32)
+        .collect();
+
+    if cache.timestamps.iter().any(|t| used_names.contains(&t.hash)) {
+        cache.timestamps.retain(|t| {
+            if used_names.contains(&t.hash) {
+                return true;
+            }
+            report.timestamp_count += 1;
+            report.timestamp_bytes += encoded_size(t);
+            if list { report.removed_timestamps.push(t.hash); }
+            false
+        });
+    }
+
+    report
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::bundle::dyn_cache::*;
+    use crate::rtti_types::cname::CName;
+    use crate::rtti_types::timestamp::TimestampTD;
+    use crate::test_util::{params, shader, test_cache};
+
+    use super::*;
+
+    /// Timestamp key of the shared fixture's material
+    fn name_hash() -> u32 {
+        CName::new("metal_base").as_hash32()
+    }
+
+    /// The shared fixture plus shaders 3 and 4, params 0xB and 0xD, and a timestamp
+    /// that no material uses
+    fn garbage_cache() -> DynamicCacheFile {
+        let timestamp = |hash: u32| TimestampChunk { hash, timestamp: TimestampTD::default() };
+
+        let mut cache = test_cache();
+        cache.shaders.push(ShaderChunk { params: 0xB, ..shader(3) });
+        cache.shaders.push(shader(4));
+        cache.params.extend([ params(0xB), params(0xD) ]);
+        cache.timestamps = vec![ timestamp(name_hash()), timestamp(0x9ABCDEF0) ];
+        cache
+    }
+
+    #[test]
+    fn unreachable_removed() {
+        let mut cache = garbage_cache();
+        let report = collect_garbage(&mut cache, true);
+
+        assert_eq!(cache.shaders.len(), 2);
+        assert_eq!(cache.params.len(), 1);
+        assert_eq!(cache.timestamps.len(), 1);
+
+        assert_eq!(report.removed_shaders, vec![ 3, 4 ]);
+        assert_eq!(report.removed_params, vec![ 0xB, 0xD ]);
+        assert_eq!(report.removed_timestamps, vec![ 0x9ABCDEF0 ]);
+
+        // hash + params + size + blob
+        assert_eq!(report.shader_bytes, 2 * (8 + 8 + 4 + 4));
+        // hash + mask + count
+        assert_eq!(report.param_bytes, 2 * (8 + 4 + 4));
+        assert_eq!(report.timestamp_bytes, 4 + 8);
+    }
+
+    #[test]
+    fn unknown_timestamps_kept() {
+        let mut cache = garbage_cache();
+        cache.timestamps.retain(|t| t.hash != name_hash());
+
+        let report = collect_garbage(&mut cache, false);
+
+        assert_eq!(cache.timestamps.len(), 1);
+        assert_eq!(report.timestamp_count, 0);
+        assert!(report.removed_shaders.is_empty());
+    }
+}
@@ -0,0 +1,92 @@
+use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+
+/// Simple wildcard pattern, `*` matches any run of characters and `?` matches a single character
+#[derive(Clone, Debug)]
+pub struct Glob {
+    pattern: String,
+    re: Regex,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let mut re = String::from("^");
+        for c in pattern.chars() {
+            match c {
+                '*' => re.push_str(".*"),
+                '?' => re.push('.'),
+                _ => re.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        re.push('$');
+
+        Ok(Glob {
+            pattern: String::from(pattern),
+            re: Regex::new(&re)?,
+        })
+    }
+
+    pub fn is_match(&self, s: &str) -> bool {
+        self.re.is_match(s)
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.pattern.as_str()
+    }
+}
+
+impl std::fmt::Display for Glob {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+impl std::str::FromStr for Glob {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Glob::new(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for Glob {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de> {
+        let pattern = String::deserialize(deserializer)?;
+        Glob::new(&pattern).map_err(serde::de::Error::custom)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact() {
+        let glob = Glob::new("metal_base").unwrap();
+        assert!(glob.is_match("metal_base"));
+        assert!(!glob.is_match("metal_base_det"));
+    }
+
+    #[test]
+    fn wildcards() {
+        let glob = Glob::new("metal_*").unwrap();
+        assert!(glob.is_match("metal_base"));
+        assert!(glob.is_match("metal_"));
+        assert!(!glob.is_match("3d_map_solid"));
+
+        let glob = Glob::new("mesh_decal_?").unwrap();
+        assert!(glob.is_match("mesh_decal_1"));
+        assert!(!glob.is_match("mesh_decal_12"));
+    }
+
+    #[test]
+    fn escaped() {
+        let glob = Glob::new("include_hair.fx").unwrap();
+        assert!(glob.is_match("include_hair.fx"));
+        assert!(!glob.is_match("include_hairxfx"));
+    }
+}
@@ -1,7 +1,31 @@
 pub mod rtti_types;
 pub mod bundle;
 pub mod hashmap;
+pub mod glob;
+pub mod container;
+pub mod digest;
+pub mod bitcode;
 
 pub mod shader;
+pub mod signature;
+pub mod reflection;
+pub mod spirv;
+pub mod debuginfo;
 pub mod material;
-pub mod manager;
\ No newline at end of file
+pub mod renderstage;
+pub mod manager;
+pub mod sampler;
+pub mod recipe;
+pub mod gc;
+pub mod optimize;
+pub mod analysis;
+pub mod stats;
+pub mod diff;
+pub mod validate;
+pub mod repair;
+pub mod replace;
+pub mod export;
+pub mod strip;
+
+#[cfg(test)]
+mod test_util;
@@ -1,10 +1,12 @@
 use std::rc::Rc;
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use mut_rc::MutRc;
 
 use crate::hashmap::{CNameHashMap32, CNameHashMap64, CNameKey32, CNameKey64};
-use crate::bundle::dyn_cache::{DynamicCacheFile, ParamsChunk};
+use crate::bundle::dyn_cache::{ChunkOrder, DynamicCacheFile, IncludesChecksumChunk, InfoBlock, MaterialChunk, ParamChunk, ParamsChunk, ShaderChunk, TimestampChunk};
+use crate::replace::{self, ReplaceReport, ReplaceTarget};
 
+use crate::rtti_types::cname::CName;
 use crate::material::{Material, Technique, TechniqueDesc};
 use crate::shader::{Shader, ShaderParam, ShaderParamType, ShaderType};
 
@@ -12,6 +14,11 @@ use crate::shader::{Shader, ShaderParam, ShaderParamType, ShaderType};
 pub struct Manager {
     pub materials: CNameHashMap32<Rc<Material>>,
     pub shaders: CNameHashMap64<Rc<Shader>>,
+    /// Keyed by the material name hash
+    pub timestamps: Vec<TimestampChunk>,
+    pub includes: Vec<IncludesChecksumChunk>,
+    /// Footer of the loaded cache, its timestamp and unknown hash are written back
+    pub info: InfoBlock,
 }
 
 impl Manager {
@@ -20,7 +27,12 @@ impl Manager {
         if hash != 0 {
             let s = shaders.get::<CNameKey64>(&hash.into())
                     .expect(format!("Missing {} shader for hash {:016x}", kind.to_string(), hash).as_str());
-            let _ = s.with_mut(|s| { s.kind = kind; });
+            // Stages read from the blob win, conflicts are reported by `validate`
+            let _ = s.with_mut(|s| {
+                if s.kind == ShaderType::Unknown {
+                    s.kind = kind;
+                }
+            });
             Some(s.finalize().unwrap())
         }
         else {
@@ -28,6 +40,79 @@ impl Manager {
         }
     }
 
+    /// Mutable access to a material, cloning it first if it is shared
+    pub fn material_mut(&mut self, name: &str) -> Option<&mut Material> {
+        self.materials
+            .get_mut(&CNameKey32::from(CName::new(name)))
+            .map(Rc::make_mut)
+    }
+
+    /// Swaps the compiled VS or PS of one technique, as `DynamicCacheFile::replace_shader`.
+    /// Techniques sharing the original keep it, the copy goes to `shaders` under a new hash.
+    pub fn replace_shader(&mut self, material: &str, desc: &TechniqueDesc, stage: ShaderType, compiled: Vec<u8>, force: bool) -> Result<ReplaceReport> {
+        let technique = self.materials.get(&CNameKey32::from(CName::new(material)))
+            .ok_or_else(|| anyhow!("Material {} does not exist", material))?
+            .find_technique(desc)
+            .ok_or_else(|| anyhow!("Material {} has no technique {}", material, desc))?;
+
+        let (original, other) = match stage {
+            ShaderType::Vertex => (&technique.vs, &technique.ps),
+            ShaderType::Pixel => (&technique.ps, &technique.vs),
+            _ => bail!("Only Vertex and Pixel shaders can be replaced, not {}", stage),
+        };
+        let original = original.clone()
+            .ok_or_else(|| anyhow!("Technique {} of {} has no {} shader", desc, material, stage))?;
+
+        let techniques = || self.materials.values().flat_map(|m| m.techniques.iter());
+        let vertex_shaders: Vec<&[u8]> = match stage {
+            ShaderType::Vertex => techniques()
+                .filter(|t| t.desc.vertex_factory == desc.vertex_factory)
+                .filter_map(|t| t.vs.as_ref().map(|s| s.compiled.as_slice()))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let target = ReplaceTarget {
+            stage,
+            original: &original.compiled,
+            counterpart: other.as_ref().map(|s| s.compiled.as_slice()),
+            vertex_shaders,
+        };
+        let findings = replace::check_replacement(&target, &compiled);
+        replace::refuse(&findings, force)?;
+
+        let users = techniques()
+            .flat_map(|t| [&t.vs, &t.ps])
+            .filter(|s| s.as_ref().is_some_and(|s| s.hash == original.hash))
+            .count();
+        let copied = users > 1;
+        let hash = if copied {
+            replace::copy_hash(&compiled, |h| self.shaders.contains_key(&CNameKey64::from(h)))
+        }
+        else {
+            original.hash
+        };
+
+        let mut shader: Shader = ShaderChunk { hash, params: original.params_hash, compiled }.into();
+        shader.mat_mod_mask = original.mat_mod_mask;
+        shader.params = original.params.clone();
+        if shader.kind == ShaderType::Unknown {
+            shader.kind = stage;
+        }
+        let shader = Rc::new(shader);
+        self.shaders.insert(hash.into(), Rc::clone(&shader));
+
+        let technique = self.material_mut(material)
+            .and_then(|m| m.find_technique_mut(desc))
+            .ok_or_else(|| anyhow!("Material {} has no technique {}", material, desc))?;
+        match stage {
+            ShaderType::Vertex => technique.vs = Some(shader),
+            _ => technique.ps = Some(shader),
+        }
+
+        Ok(ReplaceReport { hash, copied, findings })
+    }
+
     pub fn from_dyn_cache(cache: DynamicCacheFile) -> Result<Manager> {
         let mut materials: CNameHashMap32<MutRc<Material>> = CNameHashMap32::default();
         let mut shaders: CNameHashMap64<MutRc<Shader>> = CNameHashMap64::default();
@@ -79,18 +164,133 @@ impl Manager {
                 ps: Manager::finalize_shader_type(&shaders, m.ps_hash, ShaderType::Pixel),
                 vs_samplers: m.vs_samplers,
                 ps_samplers: m.ps_samplers,
+                timestamp: m.timestamp,
             };
 
             _ = materials.get_mut(&mat_key).unwrap().with_mut(|m| { m.techniques.push(tech); });
         }
 
         for (_, v) in &materials {
-            _ = v.with_mut(|m| { m.techniques.sort_by(|a,b| a.desc.partial_cmp(&b.desc).unwrap()); });
+            _ = v.with_mut(|m| { m.sort_techniques(); });
         }
 
         Ok(Manager {
             materials: materials.into_iter().map(|(k,v)| (k, v.finalize().unwrap())).collect(),
             shaders: shaders.into_iter().map(|(k,v)| (k, v.finalize().unwrap())).collect(),
+            timestamps: cache.timestamps,
+            includes: cache.includes,
+            info: cache.info,
         })
     }
+
+    /// Flattens back into chunks, the inverse of `from_dyn_cache`.
+    ///
+    /// Shaders keep the params hash they were loaded with. When shaders sharing a hash no
+    /// longer have the same params, the first by hash keeps it and the others get a derived
+    /// one, shared by equal layouts. Chunks are ordered by hash, the footer layout is
+    /// recomputed on save.
+    pub fn to_dyn_cache(&self) -> DynamicCacheFile {
+        let mut cache = DynamicCacheFile {
+            info: self.info.clone(),
+            shaders: Vec::new(),
+            materials: Vec::new(),
+            params: Vec::new(),
+            timestamps: self.timestamps.clone(),
+            includes: self.includes.clone(),
+        };
+
+        let mut shaders: Vec<&Rc<Shader>> = self.shaders.values().collect();
+        shaders.sort_by_key(|s| s.hash);
+        for s in shaders {
+            let chunk = ParamsChunk {
+                hash: s.params_hash,
+                mat_mod_mask: s.mat_mod_mask,
+                params: s.params.iter().map(ParamChunk::from).collect(),
+            };
+
+            let params = match cache.params.iter().find(|p| p.hash == chunk.hash) {
+                Some(p) if p.same_layout(&chunk) => p.hash,
+                None if chunk.hash != 0 => {
+                    cache.params.push(chunk);
+                    s.params_hash
+                },
+                _ => {
+                    let chunk = ParamsChunk::new(chunk.mat_mod_mask, chunk.params);
+                    match cache.params.iter().find(|p| p.same_layout(&chunk)) {
+                        Some(p) => p.hash,
+                        None => {
+                            let hash = chunk.hash;
+                            cache.params.push(chunk);
+                            hash
+                        },
+                    }
+                },
+            };
+
+            cache.shaders.push(ShaderChunk { hash: s.hash, params, compiled: s.compiled.clone() });
+        }
+
+        for m in self.materials.values() {
+            for t in &m.techniques {
+                cache.materials.push(MaterialChunk {
+                    hash: t.desc.encode_material_hash(&m.name),
+                    name: CName::new(&t.desc.encode_material_name(&m.name)),
+                    vs_hash: t.vs.as_ref().map_or(0, |s| s.hash),
+                    ps_hash: t.ps.as_ref().map_or(0, |s| s.hash),
+                    timestamp: t.timestamp,
+                    vs_samplers: t.vs_samplers.clone(),
+                    ps_samplers: t.ps_samplers.clone(),
+                });
+            }
+        }
+
+        cache.sort_chunks(ChunkOrder::Hash);
+        cache
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::bundle::dyn_cache::SaveOptions;
+    use crate::shader::ShaderParamType;
+    use crate::test_util::test_cache;
+
+    use super::*;
+
+    fn saved(cache: &mut DynamicCacheFile) -> Vec<u8> {
+        let options = SaveOptions { order: ChunkOrder::Hash, ..Default::default() };
+        let mut output = Cursor::new(Vec::new());
+        cache.save_with(&mut output, &options).unwrap();
+        output.into_inner()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut cache = test_cache();
+        cache.info.unknown_hash = 0x1234;
+        let original = saved(&mut cache.clone());
+
+        let manager = Manager::from_dyn_cache(cache).unwrap();
+        let mut converted = manager.to_dyn_cache();
+        assert_eq!(converted.info.unknown_hash, 0x1234);
+        assert_eq!(saved(&mut converted), original);
+    }
+
+    #[test]
+    fn edited_params() {
+        let mut manager = Manager::from_dyn_cache(test_cache()).unwrap();
+
+        // Split off the params shared with shader 1
+        let shader = manager.shaders.get_mut(&CNameKey64::from(2)).map(Rc::make_mut).unwrap();
+        shader.params.push(ShaderParam { name: CName::new("HitProxy"), kind: ShaderParamType::Matrix, slot: 3 });
+
+        let cache = manager.to_dyn_cache();
+        let edited = cache.shader_params(2).unwrap();
+        assert_ne!(edited.hash, 0xA);
+        assert_eq!(edited.param_count(), 1);
+        assert_eq!(cache.shader_params(1).unwrap().hash, 0xA);
+    }
 }
\ No newline at end of file
@@ -1,12 +1,17 @@
 use std::rc::Rc;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use fnv_rs::{Fnv32, FnvHasher};
 use regex::Regex;
 use once_cell::sync::Lazy;
+use serde::Deserialize;
 
+use crate::glob::Glob;
+use crate::renderstage::RenderStage;
+use crate::rtti_types::cname::CName;
 use crate::rtti_types::enums::EMaterialVertexFactory;
 use crate::rtti_types::structs::SampleStateInfo;
+use crate::rtti_types::timestamp::TimestampTD;
 
 use crate::shader::Shader;
 
@@ -16,6 +21,79 @@ pub struct Material {
     pub techniques: Vec<Technique>,
 }
 
+impl Material {
+    /// CName hash of the material name, upper 32 bits of every technique hash
+    pub fn hash(&self) -> u32 {
+        CName::new(&self.name).as_hash32()
+    }
+
+    pub fn technique_hash(&self, desc: &TechniqueDesc) -> u64 {
+        desc.encode_material_hash(&self.name)
+    }
+
+    pub fn technique_name(&self, desc: &TechniqueDesc) -> String {
+        desc.encode_material_name(&self.name)
+    }
+
+    pub fn find_technique(&self, desc: &TechniqueDesc) -> Option<&Technique> {
+        self.techniques.iter().find(|t| t.desc.is_same_technique(desc))
+    }
+
+    pub fn find_technique_mut(&mut self, desc: &TechniqueDesc) -> Option<&mut Technique> {
+        self.techniques.iter_mut().find(|t| t.desc.is_same_technique(desc))
+    }
+
+    /// Adds a new technique by copying the shaders and samplers of an existing one
+    pub fn add_technique(&mut self, from: &TechniqueDesc, to: TechniqueDesc) -> Result<()> {
+        if self.find_technique(&to).is_some() {
+            bail!("Material {} already has technique {}", self.name, to);
+        }
+
+        let mut tech = self.find_technique(from)
+            .ok_or_else(|| anyhow!("Material {} has no technique {}", self.name, from))?
+            .clone();
+        tech.desc = to;
+
+        self.techniques.push(tech);
+        self.sort_techniques();
+
+        Ok(())
+    }
+
+    pub fn remove_technique(&mut self, desc: &TechniqueDesc) -> Option<Technique> {
+        let pos = self.techniques.iter().position(|t| t.desc.is_same_technique(desc))?;
+        Some(self.techniques.remove(pos))
+    }
+
+    /// Removes every technique matching the predicate, returning how many were removed
+    pub fn remove_techniques<F: FnMut(&Technique) -> bool>(&mut self, mut f: F) -> usize {
+        let count = self.techniques.len();
+        self.techniques.retain(|t| !f(t));
+        count - self.techniques.len()
+    }
+
+    /// Replaces the description of an existing technique, e.g. to change the pass,
+    /// pass index, fallback index or vertex factory
+    pub fn retarget_technique(&mut self, from: &TechniqueDesc, to: TechniqueDesc) -> Result<()> {
+        if !from.is_same_technique(&to) && self.find_technique(&to).is_some() {
+            bail!("Material {} already has technique {}", self.name, to);
+        }
+
+        let name = self.name.clone();
+        let tech = self.find_technique_mut(from)
+            .ok_or_else(|| anyhow!("Material {} has no technique {}", name, from))?;
+        tech.desc = to;
+
+        self.sort_techniques();
+
+        Ok(())
+    }
+
+    pub fn sort_techniques(&mut self) {
+        self.techniques.sort_by(|a,b| a.desc.partial_cmp(&b.desc).unwrap());
+    }
+}
+
 #[derive(Clone)]
 pub struct Technique {
     pub desc: TechniqueDesc,
@@ -23,6 +101,8 @@ pub struct Technique {
     pub ps: Option<Rc<Shader>>,
     pub vs_samplers: Vec<SampleStateInfo>,
     pub ps_samplers: Vec<SampleStateInfo>,
+    /// From the material chunk, kept for writing it back
+    pub timestamp: TimestampTD,
 }
 
 impl PartialEq for Technique {
@@ -41,7 +121,7 @@ impl PartialOrd for Technique {
 #[derive(PartialEq, Clone)]
 pub struct TechniqueDesc {
     pub index: u32,
-    pub pass: String,
+    pub pass: RenderStage,
     pub pass_index: u8,
     pub fallback_index: u8,
     pub vertex_factory: EMaterialVertexFactory,
@@ -57,6 +137,11 @@ impl TechniqueDesc {
 
     fn has_flag(val: u32, flag: u32) -> bool { val & flag == flag }
 
+    /// Techniques are identified by their hash, which excludes the fallback index
+    pub fn is_same_technique(&self, other: &TechniqueDesc) -> bool {
+        self.encode_hash() == other.encode_hash()
+    }
+
     pub fn decode_vf_id(&mut self, id: u32) -> Result<()> {
         let factory_id: u8 = (id >> 3).try_into()?;
         self.vertex_factory = EMaterialVertexFactory::try_from(factory_id)?;
@@ -82,12 +167,22 @@ impl TechniqueDesc {
         
         hasher.update(&self.encode_vf_id().to_le_bytes());
         hasher.update(&self.index.to_le_bytes());
-        hasher.update(&self.pass.as_bytes());
+        hasher.update(self.pass.as_str().as_bytes());
         hasher.update(&self.pass_index.to_le_bytes());
 
         hasher.into()
     }
 
+    /// Composite of the material name and technique hashes, as stored in `MaterialChunk::hash`
+    pub fn encode_material_hash(&self, material: &str) -> u64 {
+        (u64::from(CName::new(material).as_hash32()) << 32) | u64::from(self.encode_hash())
+    }
+
+    /// Material name followed by the technique string, as stored in `MaterialChunk::name`
+    pub fn encode_material_name(&self, material: &str) -> String {
+        format!("{} {}", material, self.encode_string())
+    }
+
     pub fn encode_string(&self) -> String {
         let mut flag_str: String = String::from("");
         if self.is_discarded { flag_str += "; Discarded"; }
@@ -113,11 +208,12 @@ impl TechniqueDesc {
             ).unwrap()
         });
 
-        let tokens = TECH_DESC_RE.captures(input.as_str()).unwrap();
-        
+        let tokens = TECH_DESC_RE.captures(input.as_str())
+            .ok_or_else(|| anyhow!("Invalid technique description {}", input))?;
+
         Ok(TechniqueDesc {
             index: tokens["index"].parse()?,
-            pass: tokens["pass"].to_string(),
+            pass: RenderStage::from(&tokens["pass"]),
             pass_index: tokens["pass_idx"].parse()?,
             fallback_index: tokens["fallback"].parse()?,
             vertex_factory: tokens["vf"].try_into()?,
@@ -139,6 +235,41 @@ impl PartialOrd for TechniqueDesc {
     }
 }
 
+/// Selects material techniques by name and description.
+/// Every matcher is a list of accepted values, an empty list accepts anything.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TechniqueFilter {
+    pub materials: Vec<Glob>,
+    pub passes: Vec<RenderStage>,
+    pub vertex_factories: Vec<EMaterialVertexFactory>,
+}
+
+impl TechniqueFilter {
+    /// True if the filter accepts every technique
+    pub fn is_empty(&self) -> bool {
+        self.materials.is_empty() && !self.needs_desc()
+    }
+
+    /// True if matching requires the decoded `TechniqueDesc`
+    pub fn needs_desc(&self) -> bool {
+        !self.passes.is_empty() || !self.vertex_factories.is_empty()
+    }
+
+    pub fn matches_material(&self, name: &str) -> bool {
+        self.materials.is_empty() || self.materials.iter().any(|g| g.is_match(name))
+    }
+
+    pub fn matches_desc(&self, desc: &TechniqueDesc) -> bool {
+        (self.passes.is_empty() || self.passes.contains(&desc.pass))
+        && (self.vertex_factories.is_empty() || self.vertex_factories.contains(&desc.vertex_factory))
+    }
+
+    pub fn matches(&self, material: &str, desc: &TechniqueDesc) -> bool {
+        self.matches_material(material) && self.matches_desc(desc)
+    }
+}
+
 impl std::fmt::Display for TechniqueDesc {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.encode_string())
@@ -148,14 +279,39 @@ impl std::fmt::Display for TechniqueDesc {
 
 #[cfg(test)]
 mod tests {
+    use crate::test_util::desc;
+
     use super::*;
 
+    fn test_desc(pass: &str, vertex_factory: EMaterialVertexFactory) -> TechniqueDesc {
+        TechniqueDesc { pass: RenderStage::from(pass), vertex_factory, ..desc(0) }
+    }
+
+    fn test_material() -> Material {
+        let tech = |desc: TechniqueDesc| Technique {
+            desc,
+            vs: None,
+            ps: None,
+            vs_samplers: Vec::new(),
+            ps_samplers: Vec::new(),
+            timestamp: TimestampTD::default(),
+        };
+
+        Material {
+            name: String::from("3d_map_solid"),
+            techniques: vec![
+                tech(test_desc("renderstage_gbuffer_regular", EMaterialVertexFactory::MeshStatic)),
+                tech(test_desc("renderstage_depth_prepass", EMaterialVertexFactory::MeshStatic)),
+            ]
+        }
+    }
+
     #[test]
     fn encode_vf_id() {
         // [ID: 229, VF: MeshExtSkinnedLightBlockers; PreSkinned; Dismembered]
         let tech = TechniqueDesc {
             index: 0,
-            pass: String::from(""),
+            pass: RenderStage::from(""),
             pass_index: 0,
             fallback_index: 0,
             vertex_factory: EMaterialVertexFactory::MeshExtSkinnedLightBlockers,
@@ -172,7 +328,7 @@ mod tests {
         // CompiledTechnique [Index: 3, Pass 'renderstage_skin_translucency', PassIndex: 1, Fallback: 0, RenderStageContext: [ID: 222, VF: MeshSkinnedLightBlockers; Discarded; Dismembered]
         let tech = TechniqueDesc {
             index: 3,
-            pass: String::from("renderstage_skin_translucency"),
+            pass: RenderStage::SkinTranslucency,
             pass_index: 1,
             fallback_index: 0,
             vertex_factory: EMaterialVertexFactory::MeshSkinnedLightBlockers,
@@ -189,7 +345,7 @@ mod tests {
         let known = "CompiledTechnique [Index: 3, Pass 'renderstage_highlights', PassIndex: 0, Fallback: 0, RenderStageContext: [ID: 55, VF: GarmentMeshExtSkinned; Discarded; PreSkinned; Dismembered]";
         let tech = TechniqueDesc {
             index: 3,
-            pass: String::from("renderstage_highlights"),
+            pass: RenderStage::Highlights,
             pass_index: 0,
             fallback_index: 0,
             vertex_factory: EMaterialVertexFactory::GarmentMeshExtSkinned,
@@ -200,6 +356,68 @@ mod tests {
         assert_eq!(tech.encode_string().as_str(), known);
     }
 
+    #[test]
+    fn technique_hash() {
+        let mat = test_material();
+
+        // CompiledTechnique [Index: 3, Pass 'renderstage_skin_translucency', PassIndex: 1, Fallback: 0, RenderStageContext: [ID: 222, VF: MeshSkinnedLightBlockers; Discarded; Dismembered]
+        let mut desc = test_desc("renderstage_skin_translucency", EMaterialVertexFactory::MeshSkinnedLightBlockers);
+        desc.index = 3;
+        desc.pass_index = 1;
+        desc.is_dismembered = true;
+        desc.is_discarded = true;
+
+        assert_eq!(mat.technique_hash(&desc), 0x39E2B855_1FD96A39);
+    }
+
+    #[test]
+    fn add_technique() {
+        let mut mat = test_material();
+        let from = test_desc("renderstage_gbuffer_regular", EMaterialVertexFactory::MeshStatic);
+        let to = test_desc("renderstage_gbuffer_regular", EMaterialVertexFactory::MeshSkinnedVehicle);
+
+        mat.add_technique(&from, to.clone()).unwrap();
+
+        assert_eq!(mat.techniques.len(), 3);
+        assert!(mat.find_technique(&to).is_some());
+        // Sorted by VF ID, so the new technique is last
+        assert!(mat.techniques[2].desc == to);
+        // Adding the same technique twice fails
+        assert!(mat.add_technique(&from, to).is_err());
+    }
+
+    #[test]
+    fn remove_technique() {
+        let mut mat = test_material();
+        let desc = test_desc("renderstage_depth_prepass", EMaterialVertexFactory::MeshStatic);
+
+        assert!(mat.remove_technique(&desc).is_some());
+        assert!(mat.remove_technique(&desc).is_none());
+        assert_eq!(mat.techniques.len(), 1);
+    }
+
+    #[test]
+    fn retarget_technique() {
+        let mut mat = test_material();
+        let from = test_desc("renderstage_depth_prepass", EMaterialVertexFactory::MeshStatic);
+
+        // Fallback index is not part of the technique identity
+        let mut to = from.clone();
+        to.fallback_index = 2;
+        mat.retarget_technique(&from, to.clone()).unwrap();
+        assert_eq!(mat.find_technique(&from).unwrap().desc.fallback_index, 2);
+
+        // Retargeting onto an existing technique fails
+        let taken = test_desc("renderstage_gbuffer_regular", EMaterialVertexFactory::MeshStatic);
+        assert!(mat.retarget_technique(&to, taken).is_err());
+
+        let mut moved = to.clone();
+        moved.pass_index = 1;
+        mat.retarget_technique(&to, moved.clone()).unwrap();
+        assert!(mat.find_technique(&moved).is_some());
+        assert!(mat.find_technique(&to).is_none());
+    }
+
     #[test]
     fn decode_string() {
         let known = "CompiledTechnique [Index: 3, Pass 'renderstage_skin_translucency', PassIndex: 1, Fallback: 0, RenderStageContext: [ID: 245, VF: GarmentMeshExtSkinnedLightBlockers; PreSkinned; Dismembered]";
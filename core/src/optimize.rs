@@ -0,0 +1,192 @@
+use fnv_rs::{Fnv128, FnvHasher};
+use hashbrown::HashMap;
+
+use crate::bundle::dyn_cache::{DynamicCacheFile, ParamsChunk, ShaderChunk};
+use crate::bundle::encode::encoded_size;
+
+/// Result of merging duplicate shaders
+#[derive(Default)]
+pub struct OptimizeReport {
+    /// Removed shader hash and the hash of the identical shader it was merged into
+    pub merged: Vec<(u64, u64)>,
+    /// Number of `vs_hash`/`ps_hash` references updated
+    pub retargeted: usize,
+    pub bytes_saved: u64,
+}
+
+impl OptimizeReport {
+    pub fn shaders_merged(&self) -> usize {
+        self.merged.len()
+    }
+}
+
+impl std::fmt::Display for OptimizeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Shaders merged:      {}", self.shaders_merged())?;
+        writeln!(f, "References updated:  {}", self.retargeted)?;
+        writeln!(f, "Bytes saved:         {}", self.bytes_saved)
+    }
+}
+
+/// Cheap key used to find candidate duplicates, equal keys still need a full comparison
+#[derive(PartialEq, Eq, Hash)]
+struct BlobKey {
+    size: usize,
+    hash: u128,
+}
+
+impl From<&ShaderChunk> for BlobKey {
+    fn from(value: &ShaderChunk) -> Self {
+        let mut hasher = Fnv128::new();
+        hasher.update(&value.compiled);
+
+        BlobKey {
+            size: value.compiled.len(),
+            hash: hasher.into(),
+        }
+    }
+}
+
+fn same_params(params: &HashMap<u64, &ParamsChunk>, a: u64, b: u64) -> bool {
+    if a == b {
+        return true;
+    }
+    match (params.get(&a), params.get(&b)) {
+        (Some(a), Some(b)) => a.same_layout(b),
+        _ => false,
+    }
+}
+
+/// Finds shaders with byte-identical blobs and identical param layouts.
+///
+/// Returns the index of each duplicate paired with the index of the first shader,
+/// in file order, that it duplicates.
+pub fn find_duplicates(cache: &DynamicCacheFile) -> Vec<(usize, usize)> {
+    let params: HashMap<u64, &ParamsChunk> = cache.params.iter().map(|p| (p.hash, p)).collect();
+
+    // Indices of the distinct shaders for each candidate key
+    let mut buckets: HashMap<BlobKey, Vec<usize>> = HashMap::new();
+    let mut dupes: Vec<(usize, usize)> = Vec::new();
+
+    for (i, s) in cache.shaders.iter().enumerate() {
+        let bucket = buckets.entry(BlobKey::from(s)).or_default();
+
+        let original = bucket.iter()
+            .copied()
+            .find(|&k| {
+                let k = &cache.shaders[k];
+                k.compiled == s.compiled && same_params(&params, k.params, s.params)
+            });
+
+        match original {
+            Some(k) => dupes.push((i, k)),
+            None => bucket.push(i),
+        }
+    }
+
+    dupes
+}
+
+/// Merges shaders with byte-identical blobs and identical param layouts.
+///
+/// The first shader in file order is kept and every material technique referencing
+/// one of its duplicates is pointed at it instead.
+pub fn dedup_shaders(cache: &mut DynamicCacheFile) -> OptimizeReport {
+    let mut report = OptimizeReport::default();
+
+    let dupes = find_duplicates(cache);
+    if dupes.is_empty() {
+        return report;
+    }
+
+    // Duplicate hash -> kept hash
+    let mut remap: HashMap<u64, u64> = HashMap::new();
+    let mut kept: Vec<bool> = vec![true; cache.shaders.len()];
+
+    for (i, k) in dupes {
+        let (dupe, original) = (&cache.shaders[i], &cache.shaders[k]);
+
+        // The same hash stored twice is dropped without retargeting
+        if dupe.hash != original.hash {
+            remap.insert(dupe.hash, original.hash);
+            report.merged.push((dupe.hash, original.hash));
+        }
+        report.bytes_saved += encoded_size(dupe);
+        kept[i] = false;
+    }
+
+    // Keep only the first shader for each distinct blob, preserving file order
+    let mut kept = kept.into_iter();
+    cache.shaders.retain(|_| kept.next().unwrap());
+
+    for m in cache.materials.iter_mut() {
+        if let Some(&hash) = remap.get(&m.vs_hash) {
+            m.vs_hash = hash;
+            report.retargeted += 1;
+        }
+        if let Some(&hash) = remap.get(&m.ps_hash) {
+            m.ps_hash = hash;
+            report.retargeted += 1;
+        }
+    }
+
+    report
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::bundle::dyn_cache::*;
+    use crate::test_util::{params, shader, technique, test_cache};
+
+    use super::*;
+
+    /// The shared fixture plus copies of its shaders, used by two more techniques
+    fn duplicate_cache() -> DynamicCacheFile {
+        let mut cache = test_cache();
+        cache.shaders.extend([
+            // Duplicate of 1
+            ShaderChunk { compiled: shader(1).compiled, ..shader(3) },
+            // Duplicate of 2 with a different params hash but the same layout
+            ShaderChunk { params: 0xB, compiled: shader(2).compiled, ..shader(4) },
+            // Same bytes as 2 but different params
+            ShaderChunk { params: 0xC, compiled: shader(2).compiled, ..shader(5) },
+        ]);
+        cache.materials.extend([
+            technique("metal_base", 2, 3, 4),
+            technique("metal_base", 3, 3, 5),
+        ]);
+        cache.params.extend([ params(0xB), ParamsChunk { mat_mod_mask: 2, ..params(0xC) } ]);
+        cache
+    }
+
+    #[test]
+    fn merges_duplicates() {
+        let mut cache = duplicate_cache();
+        let report = dedup_shaders(&mut cache);
+
+        assert_eq!(report.merged, vec![ (3, 1), (4, 2) ]);
+        assert_eq!(report.retargeted, 3);
+        assert_eq!(report.bytes_saved, 2 * (8 + 8 + 4 + 4));
+
+        let hashes: Vec<u64> = cache.shaders.iter().map(|s| s.hash).collect();
+        assert_eq!(hashes, vec![ 1, 2, 5 ]);
+
+        // Every material sharing a duplicate is retargeted, not just one
+        assert_eq!((cache.materials[2].vs_hash, cache.materials[2].ps_hash), (1, 2));
+        assert_eq!((cache.materials[3].vs_hash, cache.materials[3].ps_hash), (1, 5));
+    }
+
+    #[test]
+    fn no_duplicates() {
+        let mut cache = duplicate_cache();
+        cache.shaders.truncate(2);
+        cache.materials.truncate(2);
+
+        let report = dedup_shaders(&mut cache);
+
+        assert_eq!(report.shaders_merged(), 0);
+        assert_eq!(report.bytes_saved, 0);
+        assert_eq!(cache.shaders.len(), 2);
+    }
+}
@@ -0,0 +1,752 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use std::rc::Rc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use hashbrown::HashMap;
+use serde::{Deserialize, Deserializer};
+
+use crate::bundle::dyn_cache::{ChunkOrder, DynamicCacheFile, SaveOptions};
+use crate::hashmap::{CNameKey32, CNameKey64};
+use crate::manager::Manager;
+use crate::material::{Material, Technique, TechniqueDesc, TechniqueFilter};
+use crate::renderstage::{check_passes, unknown_manager_stages, unknown_stages, RenderStage};
+use crate::rtti_types::cname::CName;
+use crate::sampler::{apply_sampler_rules, SamplerRule};
+use crate::shader::{Shader, ShaderType};
+
+/// An ordered list of edits applied to a shader cache, loaded from TOML
+///
+/// ```toml
+/// name = "Better filtering"
+///
+/// [[op]]
+/// type = "sampler_override"
+/// filtering_min = [ "Linear", "AnisotropicLow" ]
+/// set = { filtering_min = "Anisotropic" }
+///
+/// [[op]]
+/// type = "sampler_override"
+/// all = true
+/// set = { address_w = "Clamp" }
+///
+/// [[op]]
+/// type = "delete_techniques"
+/// passes = [ "renderstage_wireframe" ]
+/// ```
+#[derive(Deserialize)]
+pub struct Recipe {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Chunk order used when saving the result
+    #[serde(default)]
+    pub order: ChunkOrder,
+    #[serde(rename = "op", default)]
+    pub ops: Vec<Operation>,
+    /// Relative paths in operations are resolved against this
+    #[serde(skip)]
+    pub base_dir: PathBuf,
+}
+
+/// Operations with a technique filter refuse to run when the filter is empty,
+/// unless they set `all = true`. Unknown keys are ignored by the flattened
+/// filters, so a misspelt matcher would otherwise select the whole cache.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Operation {
+    /// Replaces the VS or PS of the matched techniques, see `DynamicCacheFile::replace_shader`.
+    /// Shaders shared with unmatched techniques are copied, and incompatible blobs are
+    /// refused unless `force` is set.
+    ReplaceShader {
+        #[serde(flatten)]
+        filter: TechniqueFilter,
+        #[serde(default)]
+        all: bool,
+        stage: ShaderType,
+        file: PathBuf,
+        #[serde(default)]
+        force: bool,
+    },
+    /// Points the matched techniques at different, existing, shaders
+    SwapShaders {
+        #[serde(flatten)]
+        filter: TechniqueFilter,
+        #[serde(default)]
+        all: bool,
+        #[serde(default, deserialize_with = "de_hash")]
+        vs_hash: Option<u64>,
+        #[serde(default, deserialize_with = "de_hash")]
+        ps_hash: Option<u64>,
+    },
+    SamplerOverride {
+        #[serde(flatten)]
+        rule: SamplerRule,
+        #[serde(default)]
+        all: bool,
+    },
+    DeleteTechniques {
+        #[serde(flatten)]
+        filter: TechniqueFilter,
+        #[serde(default)]
+        all: bool,
+    },
+    CloneMaterial {
+        from: String,
+        to: String,
+    },
+}
+
+/// Hashes are written as hex strings, as TOML integers are signed 64-bit
+fn de_hash<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+    where
+        D: Deserializer<'de> {
+    let s = String::deserialize(deserializer)?;
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+    u64::from_str_radix(digits, 16)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn read_shader(base_dir: &Path, file: &Path) -> Result<Vec<u8>> {
+    let path = base_dir.join(file);
+    fs::read(&path).with_context(|| format!("Failed to read shader {}", path.display()))
+}
+
+fn replaceable(stage: ShaderType) -> Result<()> {
+    match stage {
+        ShaderType::Vertex | ShaderType::Pixel => Ok(()),
+        _ => bail!("Only Vertex and Pixel shaders can be replaced, not {}", stage),
+    }
+}
+
+/// Hashes the replacements of each original shader should be merged into.
+///
+/// Every technique is replaced on its own so it's checked against its own counterpart,
+/// which leaves one identical copy per technique sharing an original. They all keep
+/// the original's params, so they collapse into the original if it was replaced in
+/// place, or else into the first copy.
+fn merge_copies(replaced: &HashMap<u64, Vec<u64>>) -> HashMap<u64, u64> {
+    let mut merge: HashMap<u64, u64> = HashMap::new();
+    for (original, hashes) in replaced {
+        let keep = if hashes.contains(original) { *original } else { hashes[0] };
+        merge.extend(hashes.iter().filter(|h| **h != keep).map(|h| (*h, keep)));
+    }
+    merge
+}
+
+impl Operation {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Operation::ReplaceShader { .. }    => "replace_shader",
+            Operation::SwapShaders { .. }      => "swap_shaders",
+            Operation::SamplerOverride { .. }  => "sampler_override",
+            Operation::DeleteTechniques { .. } => "delete_techniques",
+            Operation::CloneMaterial { .. }    => "clone_material",
+        }
+    }
+
+    /// Passes the operation filters on, checked against typos before applying
+    fn passes(&self) -> &[RenderStage] {
+        match self {
+            Operation::ReplaceShader { filter, .. }
+            | Operation::SwapShaders { filter, .. }
+            | Operation::DeleteTechniques { filter, .. } => &filter.passes,
+            Operation::SamplerOverride { rule, .. } => &rule.filter.technique.passes,
+            Operation::CloneMaterial { .. } => &[],
+        }
+    }
+
+    /// Fails if the operation would match everything without asking for it
+    fn check_filter(&self) -> Result<()> {
+        let (empty, all) = match self {
+            Operation::ReplaceShader { filter, all, .. }
+            | Operation::SwapShaders { filter, all, .. }
+            | Operation::DeleteTechniques { filter, all } => (filter.is_empty(), *all),
+            Operation::SamplerOverride { rule, all } => (rule.filter.is_empty(), *all),
+            Operation::CloneMaterial { .. } => return Ok(()),
+        };
+        if empty && !all {
+            bail!("The filter matches every technique, check for misspelt keys or set all = true");
+        }
+        Ok(())
+    }
+
+    /// Applies the operation, returning a one line summary of what changed
+    pub fn apply(&self, cache: &mut DynamicCacheFile, base_dir: &Path) -> Result<String> {
+        self.check_filter()?;
+        check_passes(self.passes(), || unknown_stages(cache))?;
+
+        match self {
+            Operation::ReplaceShader { filter, stage, file, force, .. } => {
+                replaceable(*stage)?;
+                let compiled = read_shader(base_dir, file)?;
+
+                let mut targets: Vec<(String, TechniqueDesc, u64)> = Vec::new();
+                for m in &cache.materials {
+                    let desc = m.decode_desc()?;
+                    if !filter.matches(m.material_name(), &desc) {
+                        continue;
+                    }
+                    let hash = if *stage == ShaderType::Vertex { m.vs_hash } else { m.ps_hash };
+                    if hash != 0 {
+                        targets.push((m.material_name().to_string(), desc, hash));
+                    }
+                }
+
+                let mut replaced: HashMap<u64, Vec<u64>> = HashMap::new();
+                for (material, desc, original) in &targets {
+                    let report = cache.replace_shader(material, desc, *stage, compiled.clone(), *force)
+                        .with_context(|| format!("Failed to replace the {} of {} {}", stage, material, desc))?;
+                    replaced.entry(*original).or_default().push(report.hash);
+                }
+
+                let merge = merge_copies(&replaced);
+                for m in cache.materials.iter_mut() {
+                    let hash = if *stage == ShaderType::Vertex { &mut m.vs_hash } else { &mut m.ps_hash };
+                    if let Some(keep) = merge.get(hash) {
+                        *hash = *keep;
+                    }
+                }
+                cache.shaders.retain(|s| !merge.contains_key(&s.hash));
+
+                let copied = replaced.iter().filter(|(original, hashes)| !hashes.contains(original)).count();
+                Ok(format!(
+                    "{} {} shaders used by {} techniques replaced with {} ({} bytes), {} copied",
+                    replaced.len(), stage, targets.len(), file.display(), compiled.len(), copied
+                ))
+            },
+            Operation::SwapShaders { filter, vs_hash, ps_hash, .. } => {
+                for hash in [vs_hash, ps_hash].into_iter().flatten() {
+                    if !cache.shaders.iter().any(|s| s.hash == *hash) {
+                        bail!("No shader with hash {:016X}", hash);
+                    }
+                }
+
+                let mut count: usize = 0;
+                for m in cache.materials.iter_mut() {
+                    if !filter.matches(m.material_name(), &m.decode_desc()?) {
+                        continue;
+                    }
+                    if let Some(vs) = vs_hash { m.vs_hash = *vs; }
+                    if let Some(ps) = ps_hash { m.ps_hash = *ps; }
+                    count += 1;
+                }
+
+                Ok(format!("{} techniques retargeted", count))
+            },
+            Operation::SamplerOverride { rule, .. } => {
+                let report = apply_sampler_rules(cache, std::slice::from_ref(rule))?;
+                Ok(format!("{} samplers changed", report.total()))
+            },
+            Operation::DeleteTechniques { filter, .. } => {
+                let count = cache.remove_techniques(|m, desc| filter.matches(m, desc))?;
+                Ok(format!("{} techniques deleted", count))
+            },
+            Operation::CloneMaterial { from, to } => {
+                let count = cache.clone_material(from, to)?;
+                Ok(format!("{} techniques copied from {} to {}", count, from, to))
+            },
+        }
+    }
+
+    /// Applies the operation to a `Manager`, with the same summaries as `apply`
+    pub fn apply_manager(&self, manager: &mut Manager, base_dir: &Path) -> Result<String> {
+        self.check_filter()?;
+        check_passes(self.passes(), || Ok(unknown_manager_stages(manager)))?;
+
+        // Names of the materials with at least one technique accepted by `f`
+        let matching = |manager: &Manager, f: &dyn Fn(&str, &Technique) -> bool| -> Vec<String> {
+            manager.materials.values()
+                .filter(|m| m.techniques.iter().any(|t| f(&m.name, t)))
+                .map(|m| m.name.clone())
+                .collect()
+        };
+
+        match self {
+            Operation::ReplaceShader { filter, stage, file, force, .. } => {
+                replaceable(*stage)?;
+                let compiled = read_shader(base_dir, file)?;
+                let shader = |t: &Technique| if *stage == ShaderType::Vertex { t.vs.clone() } else { t.ps.clone() };
+
+                let mut targets: Vec<(String, TechniqueDesc, u64)> = Vec::new();
+                for m in manager.materials.values() {
+                    for t in m.techniques.iter().filter(|t| filter.matches(&m.name, &t.desc)) {
+                        if let Some(s) = shader(t) {
+                            targets.push((m.name.clone(), t.desc.clone(), s.hash));
+                        }
+                    }
+                }
+
+                let mut replaced: HashMap<u64, Vec<u64>> = HashMap::new();
+                for (material, desc, original) in &targets {
+                    let report = manager.replace_shader(material, desc, *stage, compiled.clone(), *force)
+                        .with_context(|| format!("Failed to replace the {} of {} {}", stage, material, desc))?;
+                    replaced.entry(*original).or_default().push(report.hash);
+                }
+
+                let merge = merge_copies(&replaced);
+                let keep: HashMap<u64, Rc<Shader>> = merge.iter()
+                    .filter_map(|(h, keep)| Some((*h, manager.shaders.get(&CNameKey64::from(*keep))?.clone())))
+                    .collect();
+                let merged = |t: &Technique| shader(t).is_some_and(|s| keep.contains_key(&s.hash));
+                for name in matching(manager, &|_, t| merged(t)) {
+                    let material = manager.material_mut(&name).unwrap();
+                    for t in material.techniques.iter_mut().filter(|t| merged(t)) {
+                        let slot = if *stage == ShaderType::Vertex { &mut t.vs } else { &mut t.ps };
+                        *slot = slot.as_ref().map(|s| Rc::clone(&keep[&s.hash]));
+                    }
+                }
+                for hash in merge.keys() {
+                    manager.shaders.remove(&CNameKey64::from(*hash));
+                }
+
+                let copied = replaced.iter().filter(|(original, hashes)| !hashes.contains(original)).count();
+                Ok(format!(
+                    "{} {} shaders used by {} techniques replaced with {} ({} bytes), {} copied",
+                    replaced.len(), stage, targets.len(), file.display(), compiled.len(), copied
+                ))
+            },
+            Operation::SwapShaders { filter, vs_hash, ps_hash, .. } => {
+                let find = |hash: &Option<u64>| -> Result<Option<Rc<Shader>>> {
+                    hash.map(|h| manager.shaders.get(&CNameKey64::from(h)).cloned()
+                            .ok_or_else(|| anyhow!("No shader with hash {:016X}", h)))
+                        .transpose()
+                };
+                let (vs, ps) = (find(vs_hash)?, find(ps_hash)?);
+
+                let mut count: usize = 0;
+                for name in matching(manager, &|m, t| filter.matches(m, &t.desc)) {
+                    let material = manager.material_mut(&name).unwrap();
+                    for t in material.techniques.iter_mut().filter(|t| filter.matches(&name, &t.desc)) {
+                        if let Some(vs) = &vs { t.vs = Some(Rc::clone(vs)); }
+                        if let Some(ps) = &ps { t.ps = Some(Rc::clone(ps)); }
+                        count += 1;
+                    }
+                }
+
+                Ok(format!("{} techniques retargeted", count))
+            },
+            Operation::SamplerOverride { rule, .. } => {
+                let mut count: usize = 0;
+                for name in matching(manager, &|m, t| rule.filter.technique.matches(m, &t.desc)) {
+                    let material = manager.material_mut(&name).unwrap();
+                    for t in material.techniques.iter_mut() {
+                        count += rule.apply_technique(&name, t);
+                    }
+                }
+                Ok(format!("{} samplers changed", count))
+            },
+            Operation::DeleteTechniques { filter, .. } => {
+                let mut count: usize = 0;
+                for name in matching(manager, &|m, t| filter.matches(m, &t.desc)) {
+                    let material = manager.material_mut(&name).unwrap();
+                    count += material.remove_techniques(|t| filter.matches(&name, &t.desc));
+                    // A material only exists through its techniques in the cache
+                    if material.techniques.is_empty() {
+                        manager.materials.remove(&CNameKey32::from(CName::new(&name)));
+                    }
+                }
+                Ok(format!("{} techniques deleted", count))
+            },
+            Operation::CloneMaterial { from, to } => {
+                let key = |name: &str| CNameKey32::from(CName::new(name));
+                if manager.materials.contains_key(&key(to)) {
+                    bail!("Material {} already exists", to);
+                }
+
+                let mut material = Material::clone(
+                    manager.materials.get(&key(from)).ok_or_else(|| anyhow!("Material {} does not exist", from))?
+                );
+                material.name = to.clone();
+                let count = material.techniques.len();
+                manager.materials.insert(key(to), Rc::new(material));
+
+                Ok(format!("{} techniques copied from {} to {}", count, from, to))
+            },
+        }
+    }
+}
+
+/// Summary of each operation, in recipe order
+pub struct RecipeReport {
+    pub name: String,
+    pub ops: Vec<(&'static str, String)>,
+}
+
+impl std::fmt::Display for RecipeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Recipe: {}", self.name)?;
+        for (i, (kind, summary)) in self.ops.iter().enumerate() {
+            writeln!(f, "  [{}] {}: {}", i + 1, kind, summary)?;
+        }
+        Ok(())
+    }
+}
+
+impl Recipe {
+    pub fn parse(toml: &str) -> Result<Self> {
+        Ok(toml::from_str(toml)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read recipe {}", path.display()))?;
+
+        let mut recipe = Recipe::parse(&data)
+            .with_context(|| format!("Failed to parse recipe {}", path.display()))?;
+        recipe.base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+        Ok(recipe)
+    }
+
+    fn apply_each<F>(&self, mut f: F) -> Result<RecipeReport>
+    where F: FnMut(&Operation) -> Result<String> {
+        let mut ops: Vec<(&'static str, String)> = Vec::with_capacity(self.ops.len());
+
+        for (i, op) in self.ops.iter().enumerate() {
+            let summary = f(op)
+                .with_context(|| format!("Operation {} ({}) failed", i + 1, op.kind()))?;
+            ops.push((op.kind(), summary));
+        }
+
+        Ok(RecipeReport { name: self.name.clone(), ops })
+    }
+
+    /// Applies every operation in order
+    pub fn apply(&self, cache: &mut DynamicCacheFile) -> Result<RecipeReport> {
+        self.apply_each(|op| op.apply(cache, &self.base_dir))
+    }
+
+    /// Applies every operation in order to a `Manager`, saved through `Manager::to_dyn_cache`
+    pub fn apply_manager(&self, manager: &mut Manager) -> Result<RecipeReport> {
+        self.apply_each(|op| op.apply_manager(manager, &self.base_dir))
+    }
+
+    /// Loads a cache, applies the recipe and saves the result.
+    /// A dry run prints the summary of each operation without saving.
+    pub fn run(&self, input: &Path, output: &Path, dry_run: bool) -> Result<RecipeReport> {
+        let mut cache = DynamicCacheFile::load_file(input)
+            .with_context(|| format!("Failed to load shader cache {}", input.display()))?;
+
+        let report = self.apply(&mut cache)?;
+
+        if dry_run {
+            print!("{}", report);
+        }
+        else {
+            let options = SaveOptions { order: self.order, ..Default::default() };
+            cache.save_file_with(output, &options)
+                .with_context(|| format!("Failed to save shader cache {}", output.display()))?;
+        }
+
+        Ok(report)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::container::{Container, FourCC};
+    use crate::rtti_types::enums::{EMaterialVertexFactory, ETextureAddressing, ETextureFilteringMin};
+    use crate::test_util::{sampler, technique, test_cache};
+
+    use super::*;
+
+    /// Two metal_base techniques and a glass one, all sharing VS 1
+    fn fixture() -> DynamicCacheFile {
+        let mut cache = test_cache();
+        cache.materials.push(technique("glass", 0, 1, 2));
+        cache.materials[0].ps_samplers = vec![ sampler(0), sampler(1) ];
+        cache.materials[2].ps_samplers = vec![ sampler(0) ];
+        cache.info = cache.layout();
+        cache
+    }
+
+    /// A directory holding `new.bin` and a vertex shader container `vs.bin`
+    fn shader_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("shaderpunk_recipe_{}", name));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("new.bin"), b"new").unwrap();
+
+        let mut container = Container::default();
+        container.set_part(FourCC::SHEX, 0x0001_0050u32.to_le_bytes().to_vec());
+        fs::write(dir.join("vs.bin"), container.to_bytes()).unwrap();
+        dir
+    }
+
+    fn recipe(toml: &str, base_dir: &Path) -> Recipe {
+        let mut recipe = Recipe::parse(toml).unwrap();
+        recipe.base_dir = base_dir.to_path_buf();
+        recipe
+    }
+
+    #[test]
+    fn parse() {
+        let recipe = Recipe::parse(r#"
+            name = "test"
+            order = "MaterialFirstUse"
+
+            [[op]]
+            type = "sampler_override"
+            name = "aniso"
+            materials = [ "metal_*" ]
+            filtering_min = [ "Linear", "AnisotropicLow" ]
+            set = { filtering_min = "Anisotropic" }
+
+            [[op]]
+            type = "swap_shaders"
+            materials = [ "metal_base" ]
+            vertex_factories = [ "MeshStatic" ]
+            ps_hash = "0x00000000DEADBEEF"
+
+            [[op]]
+            type = "delete_techniques"
+            passes = [ "renderstage_wireframe" ]
+
+            [[op]]
+            type = "clone_material"
+            from = "metal_base"
+            to = "metal_base_copy"
+        "#).unwrap();
+
+        assert_eq!(recipe.name, "test");
+        assert_eq!(recipe.order, ChunkOrder::MaterialFirstUse);
+        assert_eq!(recipe.ops.len(), 4);
+
+        match &recipe.ops[0] {
+            Operation::SamplerOverride { rule, .. } => {
+                assert!(rule.filter.technique.matches_material("metal_base"));
+                assert_eq!(rule.filter.filtering_min.len(), 2);
+                assert_eq!(rule.set.filtering_min, Some(ETextureFilteringMin::Anisotropic));
+            },
+            _ => panic!("Expected sampler_override"),
+        }
+
+        match &recipe.ops[1] {
+            Operation::SwapShaders { filter, vs_hash, ps_hash, .. } => {
+                assert_eq!(filter.vertex_factories, vec![ EMaterialVertexFactory::MeshStatic ]);
+                assert_eq!(*vs_hash, None);
+                assert_eq!(*ps_hash, Some(0xDEADBEEF));
+            },
+            _ => panic!("Expected swap_shaders"),
+        }
+
+        assert_eq!(recipe.ops[2].kind(), "delete_techniques");
+        assert_eq!(recipe.ops[3].kind(), "clone_material");
+    }
+
+    #[test]
+    fn parse_unknown_op() {
+        assert!(Recipe::parse(r#"
+            [[op]]
+            type = "frobnicate"
+        "#).is_err());
+    }
+
+    #[test]
+    fn empty_filter() {
+        let mut cache = fixture();
+
+        // `material` isn't a matcher, the filter is empty
+        let typo = recipe(r#"
+            [[op]]
+            type = "delete_techniques"
+            material = "metal_base"
+        "#, Path::new(""));
+        assert!(typo.apply(&mut cache).is_err());
+        assert_eq!(cache.materials.len(), 3);
+
+        let all = recipe(r#"
+            [[op]]
+            type = "sampler_override"
+            all = true
+            set = { address_u = "Clamp" }
+        "#, Path::new(""));
+        assert_eq!(all.apply(&mut cache).unwrap().ops[0].1, "3 samplers changed");
+        assert_eq!(cache.materials[2].ps_samplers[0].addressU, ETextureAddressing::Clamp);
+    }
+
+    #[test]
+    fn unknown_pass() {
+        let mut cache = fixture();
+        let typo = recipe(r#"
+            [[op]]
+            type = "delete_techniques"
+            passes = [ "renderstage_gbufer_regular" ]
+        "#, Path::new(""));
+
+        assert!(typo.apply(&mut cache).is_err());
+        let mut manager = Manager::from_dyn_cache(fixture()).unwrap();
+        assert!(typo.apply_manager(&mut manager).is_err());
+    }
+
+    #[test]
+    fn apply() {
+        let dir = shader_dir("apply");
+        let mut cache = fixture();
+
+        let report = recipe(r#"
+            [[op]]
+            type = "replace_shader"
+            materials = [ "metal_base" ]
+            stage = "Vertex"
+            file = "new.bin"
+
+            [[op]]
+            type = "swap_shaders"
+            materials = [ "glass" ]
+            ps_hash = "1"
+
+            [[op]]
+            type = "sampler_override"
+            materials = [ "metal_*" ]
+            registers = [ 1 ]
+            set = { filtering_min = "Anisotropic" }
+
+            [[op]]
+            type = "clone_material"
+            from = "glass"
+            to = "glass_copy"
+
+            [[op]]
+            type = "delete_techniques"
+            materials = [ "glass" ]
+        "#, &dir).apply(&mut cache).unwrap();
+
+        let summaries: Vec<&str> = report.ops.iter().map(|(_, s)| s.as_str()).collect();
+        assert_eq!(summaries, vec![
+            "1 Vertex shaders used by 2 techniques replaced with new.bin (3 bytes), 1 copied",
+            "1 techniques retargeted",
+            "1 samplers changed",
+            "1 techniques copied from glass to glass_copy",
+            "1 techniques deleted",
+        ]);
+
+        // Both metal_base techniques share one copy, glass_copy keeps the original
+        assert_eq!(cache.shaders.len(), 3);
+        let copy = cache.materials[0].vs_hash;
+        assert_ne!(copy, 1);
+        assert_eq!(cache.materials[1].vs_hash, copy);
+        assert_eq!(cache.shaders.iter().find(|s| s.hash == copy).unwrap().compiled, b"new");
+        assert_eq!(cache.shaders[0].compiled, vec![1; 4]);
+
+        assert_eq!(cache.materials[0].ps_samplers[1].filteringMin, ETextureFilteringMin::Anisotropic);
+        assert_eq!(cache.materials.len(), 3);
+        assert_eq!(cache.materials[2].material_name(), "glass_copy");
+        assert_eq!((cache.materials[2].vs_hash, cache.materials[2].ps_hash), (1, 1));
+    }
+
+    #[test]
+    fn replace_checks() {
+        let dir = shader_dir("replace_checks");
+        let replace = |force: bool| recipe(&format!(r#"
+            [[op]]
+            type = "replace_shader"
+            materials = [ "glass" ]
+            stage = "Pixel"
+            file = "vs.bin"
+            force = {}
+        "#, force), &dir);
+
+        let mut cache = fixture();
+        assert!(replace(false).apply(&mut cache).is_err());
+        assert_eq!(cache.shaders[1].compiled, vec![2; 4]);
+
+        // PS 2 is shared with metal_base
+        replace(true).apply(&mut cache).unwrap();
+        assert_eq!(cache.shaders.len(), 3);
+        assert_eq!(cache.materials[0].ps_hash, 2);
+        assert_ne!(cache.materials[2].ps_hash, 2);
+    }
+
+    #[test]
+    fn apply_manager() {
+        let dir = shader_dir("apply_manager");
+        let mut manager = Manager::from_dyn_cache(fixture()).unwrap();
+
+        let report = recipe(r#"
+            [[op]]
+            type = "replace_shader"
+            materials = [ "metal_base" ]
+            stage = "Vertex"
+            file = "new.bin"
+
+            [[op]]
+            type = "swap_shaders"
+            materials = [ "glass" ]
+            ps_hash = "1"
+
+            [[op]]
+            type = "sampler_override"
+            materials = [ "metal_*" ]
+            registers = [ 1 ]
+            set = { filtering_min = "Anisotropic" }
+
+            [[op]]
+            type = "clone_material"
+            from = "glass"
+            to = "glass_copy"
+
+            [[op]]
+            type = "delete_techniques"
+            materials = [ "glass" ]
+        "#, &dir).apply_manager(&mut manager).unwrap();
+
+        let summaries: Vec<&str> = report.ops.iter().map(|(_, s)| s.as_str()).collect();
+        assert_eq!(summaries, vec![
+            "1 Vertex shaders used by 2 techniques replaced with new.bin (3 bytes), 1 copied",
+            "1 techniques retargeted",
+            "1 samplers changed",
+            "1 techniques copied from glass to glass_copy",
+            "1 techniques deleted",
+        ]);
+
+        assert_eq!(manager.shaders.len(), 3);
+        assert_eq!(manager.materials.len(), 2);
+
+        let metal = manager.materials.values().find(|m| m.name == "metal_base").unwrap();
+        let copy = metal.techniques[0].vs.as_ref().unwrap();
+        assert_eq!(copy.compiled, b"new");
+        assert!(Rc::ptr_eq(copy, metal.techniques[1].vs.as_ref().unwrap()));
+        assert_eq!(metal.techniques[0].ps_samplers[1].filteringMin, ETextureFilteringMin::Anisotropic);
+
+        let glass = manager.materials.values().find(|m| m.name == "glass_copy").unwrap();
+        assert_eq!(glass.techniques[0].vs.as_ref().unwrap().hash, 1);
+        assert_eq!(glass.techniques[0].ps.as_ref().unwrap().hash, 1);
+
+        let mut output = std::io::Cursor::new(Vec::new());
+        manager.to_dyn_cache().save(&mut output).unwrap();
+        output.set_position(0);
+        let saved = DynamicCacheFile::load(&mut output).unwrap();
+        assert_eq!((saved.shaders.len(), saved.materials.len()), (3, 3));
+        assert!(saved.materials.iter().any(|m| m.name.as_str().starts_with("glass_copy ") && m.ps_hash == 1));
+    }
+
+    #[test]
+    fn dry_run() {
+        let dir = shader_dir("dry_run");
+        let input = dir.join("input.cache");
+        let output = dir.join("output.cache");
+        let _ = fs::remove_file(&output);
+        fixture().save_file(&input).unwrap();
+        let before = fs::read(&input).unwrap();
+
+        let recipe = recipe(r#"
+            [[op]]
+            type = "delete_techniques"
+            materials = [ "glass" ]
+        "#, &dir);
+
+        let report = recipe.run(&input, &output, true).unwrap();
+        assert_eq!(report.ops[0].1, "1 techniques deleted");
+        assert_eq!(fs::read(&input).unwrap(), before);
+        assert!(!output.exists());
+
+        recipe.run(&input, &output, false).unwrap();
+        let saved = DynamicCacheFile::load_file(&output).unwrap();
+        assert_eq!(saved.materials.len(), 2);
+    }
+}
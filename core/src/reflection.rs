@@ -0,0 +1,362 @@
+use std::ffi::CStr;
+use std::io::{self, Cursor};
+
+use crate::bundle::decode::DecodeExt;
+use crate::container::{Container, FourCC};
+use crate::spirv::SpirvModule;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ResourceClass {
+    CBuffer,
+    SRV,
+    UAV,
+    Sampler,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResourceDimension {
+    Unknown,
+    Buffer,
+    Texture1D,
+    Texture1DArray,
+    Texture2D,
+    Texture2DArray,
+    Texture2DMS,
+    Texture2DMSArray,
+    Texture3D,
+    TextureCube,
+    TextureCubeArray,
+    RawBuffer,
+    StructuredBuffer,
+    AccelerationStructure,
+}
+
+/// A variable of a constant buffer, offsets and sizes are in bytes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CBufferVariable {
+    pub name: String,
+    pub offset: u32,
+    pub size: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResourceBinding {
+    /// Empty when reflected from PSV0, which doesn't keep names
+    pub name: String,
+    pub class: ResourceClass,
+    pub dimension: ResourceDimension,
+    pub space: u32,
+    pub register: u32,
+    /// Number of registers, `u32::MAX` for unbounded arrays
+    pub count: u32,
+    /// Size in bytes, constant buffers only
+    pub size: Option<u32>,
+    /// Constant buffers only, and only from RDEF
+    pub variables: Vec<CBufferVariable>,
+}
+
+/// Resource bindings of a shader, from RDEF when present, otherwise from PSV0,
+/// or from the decorations of a SPIR-V module
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Reflection {
+    pub bindings: Vec<ResourceBinding>,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn read_string(data: &[u8], offset: u32) -> io::Result<String> {
+    data.get(offset as usize..)
+        .and_then(|s| CStr::from_bytes_until_nul(s).ok())
+        .map(|s| s.to_string_lossy().into_owned())
+        .ok_or_else(|| invalid("Invalid string offset"))
+}
+
+impl Reflection {
+    /// None if the blob is neither SPIR-V nor a container with RDEF or PSV0
+    pub fn from_blob(data: &[u8]) -> Option<io::Result<Self>> {
+        if SpirvModule::is_spirv(data) {
+            return Some(SpirvModule::parse(data).map(|m| Reflection::from(&m)));
+        }
+        if !Container::is_container(data) {
+            return None;
+        }
+        match Container::parse(data) {
+            Ok(c) => Reflection::from_container(&c),
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    pub fn from_container(container: &Container) -> Option<io::Result<Self>> {
+        if let Some(p) = container.part(FourCC::RDEF) {
+            return Some(Reflection::parse_rdef(&p.data));
+        }
+        container.part(FourCC::PSV0).map(|p| Reflection::parse_psv0(&p.data))
+    }
+
+    pub fn of_class(&self, class: ResourceClass) -> impl Iterator<Item = &ResourceBinding> {
+        self.bindings.iter().filter(move |b| b.class == class)
+    }
+
+    /// Sampler bound to the register in space 0
+    pub fn sampler(&self, register: u32) -> Option<&ResourceBinding> {
+        self.of_class(ResourceClass::Sampler)
+            .find(|b| b.space == 0 && register >= b.register && register - b.register < b.count)
+    }
+
+    /// Constant buffer variable with the name, and the buffer holding it
+    pub fn find_variable(&self, name: &str) -> Option<(&ResourceBinding, &CBufferVariable)> {
+        self.of_class(ResourceClass::CBuffer)
+            .find_map(|b| b.variables.iter().find(|v| v.name == name).map(|v| (b, v)))
+    }
+
+    /// Resource definitions, written by FXC and by DXC with reflection kept
+    pub fn parse_rdef(data: &[u8]) -> io::Result<Self> {
+        let mut input = Cursor::new(data);
+        let cbuffer_count: u32 = input.decode()?;
+        let cbuffer_offset: u32 = input.decode()?;
+        let binding_count: u32 = input.decode()?;
+        let binding_offset: u32 = input.decode()?;
+        let _minor: u8 = input.decode()?;
+        let major: u8 = input.decode()?;
+        let _program_type: u16 = input.decode()?;
+        let _flags: u32 = input.decode()?;
+        let _creator: u32 = input.decode()?;
+
+        // Shader model 5 adds a header with the record sizes. Variables gain the texture and
+        // sampler ranges, and from 5.1 bindings gain the register space and id.
+        let (binding_size, var_size): (u64, u64) = if major >= 5 {
+            if input.decode::<u32>()? != u32::from_le_bytes(*b"RD11") {
+                return Err(invalid("Invalid RDEF header"));
+            }
+            let _header_size: u32 = input.decode()?;
+            let _cbuffer_size: u32 = input.decode()?;
+            let binding_size: u32 = input.decode()?;
+            let var_size: u32 = input.decode()?;
+            if binding_size < 32 || var_size < 12 {
+                return Err(invalid("Invalid RDEF record size"));
+            }
+            (binding_size.into(), var_size.into())
+        }
+        else {
+            (32, 24)
+        };
+
+        // (name, size, variables) in declaration order, matched to bindings by name.
+        // Counts aren't trusted for preallocation, a corrupt one fails at the end of the part.
+        let mut cbuffers: Vec<(String, u32, Vec<CBufferVariable>)> = Vec::new();
+        for i in 0..cbuffer_count {
+            input.set_position(cbuffer_offset as u64 + 24 * i as u64);
+            let name: u32 = input.decode()?;
+            let var_count: u32 = input.decode()?;
+            let var_offset: u32 = input.decode()?;
+            let size: u32 = input.decode()?;
+
+            let mut variables: Vec<CBufferVariable> = Vec::new();
+            for v in 0..var_count {
+                input.set_position(var_offset as u64 + var_size * v as u64);
+                let name: u32 = input.decode()?;
+                let offset: u32 = input.decode()?;
+                let size: u32 = input.decode()?;
+                variables.push(CBufferVariable { name: read_string(data, name)?, offset, size });
+            }
+
+            cbuffers.push((read_string(data, name)?, size, variables));
+        }
+
+        let mut bindings: Vec<ResourceBinding> = Vec::new();
+        for i in 0..binding_count {
+            input.set_position(binding_offset as u64 + binding_size * i as u64);
+            let name: u32 = input.decode()?;
+            let input_type: u32 = input.decode()?;
+            let _return_type: u32 = input.decode()?;
+            let dimension: u32 = input.decode()?;
+            let _samples: u32 = input.decode()?;
+            let register: u32 = input.decode()?;
+            let count: u32 = input.decode()?;
+            let _flags: u32 = input.decode()?;
+            let space: u32 = if binding_size >= 40 { input.decode()? } else { 0 };
+
+            let name = read_string(data, name)?;
+            // D3D_SHADER_INPUT_TYPE
+            let (class, dimension) = match input_type {
+                0 => (ResourceClass::CBuffer, ResourceDimension::Unknown),
+                1 => (ResourceClass::SRV, ResourceDimension::Buffer),
+                2 => (ResourceClass::SRV, srv_dimension(dimension)),
+                3 => (ResourceClass::Sampler, ResourceDimension::Unknown),
+                4 => (ResourceClass::UAV, srv_dimension(dimension)),
+                5 => (ResourceClass::SRV, ResourceDimension::StructuredBuffer),
+                7 => (ResourceClass::SRV, ResourceDimension::RawBuffer),
+                6 | 9 | 10 | 11 => (ResourceClass::UAV, ResourceDimension::StructuredBuffer),
+                8 => (ResourceClass::UAV, ResourceDimension::RawBuffer),
+                12 => (ResourceClass::SRV, ResourceDimension::AccelerationStructure),
+                _ => return Err(invalid("Unknown RDEF input type")),
+            };
+
+            let (size, variables) = match class {
+                ResourceClass::CBuffer => cbuffers.iter()
+                    .find(|c| c.0 == name)
+                    .map_or((None, Vec::new()), |c| (Some(c.1), c.2.clone())),
+                _ => (None, Vec::new()),
+            };
+
+            bindings.push(ResourceBinding { name, class, dimension, space, register, count, size, variables });
+        }
+
+        Ok(Reflection { bindings })
+    }
+
+    /// Pipeline state validation, written by DXC. Has no names or buffer sizes.
+    pub fn parse_psv0(data: &[u8]) -> io::Result<Self> {
+        let mut input = Cursor::new(data);
+        let info_size: u32 = input.decode()?;
+        input.set_position(4 + info_size as u64);
+
+        let count: u32 = input.decode()?;
+        if count == 0 {
+            return Ok(Reflection::default());
+        }
+        let binding_size: u32 = input.decode()?;
+        if binding_size < 16 {
+            return Err(invalid("Invalid PSV0 binding size"));
+        }
+
+        let start = input.position();
+        let mut bindings: Vec<ResourceBinding> = Vec::new();
+        for i in 0..count {
+            input.set_position(start + binding_size as u64 * i as u64);
+            let kind: u32 = input.decode()?;
+            let space: u32 = input.decode()?;
+            let lower: u32 = input.decode()?;
+            let upper: u32 = input.decode()?;
+            let shape: u32 = if binding_size >= 20 { input.decode()? } else { 0 };
+
+            // PSVResourceType
+            let class = match kind {
+                1 => ResourceClass::Sampler,
+                2 => ResourceClass::CBuffer,
+                3..=5 => ResourceClass::SRV,
+                6..=9 => ResourceClass::UAV,
+                _ => return Err(invalid("Unknown PSV0 resource type")),
+            };
+
+            bindings.push(ResourceBinding {
+                name: String::new(),
+                class,
+                dimension: psv_dimension(shape),
+                space,
+                register: lower,
+                count: match upper {
+                    u32::MAX => u32::MAX,
+                    _ => upper.checked_sub(lower).ok_or_else(|| invalid("Invalid PSV0 register range"))? + 1,
+                },
+                size: None,
+                variables: Vec::new(),
+            });
+        }
+
+        Ok(Reflection { bindings })
+    }
+}
+
+/// D3D_SRV_DIMENSION
+fn srv_dimension(value: u32) -> ResourceDimension {
+    match value {
+        1 | 11 => ResourceDimension::Buffer,
+        2  => ResourceDimension::Texture1D,
+        3  => ResourceDimension::Texture1DArray,
+        4  => ResourceDimension::Texture2D,
+        5  => ResourceDimension::Texture2DArray,
+        6  => ResourceDimension::Texture2DMS,
+        7  => ResourceDimension::Texture2DMSArray,
+        8  => ResourceDimension::Texture3D,
+        9  => ResourceDimension::TextureCube,
+        10 => ResourceDimension::TextureCubeArray,
+        _  => ResourceDimension::Unknown,
+    }
+}
+
+/// PSVResourceKind
+fn psv_dimension(value: u32) -> ResourceDimension {
+    match value {
+        1  => ResourceDimension::Texture1D,
+        2  => ResourceDimension::Texture2D,
+        3  => ResourceDimension::Texture2DMS,
+        4  => ResourceDimension::Texture3D,
+        5  => ResourceDimension::TextureCube,
+        6  => ResourceDimension::Texture1DArray,
+        7  => ResourceDimension::Texture2DArray,
+        8  => ResourceDimension::Texture2DMSArray,
+        9  => ResourceDimension::TextureCubeArray,
+        10 => ResourceDimension::Buffer,
+        11 => ResourceDimension::RawBuffer,
+        12 => ResourceDimension::StructuredBuffer,
+        16 => ResourceDimension::AccelerationStructure,
+        _  => ResourceDimension::Unknown,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{push, rdef, rdef_model};
+
+    use super::*;
+
+    #[test]
+    fn parse_rdef() {
+        let r = Reflection::parse_rdef(&rdef(&[("Color", 0, 16), ("Transform", 16, 64)], &[0, 3])).unwrap();
+
+        assert_eq!(r.bindings.len(), 4);
+        let cb = &r.bindings[0];
+        assert_eq!((cb.class, cb.name.as_str(), cb.register, cb.size), (ResourceClass::CBuffer, "Material", 1, Some(80)));
+        assert_eq!(r.bindings[1].dimension, ResourceDimension::Texture2D);
+
+        let (_, v) = r.find_variable("Transform").unwrap();
+        assert_eq!((v.offset, v.size), (16, 64));
+        assert!(r.sampler(3).is_some());
+        assert!(r.sampler(1).is_none());
+    }
+
+    #[test]
+    fn shader_model_5_0() {
+        // 32-byte bindings without a register space
+        let r = Reflection::parse_rdef(&rdef_model(0, &[("Color", 0, 16)], &[2])).unwrap();
+        assert_eq!(r.bindings.len(), 3);
+        assert_eq!((r.bindings[1].name.as_str(), r.bindings[1].dimension), ("Albedo", ResourceDimension::Texture2D));
+        assert!(r.sampler(2).is_some());
+        assert_eq!(r.find_variable("Color").unwrap().0.size, Some(16));
+    }
+
+    #[test]
+    fn corrupt_counts() {
+        let data = rdef(&[("Color", 0, 16)], &[0]);
+        // cbuffer count, binding count and the variable count of the first cbuffer
+        for offset in [0, 8, 64] {
+            let mut data = data.clone();
+            data[offset..offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+            assert!(Reflection::parse_rdef(&data).is_err());
+        }
+    }
+
+    #[test]
+    fn parse_psv0() {
+        let mut data: Vec<u8> = Vec::new();
+        push(&mut data, &[24, 0, 0, 0, 0, 0, 0]);
+        push(&mut data, &[2, 24]);
+        push(&mut data, &[2, 0, 1, 1, 13, 0]);
+        push(&mut data, &[1, 0, 4, u32::MAX, 14, 0]);
+
+        let r = Reflection::parse_psv0(&data).unwrap();
+        assert_eq!(r.bindings.len(), 2);
+        assert_eq!(r.bindings[0].class, ResourceClass::CBuffer);
+        assert_eq!(r.bindings[1].count, u32::MAX);
+        assert!(r.sampler(100).is_some());
+
+        // Upper register below the lower one
+        data.truncate(data.len() - 16);
+        push(&mut data, &[4, 3, 14, 0]);
+        assert!(Reflection::parse_psv0(&data).is_err());
+    }
+}
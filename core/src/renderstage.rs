@@ -0,0 +1,252 @@
+use std::hash::{Hash, Hasher};
+
+use anyhow::{bail, Result};
+use hashbrown::HashMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::bundle::dyn_cache::DynamicCacheFile;
+use crate::manager::Manager;
+
+macro_rules! render_stages {
+    ($($variant:ident => $name:literal,)*) => {
+        /// Render stage (pass) a technique is compiled for.
+        /// Known stages are listed in `autodoc/templates/renderstage`.
+        #[derive(Clone, Debug)]
+        pub enum RenderStage {
+            $($variant,)*
+            /// Not seen in any known cache, or a typo
+            Unknown(String),
+        }
+
+        impl RenderStage {
+            pub const KNOWN: &[RenderStage] = &[
+                $(RenderStage::$variant,)*
+            ];
+
+            pub fn as_str(&self) -> &str {
+                match self {
+                    $(RenderStage::$variant => $name,)*
+                    RenderStage::Unknown(s) => s.as_str(),
+                }
+            }
+        }
+
+        impl From<&str> for RenderStage {
+            fn from(value: &str) -> Self {
+                match value {
+                    $($name => RenderStage::$variant,)*
+                    _ => RenderStage::Unknown(String::from(value)),
+                }
+            }
+        }
+    };
+}
+
+render_stages! {
+    CascadeRegular                 => "renderstage_cascade_regular",
+    CompositionElement             => "renderstage_composition_element",
+    CountLights                    => "renderstage_count_lights",
+    Debugdraw                      => "renderstage_debugdraw",
+    DebugdrawWireframe             => "renderstage_debugdraw_wireframe",
+    Depth                          => "renderstage_depth",
+    DepthPrepass                   => "renderstage_depth_prepass",
+    DepthprepassMirrorOpaqueNotxaa => "renderstage_depthprepass_mirror_opaque_notxaa",
+    Distortion                     => "renderstage_distortion",
+    GbufferRegular                 => "renderstage_gbuffer_regular",
+    GbufferVelbuffRegular          => "renderstage_gbuffer_velbuff_regular",
+    HairAlphaAccum                 => "renderstage_hair_alpha_accum",
+    HairBasecolorBlend             => "renderstage_hair_basecolor_blend",
+    HairGbufferSolid               => "renderstage_hair_gbuffer_solid",
+    HairGbufferVelbuffSolid        => "renderstage_hair_gbuffer_velbuff_solid",
+    Highlights                     => "renderstage_highlights",
+    HologramDepth                  => "renderstage_hologram_depth",
+    LateGbufferRegular             => "renderstage_late_gbuffer_regular",
+    Lighting                       => "renderstage_lighting",
+    MarkTransparentParticlesRt     => "renderstage_mark_transparent_particles_rt",
+    MaskstencilOpaqueNotxaa        => "renderstage_maskstencil_opaque_notxaa",
+    OpaqueNotxaa                   => "renderstage_opaque_notxaa",
+    Overdraw                       => "renderstage_overdraw",
+    ParticleOverdraw               => "renderstage_particle_overdraw",
+    PlanarReflection               => "renderstage_planar_reflection",
+    PostFeedbackColor              => "renderstage_post_feedback_color",
+    PostGbuffer                    => "renderstage_post_gbuffer",
+    ScreenSpaceWaterDepth          => "renderstage_screen_space_water_depth",
+    ScreenspaceVfx                 => "renderstage_screenspace_vfx",
+    SkinTranslucency               => "renderstage_skin_translucency",
+    SubsurfaceEmissive             => "renderstage_subsurface_emissive",
+    TerrainHeightmap               => "renderstage_terrain_heightmap",
+    TopDownCarProxy                => "renderstage_top_down_car_proxy",
+    TopDownCarProxyBackFace        => "renderstage_top_down_car_proxy_back_face",
+    TopDownCarProxyDebug           => "renderstage_top_down_car_proxy_debug",
+    Transparent                    => "renderstage_transparent",
+    TransparentBackFace            => "renderstage_transparent_back_face",
+    TransparentBackground          => "renderstage_transparent_background",
+    TransparentDepthWrite          => "renderstage_transparent_depth_write",
+    TransparentMarkRt              => "renderstage_transparent_mark_rt",
+    TransparentNotxaa              => "renderstage_transparent_notxaa",
+    TransparentNotxaaBackground    => "renderstage_transparent_notxaa_background",
+    Unlit                          => "renderstage_unlit",
+    Velocitybuffer                 => "renderstage_velocitybuffer",
+    Vision                         => "renderstage_vision",
+    Wireframe                      => "renderstage_wireframe",
+    WireframeSolid                 => "renderstage_wireframe_solid",
+}
+
+impl RenderStage {
+    pub fn is_known(&self) -> bool {
+        !matches!(self, RenderStage::Unknown(_))
+    }
+}
+
+impl From<String> for RenderStage {
+    fn from(value: String) -> Self {
+        RenderStage::from(value.as_str())
+    }
+}
+
+impl std::str::FromStr for RenderStage {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(RenderStage::from(s))
+    }
+}
+
+impl std::fmt::Display for RenderStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+// Equality, hashing and ordering all go through the name, so an `Unknown` holding a
+// known name still behaves like the known stage and techniques keep their string order
+
+impl PartialEq for RenderStage {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+impl Eq for RenderStage {}
+
+impl Hash for RenderStage {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
+}
+
+impl PartialOrd for RenderStage {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RenderStage {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl Serialize for RenderStage {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for RenderStage {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de> {
+        Ok(RenderStage::from(String::deserialize(deserializer)?))
+    }
+}
+
+/// Every unknown render stage used by the cache, with the number of techniques using it
+pub fn unknown_stages(cache: &DynamicCacheFile) -> Result<Vec<(String, usize)>> {
+    let mut passes: Vec<RenderStage> = Vec::with_capacity(cache.materials.len());
+    for m in &cache.materials {
+        passes.push(m.decode_desc()?.pass);
+    }
+    Ok(count_unknown(passes.iter()))
+}
+
+/// `unknown_stages` of the techniques loaded in a `Manager`
+pub fn unknown_manager_stages(manager: &Manager) -> Vec<(String, usize)> {
+    count_unknown(manager.materials.values().flat_map(|m| m.techniques.iter().map(|t| &t.desc.pass)))
+}
+
+fn count_unknown<'a>(passes: impl Iterator<Item = &'a RenderStage>) -> Vec<(String, usize)> {
+    let mut unknown: HashMap<String, usize> = HashMap::new();
+    for pass in passes.filter(|p| !p.is_known()) {
+        *unknown.entry(pass.to_string()).or_default() += 1;
+    }
+
+    let mut unknown: Vec<(String, usize)> = unknown.into_iter().collect();
+    unknown.sort();
+    unknown
+}
+
+/// Fails on a pass given by the user that isn't registered and that no technique
+/// uses either, which is most likely a typo that would silently match nothing.
+/// `used` is only called if there are unregistered passes, see `unknown_stages`.
+pub fn check_passes<F>(passes: &[RenderStage], used: F) -> Result<()>
+where F: FnOnce() -> Result<Vec<(String, usize)>> {
+    if passes.iter().all(RenderStage::is_known) {
+        return Ok(());
+    }
+
+    let used = used()?;
+    for pass in passes.iter().filter(|p| !p.is_known()) {
+        if !used.iter().any(|(name, _)| name == pass.as_str()) {
+            bail!("Unknown render stage {}, it isn't registered or used by any technique", pass);
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_known() {
+        assert_eq!(RenderStage::from("renderstage_gbuffer_regular"), RenderStage::GbufferRegular);
+        assert!(RenderStage::from("renderstage_particle_overdraw").is_known());
+    }
+
+    #[test]
+    fn parse_unknown() {
+        let stage = RenderStage::from("renderstage_gbufer_regular");
+        assert!(!stage.is_known());
+        assert_eq!(stage.as_str(), "renderstage_gbufer_regular");
+    }
+
+    #[test]
+    fn roundtrip_known() {
+        assert_eq!(RenderStage::KNOWN.len(), 47);
+        for stage in RenderStage::KNOWN {
+            let parsed = RenderStage::from(stage.as_str());
+            assert!(parsed.is_known());
+            assert_eq!(&parsed, stage);
+        }
+    }
+
+    #[test]
+    fn check_typos() {
+        let used = || Ok(vec![ (String::from("renderstage_new_in_patch"), 3) ]);
+
+        assert!(check_passes(&[RenderStage::Wireframe], || unreachable!()).is_ok());
+        assert!(check_passes(&[RenderStage::from("renderstage_new_in_patch")], used).is_ok());
+        assert!(check_passes(&[RenderStage::Wireframe, RenderStage::from("renderstage_wirefame")], used).is_err());
+    }
+
+    #[test]
+    fn name_ordering() {
+        let unknown = RenderStage::Unknown(String::from("renderstage_depth"));
+        assert_eq!(unknown, RenderStage::Depth);
+        assert!(RenderStage::Depth < RenderStage::DepthPrepass);
+        assert!(RenderStage::Wireframe > RenderStage::from("renderstage_aaa"));
+    }
+}
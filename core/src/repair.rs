@@ -0,0 +1,300 @@
+use hashbrown::{HashMap, HashSet};
+use serde::Serialize;
+
+use crate::bundle::dyn_cache::DynamicCacheFile;
+use crate::bundle::encode::{Encode, EncodeExt};
+use crate::digest;
+use crate::validate::{validate, Check, ValidationReport};
+
+#[derive(Clone, Copy, Default)]
+pub struct RepairOptions {
+    /// Point dangling shader references at the shaders of the technique's fallback,
+    /// instead of dropping the technique
+    pub redirect: bool,
+    /// Re-sign shader containers whose digest doesn't match their contents. Off by default,
+    /// a stale digest can mean the blob was edited or corrupted, and signing hides which.
+    pub resign: bool,
+}
+
+/// A single fix, tagged with the check it addresses
+#[derive(Clone, Debug, Serialize)]
+pub struct Change {
+    pub check: Check,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct RepairReport {
+    pub changes: Vec<Change>,
+    /// Findings that couldn't be fixed safely
+    pub remaining: ValidationReport,
+}
+
+impl RepairReport {
+    fn push(&mut self, check: Check, message: String) {
+        self.changes.push(Change { check, message });
+    }
+}
+
+impl std::fmt::Display for RepairReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for c in &self.changes {
+            writeln!(f, "FIXED: {:?}: {}", c.check, c.message)?;
+        }
+        writeln!(f, "{} fixes applied", self.changes.len())?;
+        write!(f, "{}", self.remaining)
+    }
+}
+
+fn encoded<A: Encode>(value: &A) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::new();
+    // Writing to a Vec can't fail
+    data.encode(value).unwrap();
+    data
+}
+
+/// Removes byte-identical copies of a chunk, keeping the first. Copies that differ are
+/// left for the validator to report, as there's no way to tell which one the game uses.
+fn dedup<T: Encode, K: Eq + std::hash::Hash>(chunks: &mut Vec<T>, key: impl Fn(&T) -> K) -> Vec<K> {
+    let mut seen: HashMap<K, Vec<u8>> = HashMap::new();
+    let mut removed: Vec<K> = Vec::new();
+
+    chunks.retain(|c| {
+        let bytes = encoded(c);
+        match seen.get(&key(c)) {
+            None => {
+                seen.insert(key(c), bytes);
+                true
+            },
+            Some(first) if *first == bytes => {
+                removed.push(key(c));
+                false
+            },
+            Some(_) => true,
+        }
+    });
+
+    removed
+}
+
+/// Fixes what can be fixed without guessing, then validates the result.
+///
+/// - Byte-identical duplicate chunks are removed
+/// - Shader containers with a stale digest are re-signed, if asked for
+/// - Techniques referencing missing shaders are dropped, or redirected to their fallback
+/// - Composite technique hashes are recomputed from the name
+/// - Footer counts, including `param_count`, sizes and offsets are recomputed
+pub fn repair(cache: &mut DynamicCacheFile, options: RepairOptions) -> RepairReport {
+    let mut report = RepairReport { changes: Vec::new(), remaining: ValidationReport::default() };
+
+    //--------------------------------------------------------------------------
+    // Duplicates
+
+    for hash in dedup(&mut cache.shaders, |s| s.hash) {
+        report.push(Check::DuplicateShader, format!("removed copy of shader [{:016X}]", hash));
+    }
+    for hash in dedup(&mut cache.materials, |m| m.hash) {
+        report.push(Check::DuplicateTechnique, format!("removed copy of technique [{:016X}]", hash));
+    }
+    for hash in dedup(&mut cache.params, |p| p.hash) {
+        report.push(Check::DuplicateParams, format!("removed copy of params [{:016X}]", hash));
+    }
+    for hash in dedup(&mut cache.timestamps, |t| t.hash) {
+        report.push(Check::DuplicateTimestamp, format!("removed copy of timestamp [{:08X}]", hash));
+    }
+    for path in dedup(&mut cache.includes, |i| i.path.to_string()) {
+        report.push(Check::DuplicateInclude, format!("removed copy of include {}", path));
+    }
+
+    //--------------------------------------------------------------------------
+    // Shader digests
+
+    // Otherwise they're left for the validator to report
+    if options.resign {
+        for s in cache.shaders.iter_mut() {
+            if digest::resign(&mut s.compiled) {
+                report.push(Check::ShaderDigest, format!("re-signed shader [{:016X}]", s.hash));
+            }
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    // Dangling shader references
+
+    let shaders: HashSet<u64> = cache.shaders.iter().map(|s| s.hash).collect();
+    let exists = |hash: u64| hash == 0 || shaders.contains(&hash);
+
+    // (material, technique index) -> (vs_hash, ps_hash), for fallback lookups
+    let techniques: HashMap<(String, u32), (u64, u64)> = cache.materials.iter()
+        .filter_map(|m| {
+            let desc = m.decode_desc().ok()?;
+            Some(((m.material_name().to_string(), desc.index), (m.vs_hash, m.ps_hash)))
+        })
+        .collect();
+
+    let mut changes: Vec<Change> = Vec::new();
+    cache.materials.retain_mut(|m| {
+        if exists(m.vs_hash) && exists(m.ps_hash) {
+            return true;
+        }
+
+        if options.redirect {
+            let fallback = m.decode_desc().ok()
+                .filter(|d| d.fallback_index != 0)
+                .and_then(|d| techniques.get(&(m.material_name().to_string(), u32::from(d.fallback_index))))
+                .filter(|(vs, ps)| exists(*vs) && exists(*ps));
+
+            if let Some(&(vs, ps)) = fallback {
+                if !exists(m.vs_hash) { m.vs_hash = vs; }
+                if !exists(m.ps_hash) { m.ps_hash = ps; }
+                changes.push(Change {
+                    check: Check::MissingShader,
+                    message: format!("redirected {} to the shaders of its fallback", m.name),
+                });
+                return true;
+            }
+        }
+
+        changes.push(Change {
+            check: Check::MissingShader,
+            message: format!("dropped {}, it references a missing shader", m.name),
+        });
+        false
+    });
+    report.changes.append(&mut changes);
+
+    //--------------------------------------------------------------------------
+    // Composite hashes
+
+    let mut hashes: HashSet<u64> = cache.materials.iter().map(|m| m.hash).collect();
+    for m in cache.materials.iter_mut() {
+        let Ok(desc) = m.decode_desc() else {
+            continue;
+        };
+        let expected = desc.encode_material_hash(m.material_name());
+
+        // Never create a duplicate
+        if expected != m.hash && !hashes.contains(&expected) {
+            report.push(Check::TechniqueHash, format!("rehashed {} from [{:016X}] to [{:016X}]", m.name, m.hash, expected));
+            hashes.remove(&m.hash);
+            hashes.insert(expected);
+            m.hash = expected;
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    // Footer
+
+    let mut layout = cache.layout();
+    layout.timestamp = cache.info.timestamp;
+    layout.unknown_hash = cache.info.unknown_hash;
+
+    let before = validate(cache);
+    for finding in before.findings.iter().filter(|f| f.check == Check::Footer) {
+        report.push(Check::Footer, format!("recomputed {}", finding.message.trim_start_matches("footer ")));
+    }
+    cache.info = layout;
+
+    report.remaining = validate(cache);
+    report
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::bundle::dyn_cache::*;
+    use crate::material::TechniqueDesc;
+    use crate::rtti_types::cname::CName;
+    use crate::test_util::{desc, shader, technique_with, test_cache};
+
+    use super::*;
+
+    fn technique(index: u32, fallback_index: u8, vs_hash: u64, ps_hash: u64) -> MaterialChunk {
+        technique_with("metal_base", &TechniqueDesc { fallback_index, ..desc(index) }, vs_hash, ps_hash)
+    }
+
+    fn broken_cache() -> DynamicCacheFile {
+        let mut cache = test_cache();
+        // Stale footer
+        cache.info = InfoBlock::default();
+        cache.shaders.push(shader(1));
+        cache.materials = vec![
+            technique(1, 0, 1, 2),
+            // Missing pixel shader, falls back to index 1
+            technique(2, 1, 1, 3),
+            // Missing vertex shader, no fallback
+            technique(3, 0, 4, 2),
+            technique(1, 0, 1, 2),
+        ];
+        cache
+    }
+
+    #[test]
+    fn drop_dangling() {
+        let mut cache = broken_cache();
+        let report = repair(&mut cache, RepairOptions::default());
+
+        assert_eq!(cache.shaders.len(), 2);
+        assert_eq!(cache.materials.len(), 1);
+        assert!(report.changes.iter().any(|c| c.check == Check::DuplicateShader));
+        assert!(report.changes.iter().any(|c| c.check == Check::DuplicateTechnique));
+        assert_eq!(report.changes.iter().filter(|c| c.check == Check::MissingShader).count(), 2);
+        assert!(report.changes.iter().any(|c| c.check == Check::Footer));
+        assert!(report.remaining.findings.is_empty(), "{}", report.remaining);
+    }
+
+    #[test]
+    fn redirect_dangling() {
+        let mut cache = broken_cache();
+        let report = repair(&mut cache, RepairOptions { redirect: true, ..Default::default() });
+
+        assert_eq!(cache.materials.len(), 2);
+        assert_eq!((cache.materials[1].vs_hash, cache.materials[1].ps_hash), (1, 2));
+        assert!(report.remaining.is_ok());
+    }
+
+    #[test]
+    fn rehash() {
+        let mut cache = broken_cache();
+        cache.materials.truncate(1);
+        cache.materials[0].hash ^= 1;
+
+        let report = repair(&mut cache, RepairOptions::default());
+
+        assert!(report.changes.iter().any(|c| c.check == Check::TechniqueHash));
+        assert!(report.remaining.is_ok());
+    }
+
+    #[test]
+    fn malformed_name() {
+        let mut cache = broken_cache();
+        let mut garbage = technique(4, 0, 1, 2);
+        garbage.name = CName::new("metal_base not a technique");
+        cache.materials.push(garbage.clone());
+        // Dangling, so redirect has to look at its name
+        garbage.hash ^= 1;
+        garbage.ps_hash = 3;
+        cache.materials.push(garbage);
+
+        let report = repair(&mut cache, RepairOptions { redirect: true, ..Default::default() });
+
+        assert_eq!(cache.materials.len(), 3);
+        assert!(report.remaining.has(Check::TechniqueName));
+    }
+
+    #[test]
+    fn resign() {
+        let mut cache = broken_cache();
+        cache.shaders[1].compiled = crate::container::Container::default().to_bytes();
+
+        let report = repair(&mut cache.clone(), RepairOptions::default());
+        assert!(!report.changes.iter().any(|c| c.check == Check::ShaderDigest));
+        assert!(report.remaining.has(Check::ShaderDigest));
+
+        let report = repair(&mut cache, RepairOptions { resign: true, ..Default::default() });
+
+        assert!(report.changes.iter().any(|c| c.check == Check::ShaderDigest));
+        assert!(digest::verify_digest(&cache.shaders[1].compiled));
+        assert!(!report.remaining.has(Check::ShaderDigest));
+    }
+}
@@ -0,0 +1,339 @@
+use fnv_rs::{Fnv64, FnvHasher};
+
+use crate::container::Container;
+use crate::digest;
+use crate::reflection::{Reflection, ResourceDimension};
+use crate::shader::ShaderType;
+use crate::signature::{self, Signature};
+use crate::validate::{Check, Finding, Severity};
+
+/// Outcome of swapping the blob of one technique's shader
+pub struct ReplaceReport {
+    /// Shader the technique uses now
+    pub hash: u64,
+    /// The original was shared, the technique got a copy and the others keep the original
+    pub copied: bool,
+    /// Warnings, and the errors that were overridden with `force`
+    pub findings: Vec<Finding>,
+}
+
+impl std::fmt::Display for ReplaceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let how = if self.copied { "copied to" } else { "replaced in place as" };
+        writeln!(f, "Shader {} [{:016X}]", how, self.hash)?;
+        for finding in &self.findings {
+            writeln!(f, "{}", finding)?;
+        }
+        Ok(())
+    }
+}
+
+/// What a replacement blob is checked against
+pub struct ReplaceTarget<'a> {
+    pub stage: ShaderType,
+    pub original: &'a [u8],
+    /// The technique's other shader, the PS when replacing the VS and the other way around
+    pub counterpart: Option<&'a [u8]>,
+    /// Vertex shaders used with the technique's vertex factory, for the inputs it provides
+    pub vertex_shaders: Vec<&'a [u8]>,
+}
+
+fn finding(severity: Severity, check: Check, message: String) -> Finding {
+    Finding { severity, check, message }
+}
+
+fn signatures(data: &[u8]) -> Option<(Signature, Signature)> {
+    let container = Container::parse(data).ok()?;
+    let input = Signature::input(&container)?.ok()?;
+    let output = Signature::output(&container)?.ok()?;
+    Some((input, output))
+}
+
+/// Checks a replacement blob against the shader it replaces and the stages around it.
+///
+/// Anything that would fail to link or read unbound resources is an error, what
+/// can't be compared is a warning.
+pub fn check_replacement(target: &ReplaceTarget, compiled: &[u8]) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = Vec::new();
+
+    match ShaderType::detect(compiled) {
+        ShaderType::Unknown => findings.push(finding(
+            Severity::Warning, Check::ShaderStage, "The replacement's stage couldn't be read".to_string()
+        )),
+        kind if kind != target.stage => findings.push(finding(
+            Severity::Error, Check::ShaderStage, format!("Replacing a {} shader with a {} shader", target.stage, kind)
+        )),
+        _ => {},
+    }
+
+    if Container::is_container(compiled) && !digest::verify_digest(compiled) {
+        findings.push(finding(
+            Severity::Warning, Check::ShaderDigest, "The replacement's digest is stale, save with re-signing".to_string()
+        ));
+    }
+
+    check_signatures(target, compiled, &mut findings);
+    check_bindings(target.original, compiled, &mut findings);
+
+    findings
+}
+
+fn check_signatures(target: &ReplaceTarget, compiled: &[u8], findings: &mut Vec<Finding>) {
+    let Some((input, output)) = signatures(compiled) else {
+        findings.push(finding(
+            Severity::Warning, Check::Signature, "The replacement's signatures couldn't be read".to_string()
+        ));
+        return;
+    };
+    let counterpart = target.counterpart.and_then(signatures);
+    let mut error = |context: &str, issue: String| {
+        findings.push(finding(Severity::Error, Check::Signature, format!("{}: {}", context, issue)));
+    };
+
+    match target.stage {
+        ShaderType::Vertex => {
+            // Every vertex shader of the factory reads a subset of the vertex layout
+            let provided = Signature {
+                elements: target.vertex_shaders.iter()
+                    .filter_map(|s| signatures(s))
+                    .flat_map(|(input, _)| input.elements)
+                    .collect(),
+            };
+            if !provided.elements.is_empty() {
+                for issue in signature::link(&provided, &input, false) {
+                    error("Vertex input", issue);
+                }
+            }
+            if let Some((ps_input, _)) = &counterpart {
+                for issue in signature::link(&output, ps_input, true) {
+                    error("Pixel shader input", issue);
+                }
+                for issue in signature::link_modes(&output, ps_input) {
+                    findings.push(finding(Severity::Warning, Check::Signature, format!("Pixel shader input: {}", issue)));
+                }
+            }
+        },
+        ShaderType::Pixel => {
+            if let Some((_, vs_output)) = &counterpart {
+                for issue in signature::link(vs_output, &input, true) {
+                    error("Vertex shader output", issue);
+                }
+                for issue in signature::link_modes(vs_output, &input) {
+                    findings.push(finding(Severity::Warning, Check::Signature, format!("Vertex shader output: {}", issue)));
+                }
+            }
+            // Render targets are bound for the original, extra or missing ones aren't fatal
+            if let Some((_, original)) = signatures(target.original) {
+                for issue in signature::link(&output, &original, true) {
+                    findings.push(finding(Severity::Warning, Check::Signature, format!("Pixel output: {}", issue)));
+                }
+            }
+        },
+        _ => {},
+    }
+}
+
+fn check_bindings(original: &[u8], compiled: &[u8], findings: &mut Vec<Finding>) {
+    let reflect = |data: &[u8]| Reflection::from_blob(data).and_then(Result::ok);
+    let (Some(original), Some(new)) = (reflect(original), reflect(compiled)) else {
+        findings.push(finding(
+            Severity::Warning, Check::ResourceBinding, "Resource bindings couldn't be compared".to_string()
+        ));
+        return;
+    };
+    let mut error = |message: String| findings.push(finding(Severity::Error, Check::ResourceBinding, message));
+
+    for b in &new.bindings {
+        let Some(o) = original.bindings.iter()
+            .find(|o| (o.class, o.space, o.register) == (b.class, b.space, b.register)) else {
+            error(format!("{:?} {} at register {} space {} isn't bound for the original", b.class, b.name, b.register, b.space));
+            continue;
+        };
+
+        let known = |d: ResourceDimension| d != ResourceDimension::Unknown;
+        if known(b.dimension) && known(o.dimension) && b.dimension != o.dimension {
+            error(format!("{} is a {:?} but the original binds a {:?}", b.name, b.dimension, o.dimension));
+        }
+        if let (Some(size), Some(bound)) = (b.size, o.size) {
+            if size > bound {
+                error(format!("{} is {} bytes but only {} are bound", b.name, size, bound));
+            }
+        }
+        for v in &b.variables {
+            if let Some(ov) = o.variables.iter().find(|ov| ov.name == v.name && ov.offset != v.offset) {
+                error(format!("{}.{} is at offset {} but the original has it at {}", b.name, v.name, v.offset, ov.offset));
+            }
+        }
+    }
+}
+
+/// Hash for a copy of a shader, from the blob and distinct from every taken hash
+pub fn copy_hash<F: Fn(u64) -> bool>(compiled: &[u8], taken: F) -> u64 {
+    let mut hasher = Fnv64::new();
+    hasher.update(compiled);
+    let mut hash: u64 = hasher.into();
+    while hash == 0 || taken(hash) {
+        hash = hash.wrapping_add(1);
+    }
+    hash
+}
+
+/// Errors, unless forced, turn into a failure listing every finding
+pub fn refuse(findings: &[Finding], force: bool) -> anyhow::Result<()> {
+    let errors = findings.iter().filter(|f| f.severity == Severity::Error).count();
+    if errors == 0 || force {
+        return Ok(());
+    }
+
+    let list: Vec<String> = findings.iter().map(|f| f.to_string()).collect();
+    anyhow::bail!("Replacement is incompatible ({} errors):\n{}", errors, list.join("\n"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::container::FourCC;
+    use crate::manager::Manager;
+    use crate::test_util::{isg1, rdef, test_cache};
+
+    use super::*;
+
+    type Element = (&'static str, u32, u32, u32, u32, u8);
+
+    const POSITION: Element = ("SV_Position", 0, 1, 3, 0, 0b1111);
+    const UV0: Element = ("TEXCOORD", 0, 0, 3, 1, 0b0011);
+    const UV1: Element = ("TEXCOORD", 1, 0, 3, 1, 0b1100);
+    const TARGET: Element = ("SV_Target", 0, 64, 3, 0, 0b1111);
+
+    /// Shader model 5 container, `kind` is 0 for pixel and 1 for vertex shaders
+    fn blob(kind: u32, input: &[Element], output: &[Element], variables: &[(&str, u32, u32)]) -> Vec<u8> {
+        let mut container = Container::default();
+        container.set_part(FourCC::SHEX, ((kind << 16) | 0x50).to_le_bytes().to_vec());
+        container.set_part(FourCC::ISG1, isg1(input));
+        container.set_part(FourCC::OSG1, isg1(output));
+        container.set_part(FourCC::RDEF, rdef(variables, &[0]));
+        let mut data = container.to_bytes();
+        digest::resign(&mut data);
+        data
+    }
+
+    fn vs() -> Vec<u8> {
+        blob(1, &[("POSITION", 0, 0, 3, 0, 0b0111)], &[POSITION, UV0], &[("World", 0, 64)])
+    }
+
+    fn ps(input: &[Element], variables: &[(&str, u32, u32)]) -> Vec<u8> {
+        blob(0, input, &[TARGET], variables)
+    }
+
+    fn errors(findings: &[Finding]) -> Vec<&str> {
+        findings.iter().filter(|f| f.severity == Severity::Error).map(|f| f.message.as_str()).collect()
+    }
+
+    #[test]
+    fn compatibility() {
+        let vs = vs();
+        let original = ps(&[POSITION, UV0], &[("Color", 0, 16), ("Tint", 16, 16)]);
+        let target = ReplaceTarget {
+            stage: ShaderType::Pixel,
+            original: &original,
+            counterpart: Some(&vs),
+            vertex_shaders: Vec::new(),
+        };
+
+        // Reading less is fine
+        assert!(check_replacement(&target, &ps(&[UV0], &[("Color", 0, 16)])).is_empty());
+
+        let findings = check_replacement(&target, &ps(&[POSITION, UV0, UV1], &[("Color", 0, 16), ("Tint", 32, 16)]));
+        assert_eq!(errors(&findings), vec![
+            "Vertex shader output: float2 TEXCOORD1 isn't provided",
+            "Material is 48 bytes but only 32 are bound",
+            "Material.Tint is at offset 32 but the original has it at 16",
+        ]);
+
+        let findings = check_replacement(&target, &vs);
+        assert!(findings.iter().any(|f| f.check == Check::ShaderStage && f.severity == Severity::Error));
+
+        let findings = check_replacement(&target, b"not a shader");
+        assert!(errors(&findings).is_empty());
+        assert_eq!(findings.len(), 3);
+    }
+
+    #[test]
+    fn vertex_inputs() {
+        let vs = vs();
+        let original_ps = ps(&[POSITION, UV0], &[]);
+        let target = ReplaceTarget {
+            stage: ShaderType::Vertex,
+            original: &vs,
+            counterpart: Some(&original_ps),
+            vertex_shaders: vec![ &vs ],
+        };
+
+        let new = blob(1, &[("POSITION", 0, 0, 3, 0, 0b0111), ("NORMAL", 0, 0, 3, 1, 0b0111)], &[POSITION], &[("World", 0, 64)]);
+        assert_eq!(errors(&check_replacement(&target, &new)), vec![
+            "Vertex input: float3 NORMAL isn't provided",
+            "Pixel shader input: float2 TEXCOORD isn't provided",
+        ]);
+    }
+
+    #[test]
+    fn copy_on_write() {
+        let mut cache = test_cache();
+        cache.shaders[0].compiled = vs();
+        cache.shaders[1].compiled = ps(&[POSITION, UV0], &[("Color", 0, 16)]);
+        let desc = cache.materials[0].decode_desc().unwrap();
+
+        // The PS is only used by the first technique
+        let new_ps = ps(&[UV0], &[("Color", 0, 16)]);
+        let report = cache.replace_shader("metal_base", &desc, ShaderType::Pixel, new_ps.clone(), false).unwrap();
+        assert!(!report.copied);
+        assert_eq!(report.hash, 2);
+        assert_eq!(cache.shaders[1].compiled, new_ps);
+
+        // The VS is shared with the second, which keeps the original
+        let new_vs = blob(1, &[("POSITION", 0, 0, 3, 0, 0b0111)], &[POSITION, UV0, UV1], &[("World", 0, 64)]);
+        let report = cache.replace_shader("metal_base", &desc, ShaderType::Vertex, new_vs.clone(), false).unwrap();
+        assert!(report.copied);
+        assert_eq!(cache.shaders.len(), 3);
+        assert_eq!(cache.materials[0].vs_hash, report.hash);
+        assert_eq!(cache.materials[1].vs_hash, 1);
+        assert_eq!(cache.shaders[1].compiled, new_vs);
+        assert_eq!(cache.shaders[1].params, cache.shaders[0].params);
+
+        // Refused unless forced
+        let broken = ps(&[("COLOR", 0, 0, 3, 2, 0b1111)], &[]);
+        assert!(cache.replace_shader("metal_base", &desc, ShaderType::Pixel, broken.clone(), false).is_err());
+        assert_eq!(cache.shaders[2].compiled, new_ps);
+        let report = cache.replace_shader("metal_base", &desc, ShaderType::Pixel, broken, true).unwrap();
+        assert!(report.findings.iter().any(|f| f.severity == Severity::Error));
+
+        assert!(cache.replace_shader("metal_base", &desc, ShaderType::Compute, vs(), true).is_err());
+    }
+
+    #[test]
+    fn manager() {
+        let mut cache = test_cache();
+        cache.shaders[0].compiled = vs();
+        cache.shaders[1].compiled = ps(&[POSITION, UV0], &[]);
+        cache.info = cache.layout();
+        let desc = cache.materials[0].decode_desc().unwrap();
+        let other = cache.materials[1].decode_desc().unwrap();
+
+        let mut manager = Manager::from_dyn_cache(cache).unwrap();
+        let report = manager.replace_shader("metal_base", &desc, ShaderType::Vertex, vs(), false).unwrap();
+        assert!(report.copied);
+        assert_eq!(manager.shaders.len(), 3);
+
+        let material = Rc::clone(manager.materials.values().next().unwrap());
+        let replaced = material.find_technique(&desc).unwrap().vs.as_ref().unwrap();
+        assert_eq!(replaced.hash, report.hash);
+        assert_eq!(replaced.kind, ShaderType::Vertex);
+        assert_eq!(material.find_technique(&other).unwrap().vs.as_ref().unwrap().hash, 1);
+
+        let report = manager.replace_shader("metal_base", &desc, ShaderType::Pixel, ps(&[UV0], &[]), false).unwrap();
+        assert!(!report.copied);
+        assert_eq!(manager.shaders.len(), 3);
+    }
+}
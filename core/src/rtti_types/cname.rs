@@ -8,7 +8,7 @@ use crate::bundle::decode::{Decode, DecodeExt};
 use crate::bundle::encode::{Encode, EncodeExt};
 use crate::rtti_types::vlqint32::VLQInt32;
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
 pub struct CName(String);
 
 impl CName {
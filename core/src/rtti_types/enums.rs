@@ -3,7 +3,7 @@
 #![allow(non_snake_case)]
 
 use enum_try_from::impl_enum_try_from;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use strum_macros::{Display, EnumString};
 use thiserror::Error;
 
@@ -16,7 +16,7 @@ pub enum EnumError {
 
 impl_enum_try_from!(
     #[repr(u8)]
-    #[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize)]
+    #[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize, Deserialize)]
     pub enum ETextureFilteringMin {
         Point          = 0,
         Linear         = 1,
@@ -31,7 +31,7 @@ impl_enum_try_from!(
 
 impl_enum_try_from!(
     #[repr(u8)]
-    #[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize)]
+    #[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize, Deserialize)]
     pub enum ETextureFilteringMag {
         Point   = 0,
         Linear  = 1,
@@ -44,7 +44,7 @@ impl_enum_try_from!(
 
 impl_enum_try_from!(
     #[repr(u8)]
-    #[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize)]
+    #[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize, Deserialize)]
     pub enum ETextureFilteringMip {
         None    = 0,
         Point   = 1,
@@ -58,7 +58,7 @@ impl_enum_try_from!(
 
 impl_enum_try_from!(
     #[repr(u8)]
-    #[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize)]
+    #[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize, Deserialize)]
     pub enum ETextureAddressing {
         Wrap       = 0,
         Mirror     = 1,
@@ -74,7 +74,7 @@ impl_enum_try_from!(
 
 impl_enum_try_from!(
     #[repr(u8)]
-    #[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize)]
+    #[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize, Deserialize)]
     pub enum ETextureComparisonFunction {
         None         = 0,
         Less         = 1,
@@ -93,7 +93,7 @@ impl_enum_try_from!(
 
 impl_enum_try_from!(
     #[repr(u8)]
-    #[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize)]
+    #[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize, Deserialize)]
     pub enum EMaterialModifier {
         HitProxy                = 0,
         WindData                = 1,
@@ -137,7 +137,7 @@ impl_enum_try_from!(
 
 impl_enum_try_from!(
     #[repr(u8)]
-    #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Display, Serialize, EnumString, Hash)]
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Display, Serialize, Deserialize, EnumString, Hash)]
     pub enum EMaterialVertexFactory
     {
         Invalid                             = 0,
@@ -189,7 +189,7 @@ impl Default for EMaterialVertexFactory {
 
 impl_enum_try_from!(
     #[repr(u8)]
-    #[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize)]
+    #[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize, Deserialize)]
     pub enum EFeatureFlag
 	{
 		Default                     =  0,
@@ -8,7 +8,7 @@ use crate::bundle::decode::{Decode, DecodeExt};
 use crate::bundle::encode::{Encode, EncodeExt};
 use crate::rtti_types::enums::*;
 
-#[derive(Clone, Copy, Serialize)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize)]
 pub struct SampleStateInfo {
     pub filteringMin: ETextureFilteringMin,
     pub filteringMag: ETextureFilteringMag,
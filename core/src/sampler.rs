@@ -0,0 +1,246 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::bundle::dyn_cache::{DynamicCacheFile, MaterialChunk};
+use crate::material::{Technique, TechniqueFilter};
+use crate::renderstage::{check_passes, unknown_stages};
+use crate::rtti_types::enums::*;
+use crate::rtti_types::structs::SampleStateInfo;
+use crate::shader::ShaderType;
+
+/// Selects which samplers a rule applies to.
+/// Every matcher is a list of accepted values, an empty list accepts anything.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SamplerFilter {
+    #[serde(flatten)]
+    pub technique: TechniqueFilter,
+    /// Only `ShaderType::Vertex` and `ShaderType::Pixel` have samplers
+    pub stages: Vec<ShaderType>,
+    pub registers: Vec<u8>,
+    pub filtering_min: Vec<ETextureFilteringMin>,
+    pub filtering_mag: Vec<ETextureFilteringMag>,
+    pub filtering_mip: Vec<ETextureFilteringMip>,
+    /// Matches if any of the U, V or W addressing modes match
+    pub addressing: Vec<ETextureAddressing>,
+    pub comparison_func: Vec<ETextureComparisonFunction>,
+}
+
+fn accepts<T: PartialEq>(list: &[T], value: &T) -> bool {
+    list.is_empty() || list.contains(value)
+}
+
+impl SamplerFilter {
+    /// True if the filter accepts every sampler
+    pub fn is_empty(&self) -> bool {
+        self.technique.is_empty()
+        && self.stages.is_empty()
+        && self.registers.is_empty()
+        && self.filtering_min.is_empty()
+        && self.filtering_mag.is_empty()
+        && self.filtering_mip.is_empty()
+        && self.addressing.is_empty()
+        && self.comparison_func.is_empty()
+    }
+
+    pub fn matches_sampler(&self, stage: ShaderType, s: &SampleStateInfo) -> bool {
+        accepts(&self.stages, &stage)
+        && accepts(&self.registers, &s.register)
+        && accepts(&self.filtering_min, &s.filteringMin)
+        && accepts(&self.filtering_mag, &s.filteringMag)
+        && accepts(&self.filtering_mip, &s.filteringMip)
+        && accepts(&self.comparison_func, &s.comparisonFunc)
+        && (self.addressing.is_empty()
+            || [s.addressU, s.addressV, s.addressW].iter().any(|a| self.addressing.contains(a)))
+    }
+}
+
+/// New values for the matched samplers, `None` leaves the field untouched
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SamplerSetter {
+    pub filtering_min: Option<ETextureFilteringMin>,
+    pub filtering_mag: Option<ETextureFilteringMag>,
+    pub filtering_mip: Option<ETextureFilteringMip>,
+    pub address_u: Option<ETextureAddressing>,
+    pub address_v: Option<ETextureAddressing>,
+    pub address_w: Option<ETextureAddressing>,
+    pub comparison_func: Option<ETextureComparisonFunction>,
+    pub register: Option<u8>,
+}
+
+impl SamplerSetter {
+    /// Returns true if the sampler state was actually changed
+    pub fn apply(&self, s: &mut SampleStateInfo) -> bool {
+        let old = *s;
+
+        if let Some(v) = self.filtering_min   { s.filteringMin = v; }
+        if let Some(v) = self.filtering_mag   { s.filteringMag = v; }
+        if let Some(v) = self.filtering_mip   { s.filteringMip = v; }
+        if let Some(v) = self.address_u       { s.addressU = v; }
+        if let Some(v) = self.address_v       { s.addressV = v; }
+        if let Some(v) = self.address_w       { s.addressW = v; }
+        if let Some(v) = self.comparison_func { s.comparisonFunc = v; }
+        if let Some(v) = self.register        { s.register = v; }
+
+        old != *s
+    }
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SamplerRule {
+    pub name: String,
+    #[serde(flatten)]
+    pub filter: SamplerFilter,
+    pub set: SamplerSetter,
+}
+
+impl SamplerRule {
+    /// Applies the rule to a single material technique, returning the number of samplers changed
+    pub fn apply_chunk(&self, chunk: &mut MaterialChunk) -> Result<usize> {
+        let technique = &self.filter.technique;
+        if !technique.matches_material(chunk.material_name()) {
+            return Ok(0);
+        }
+        if technique.needs_desc() && !technique.matches_desc(&chunk.decode_desc()?) {
+            return Ok(0);
+        }
+
+        Ok(self.apply_samplers(&mut chunk.vs_samplers, &mut chunk.ps_samplers))
+    }
+
+    /// Applies the rule to a technique of a `Manager` material, returning the number of samplers changed
+    pub fn apply_technique(&self, material: &str, technique: &mut Technique) -> usize {
+        if !self.filter.technique.matches(material, &technique.desc) {
+            return 0;
+        }
+        self.apply_samplers(&mut technique.vs_samplers, &mut technique.ps_samplers)
+    }
+
+    fn apply_samplers(&self, vs_samplers: &mut [SampleStateInfo], ps_samplers: &mut [SampleStateInfo]) -> usize {
+        let mut changed: usize = 0;
+
+        for (stage, samplers) in [(ShaderType::Vertex, vs_samplers), (ShaderType::Pixel, ps_samplers)] {
+            for s in samplers.iter_mut() {
+                if self.filter.matches_sampler(stage, s) && self.set.apply(s) {
+                    changed += 1;
+                }
+            }
+        }
+
+        changed
+    }
+}
+
+/// Number of samplers changed by each rule, in rule order
+pub struct SamplerReport {
+    pub changed: Vec<(String, usize)>,
+}
+
+impl SamplerReport {
+    pub fn total(&self) -> usize {
+        self.changed.iter().map(|(_, n)| n).sum()
+    }
+}
+
+impl std::fmt::Display for SamplerReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, count) in &self.changed {
+            writeln!(f, "{}: {} samplers changed", name, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Applies the rules in order to every technique in the cache.
+/// Later rules see the changes made by earlier ones.
+pub fn apply_sampler_rules(cache: &mut DynamicCacheFile, rules: &[SamplerRule]) -> Result<SamplerReport> {
+    let mut changed: Vec<(String, usize)> = Vec::with_capacity(rules.len());
+
+    for rule in rules {
+        check_passes(&rule.filter.technique.passes, || unknown_stages(cache))
+            .with_context(|| format!("Sampler rule {}", rule.name))?;
+
+        let mut count: usize = 0;
+        for chunk in cache.materials.iter_mut() {
+            count += rule.apply_chunk(chunk)?;
+        }
+        changed.push((rule.name.clone(), count));
+    }
+
+    Ok(SamplerReport { changed })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::glob::Glob;
+    use crate::renderstage::RenderStage;
+    use crate::test_util::{self, technique};
+
+    use super::*;
+
+    fn sampler(filtering_min: ETextureFilteringMin, register: u8) -> SampleStateInfo {
+        SampleStateInfo { filteringMin: filtering_min, ..test_util::sampler(register) }
+    }
+
+    fn chunk(material: &str) -> MaterialChunk {
+        MaterialChunk {
+            vs_samplers: vec![ sampler(ETextureFilteringMin::Linear, 0) ],
+            ps_samplers: vec![
+                sampler(ETextureFilteringMin::Linear, 0),
+                sampler(ETextureFilteringMin::AnisotropicLow, 1),
+                sampler(ETextureFilteringMin::Anisotropic, 2),
+                sampler(ETextureFilteringMin::Point, 3),
+            ],
+            ..technique(material, 0, 0, 0)
+        }
+    }
+
+    fn aniso_rule() -> SamplerRule {
+        SamplerRule {
+            name: String::from("aniso"),
+            filter: SamplerFilter {
+                filtering_min: vec![ ETextureFilteringMin::Linear, ETextureFilteringMin::AnisotropicLow ],
+                ..Default::default()
+            },
+            set: SamplerSetter {
+                filtering_min: Some(ETextureFilteringMin::Anisotropic),
+                ..Default::default()
+            }
+        }
+    }
+
+    #[test]
+    fn counts_changes() {
+        let mut c = chunk("metal_base");
+        assert_eq!(aniso_rule().apply_chunk(&mut c).unwrap(), 3);
+        assert_eq!(c.ps_samplers[1].filteringMin, ETextureFilteringMin::Anisotropic);
+        assert_eq!(c.ps_samplers[3].filteringMin, ETextureFilteringMin::Point);
+
+        // Already applied, nothing left to change
+        assert_eq!(aniso_rule().apply_chunk(&mut c).unwrap(), 0);
+    }
+
+    #[test]
+    fn filters() {
+        let mut rule = aniso_rule();
+        rule.filter.stages = vec![ ShaderType::Pixel ];
+        rule.filter.registers = vec![ 0, 3 ];
+        assert_eq!(rule.apply_chunk(&mut chunk("metal_base")).unwrap(), 1);
+
+        let mut rule = aniso_rule();
+        rule.filter.technique.materials = vec![ Glob::new("glass_*").unwrap() ];
+        assert_eq!(rule.apply_chunk(&mut chunk("metal_base")).unwrap(), 0);
+
+        let mut rule = aniso_rule();
+        rule.filter.technique.passes = vec![ RenderStage::GbufferRegular ];
+        rule.filter.technique.vertex_factories = vec![ EMaterialVertexFactory::MeshStatic ];
+        assert_eq!(rule.apply_chunk(&mut chunk("metal_base")).unwrap(), 3);
+
+        let mut rule = aniso_rule();
+        rule.filter.addressing = vec![ ETextureAddressing::Border ];
+        assert_eq!(rule.apply_chunk(&mut chunk("metal_base")).unwrap(), 0);
+    }
+}
@@ -1,22 +1,114 @@
+use std::io;
+
 use enum_try_from::impl_enum_try_from;
+use serde::Deserialize;
 use strum_macros::{Display, EnumString};
 
 use crate::bundle::dyn_cache::ShaderChunk;
+use crate::container::{Container, ProgramKind};
+use crate::debuginfo::DebugInfo;
+use crate::reflection::Reflection;
+use crate::spirv::{ExecutionModel, SpirvModule};
+use crate::stats::ShaderStats;
 use crate::rtti_types::cname::CName;
 use crate::rtti_types::enums::{EMaterialModifier, EnumError};
 
-#[derive(Clone, Copy, Display, EnumString)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Display, EnumString, Deserialize)]
 pub enum ShaderType {
     Unknown = 0,
     Vertex,
     Pixel,
     Compute,
-    Raytrace
+    /// Libraries and every ray tracing stage
+    Raytrace,
+    Hull,
+    Domain,
+    Geometry,
+    Mesh,
+    Amplification,
+}
+
+impl ShaderType {
+    /// Stage from the program version of a container, or the first entry point of a
+    /// SPIR-V module. `Unknown` if the blob is neither or can't be read.
+    pub fn detect(data: &[u8]) -> Self {
+        match BlobFormat::detect(data) {
+            BlobFormat::Container => Container::parse(data).ok()
+                .and_then(|c| c.program_version())
+                .map_or(ShaderType::Unknown, |v| ShaderType::from(v.kind)),
+            BlobFormat::Spirv => SpirvModule::parse(data).ok()
+                .and_then(|m| m.entry_points.first().map(|e| ShaderType::from(e.model)))
+                .unwrap_or(ShaderType::Unknown),
+            BlobFormat::Unknown => ShaderType::Unknown,
+        }
+    }
+}
+
+impl From<ProgramKind> for ShaderType {
+    fn from(value: ProgramKind) -> Self {
+        match value {
+            ProgramKind::Pixel => ShaderType::Pixel,
+            ProgramKind::Vertex => ShaderType::Vertex,
+            ProgramKind::Geometry => ShaderType::Geometry,
+            ProgramKind::Hull => ShaderType::Hull,
+            ProgramKind::Domain => ShaderType::Domain,
+            ProgramKind::Compute => ShaderType::Compute,
+            ProgramKind::Library
+            | ProgramKind::RayGeneration
+            | ProgramKind::Intersection
+            | ProgramKind::AnyHit
+            | ProgramKind::ClosestHit
+            | ProgramKind::Miss
+            | ProgramKind::Callable => ShaderType::Raytrace,
+            ProgramKind::Mesh => ShaderType::Mesh,
+            ProgramKind::Amplification => ShaderType::Amplification,
+            ProgramKind::Unknown(_) => ShaderType::Unknown,
+        }
+    }
+}
+
+impl From<ExecutionModel> for ShaderType {
+    fn from(value: ExecutionModel) -> Self {
+        match value {
+            ExecutionModel::Vertex => ShaderType::Vertex,
+            ExecutionModel::TessellationControl => ShaderType::Hull,
+            ExecutionModel::TessellationEvaluation => ShaderType::Domain,
+            ExecutionModel::Geometry => ShaderType::Geometry,
+            ExecutionModel::Fragment => ShaderType::Pixel,
+            ExecutionModel::GLCompute | ExecutionModel::Kernel => ShaderType::Compute,
+            ExecutionModel::Task => ShaderType::Amplification,
+            ExecutionModel::Mesh => ShaderType::Mesh,
+            // Ray tracing models are all above 5000
+            ExecutionModel::Other(v) if (5313..=5318).contains(&v) => ShaderType::Raytrace,
+            ExecutionModel::Other(_) => ShaderType::Unknown,
+        }
+    }
+}
+
+/// What a compiled blob holds
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Display)]
+pub enum BlobFormat {
+    /// DXBC or DXIL, see `Container`
+    Container,
+    Spirv,
+    Unknown,
+}
+
+impl BlobFormat {
+    pub fn detect(data: &[u8]) -> Self {
+        if Container::is_container(data) {
+            BlobFormat::Container
+        } else if SpirvModule::is_spirv(data) {
+            BlobFormat::Spirv
+        } else {
+            BlobFormat::Unknown
+        }
+    }
 }
 
 impl_enum_try_from!(
     #[repr(u8)]
-    #[derive(Clone, Copy)]
+    #[derive(Clone, Copy, Debug)]
     pub enum ShaderParamType {
         Vector = 1,
         Matrix = 4,
@@ -33,7 +125,15 @@ pub struct Shader {
     /// Bitmask of supported EMaterialModifier values
     pub mat_mod_mask: u32,
     pub params: Vec<ShaderParam>,
+    /// Hash of the params chunk the shader was loaded with
+    pub params_hash: u64,
     pub compiled: Vec<u8>,
+    /// Resource bindings, if the blob has readable reflection data
+    pub reflection: Option<Reflection>,
+    /// Instruction counts, if the blob has a readable program
+    pub stats: Option<ShaderStats>,
+    /// Debug name and source files, if the blob carries them
+    pub debug: Option<DebugInfo>,
 }
 
 #[derive(Clone)]
@@ -46,6 +146,18 @@ pub struct ShaderParam {
 
 
 impl Shader {
+    pub fn format(&self) -> BlobFormat {
+        BlobFormat::detect(&self.compiled)
+    }
+
+    pub fn spirv(&self) -> io::Result<SpirvModule> {
+        SpirvModule::parse(&self.compiled)
+    }
+
+    pub fn container(&self) -> io::Result<Container> {
+        Container::parse(&self.compiled)
+    }
+
     fn get_supported_mat_mods(&self) -> Vec<EMaterialModifier> {
         let mut matMods: Vec<EMaterialModifier> = Vec::new();
         for i in 0..32 {
@@ -62,10 +174,14 @@ impl From<ShaderChunk> for Shader {
     fn from(value: ShaderChunk) -> Self {
         Shader {
             hash: value.hash,
-            kind: ShaderType::Unknown,
+            kind: ShaderType::detect(&value.compiled),
             mat_mod_mask: 0,
             params: Vec::new(),
-            compiled: value.compiled
+            params_hash: value.params,
+            reflection: Reflection::from_blob(&value.compiled).and_then(Result::ok),
+            stats: ShaderStats::from_blob(&value.compiled).and_then(Result::ok),
+            debug: DebugInfo::from_blob(&value.compiled).and_then(Result::ok),
+            compiled: value.compiled,
         }
     }
 }
@@ -0,0 +1,708 @@
+use std::ffi::CStr;
+use std::fmt::Write;
+use std::io::{self, Cursor};
+
+use anyhow::{bail, Result};
+use hashbrown::HashMap;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::bundle::decode::DecodeExt;
+use crate::container::{Container, FourCC};
+use crate::manager::Manager;
+use crate::rtti_types::enums::EMaterialVertexFactory;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ComponentType {
+    Unknown,
+    UInt32,
+    SInt32,
+    Float32,
+    UInt16,
+    SInt16,
+    Float16,
+    UInt64,
+    SInt64,
+    Float64,
+}
+
+impl From<u32> for ComponentType {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => ComponentType::UInt32,
+            2 => ComponentType::SInt32,
+            3 => ComponentType::Float32,
+            4 => ComponentType::UInt16,
+            5 => ComponentType::SInt16,
+            6 => ComponentType::Float16,
+            7 => ComponentType::UInt64,
+            8 => ComponentType::SInt64,
+            9 => ComponentType::Float64,
+            _ => ComponentType::Unknown,
+        }
+    }
+}
+
+impl ComponentType {
+    pub fn hlsl(&self) -> &'static str {
+        match self {
+            ComponentType::Unknown => "unknown",
+            ComponentType::UInt32  => "uint",
+            ComponentType::SInt32  => "int",
+            ComponentType::Float32 => "float",
+            ComponentType::UInt16  => "min16uint",
+            ComponentType::SInt16  => "min16int",
+            ComponentType::Float16 => "half",
+            ComponentType::UInt64  => "uint64_t",
+            ComponentType::SInt64  => "int64_t",
+            ComponentType::Float64 => "double",
+        }
+    }
+
+    /// Ignores the width, which the layouts written by hand don't track
+    fn category(&self) -> &'static str {
+        match self {
+            ComponentType::UInt32 | ComponentType::UInt16 | ComponentType::UInt64 => "uint",
+            ComponentType::SInt32 | ComponentType::SInt16 | ComponentType::SInt64 => "int",
+            ComponentType::Float32 | ComponentType::Float16 | ComponentType::Float64 => "float",
+            ComponentType::Unknown => "unknown",
+        }
+    }
+}
+
+/// D3D10_SB_INTERPOLATION_MODE, which DXIL numbers the same way
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Interpolation {
+    #[default]
+    Undefined,
+    Constant,
+    Linear,
+    LinearCentroid,
+    LinearNoPerspective,
+    LinearNoPerspectiveCentroid,
+    LinearSample,
+    LinearNoPerspectiveSample,
+}
+
+impl From<u32> for Interpolation {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => Interpolation::Constant,
+            2 => Interpolation::Linear,
+            3 => Interpolation::LinearCentroid,
+            4 => Interpolation::LinearNoPerspective,
+            5 => Interpolation::LinearNoPerspectiveCentroid,
+            6 => Interpolation::LinearSample,
+            7 => Interpolation::LinearNoPerspectiveSample,
+            _ => Interpolation::Undefined,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignatureElement {
+    pub stream: u32,
+    pub semantic: String,
+    pub semantic_index: u32,
+    /// D3D_NAME, 0 for user semantics
+    pub system_value: u32,
+    pub component_type: ComponentType,
+    pub register: u32,
+    pub mask: u8,
+    /// Always read mask for inputs, never written mask for outputs
+    pub rw_mask: u8,
+    pub min_precision: u32,
+    /// Not part of the signature parts, read from PSV0 or the DXBC declarations when present
+    pub interpolation: Interpolation,
+}
+
+impl SignatureElement {
+    pub fn components(&self) -> u32 {
+        (self.mask & 0xF).count_ones()
+    }
+
+    pub fn is_system_value(&self) -> bool {
+        self.system_value != 0
+    }
+
+    /// Generated by the input assembler or rasterizer rather than passed from the previous stage
+    pub fn is_generated(&self) -> bool {
+        GENERATED_VALUES.contains(&self.system_value)
+    }
+
+    pub fn hlsl_type(&self) -> String {
+        match self.components() {
+            0 | 1 => self.component_type.hlsl().to_string(),
+            n => format!("{}{}", self.component_type.hlsl(), n),
+        }
+    }
+
+    /// `TEXCOORD1`, the index is omitted when zero
+    pub fn full_semantic(&self) -> String {
+        if self.semantic_index == 0 {
+            self.semantic.clone()
+        } else {
+            format!("{}{}", self.semantic, self.semantic_index)
+        }
+    }
+
+    fn key(&self) -> (String, u32) {
+        (normalize_semantic(&self.semantic), self.semantic_index)
+    }
+}
+
+/// (semantic, registers, mode) of the PSV0 signature elements, which follow the
+/// resources, string table and semantic index table when the runtime info has them
+fn psv0_interpolation(data: &[u8], input: bool) -> io::Result<Vec<(String, std::ops::Range<u32>, Interpolation)>> {
+    let mut cursor = Cursor::new(data);
+    let info_size: u32 = cursor.decode()?;
+    // PSVRuntimeInfo1 added the element counts
+    if info_size < 36 {
+        return Ok(Vec::new());
+    }
+    let counts = data.get(4 + 28..4 + 31).ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+    let (inputs, outputs, patch) = (counts[0] as u64, counts[1] as u64, counts[2] as u64);
+    cursor.set_position(4 + info_size as u64);
+
+    let resources: u32 = cursor.decode()?;
+    if resources > 0 {
+        let size: u32 = cursor.decode()?;
+        cursor.set_position(cursor.position() + resources as u64 * size as u64);
+    }
+
+    let strings_size: u32 = cursor.decode()?;
+    let strings = cursor.position() as usize;
+    cursor.set_position(cursor.position() + strings_size as u64);
+    let indexes: u32 = cursor.decode()?;
+    cursor.set_position(cursor.position() + indexes as u64 * 4);
+
+    if inputs + outputs + patch == 0 {
+        return Ok(Vec::new());
+    }
+    let element_size: u32 = cursor.decode()?;
+    let start = cursor.position() + if input { 0 } else { inputs * element_size as u64 };
+
+    let mut elements = Vec::new();
+    for i in 0..if input { inputs } else { outputs } {
+        cursor.set_position(start + i * element_size as u64);
+        let name: u32 = cursor.decode()?;
+        let _indexes: u32 = cursor.decode()?;
+        let [rows, start_row, _cols, _kind, _type, mode, _, _]: [u8; 8] = cursor.decode()?;
+
+        let semantic = data.get(strings + name as usize..)
+            .and_then(|s| CStr::from_bytes_until_nul(s).ok())
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let rows = start_row as u32..start_row as u32 + rows as u32;
+        elements.push((semantic, rows, Interpolation::from(mode as u32)));
+    }
+    Ok(elements)
+}
+
+/// (register, mask, mode) of the `dcl_input_ps` declarations of a DXBC program
+fn dxbc_interpolation(data: &[u8]) -> Vec<(u32, u8, Interpolation)> {
+    const CUSTOM_DATA: u32 = 0x35;
+    const DCL_INPUT_PS: std::ops::RangeInclusive<u32> = 0x62..=0x64;
+
+    let tokens: Vec<u32> = data.chunks_exact(4).map(|t| u32::from_le_bytes(t.try_into().unwrap())).collect();
+    let mut declarations = Vec::new();
+
+    // Version and length tokens first
+    let mut pos = 2;
+    while pos < tokens.len() {
+        let opcode = tokens[pos] & 0x7FF;
+        let length = match opcode {
+            CUSTOM_DATA => tokens.get(pos + 1).copied().unwrap_or(0) as usize,
+            _ => ((tokens[pos] >> 24) & 0x7F) as usize,
+        };
+        if length == 0 {
+            break;
+        }
+
+        if DCL_INPUT_PS.contains(&opcode) {
+            let mode = Interpolation::from((tokens[pos] >> 11) & 0xF);
+            // Skip the extended opcode and operand tokens
+            let mut i = pos;
+            while tokens.get(i).is_some_and(|t| t & 0x8000_0000 != 0) {
+                i += 1;
+            }
+            let operand = i + 1;
+            let mut register = operand + 1;
+            while tokens.get(register - 1).is_some_and(|t| t & 0x8000_0000 != 0) {
+                register += 1;
+            }
+            if let (Some(op), Some(reg)) = (tokens.get(operand), tokens.get(register)) {
+                declarations.push((*reg, ((op >> 4) & 0xF) as u8, mode));
+            }
+        }
+        pos += length;
+    }
+    declarations
+}
+
+/// D3D_NAME of the vertex and instance ids, primitive id, front face, sample index,
+/// barycentrics, shading rate, coverage and inner coverage
+const GENERATED_VALUES: [u32; 9] = [6, 7, 8, 9, 10, 23, 24, 66, 70];
+
+/// Case and underscores vary between the compiler and the hand written layouts,
+/// which also spell `SV_` as `SYS_`
+fn normalize_semantic(semantic: &str) -> String {
+    let s = semantic.to_ascii_uppercase().replace('_', "");
+    match s.strip_prefix("SYS") {
+        Some(rest) => format!("SV{}", rest),
+        None => s,
+    }
+}
+
+/// Input, output or patch constant signature
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Signature {
+    pub elements: Vec<SignatureElement>,
+}
+
+impl Signature {
+    /// Parses ISGN/OSGN (24 byte elements), OSG5 (28) and ISG1/OSG1/PSG1 (32)
+    pub fn parse(fourcc: FourCC, data: &[u8]) -> io::Result<Self> {
+        let (has_stream, has_precision) = match &fourcc.0 {
+            b"ISGN" | b"OSGN" | b"PCSG" => (false, false),
+            b"OSG5" => (true, false),
+            b"ISG1" | b"OSG1" | b"PSG1" => (true, true),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a signature part", fourcc))),
+        };
+
+        let mut input = Cursor::new(data);
+        let count: u32 = input.decode()?;
+        let offset: u32 = input.decode()?;
+        input.set_position(offset as u64);
+
+        // Each element takes at least 24 bytes, a larger count can only be corrupt
+        if (count as usize).saturating_mul(24) > data.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Signature element count out of bounds"));
+        }
+
+        let mut elements: Vec<SignatureElement> = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let stream: u32 = if has_stream { input.decode()? } else { 0 };
+            let name_offset: u32 = input.decode()?;
+            let semantic_index: u32 = input.decode()?;
+            let system_value: u32 = input.decode()?;
+            let component_type: u32 = input.decode()?;
+            let register: u32 = input.decode()?;
+            let mask: u8 = input.decode()?;
+            let rw_mask: u8 = input.decode()?;
+            let _pad: u16 = input.decode()?;
+            let min_precision: u32 = if has_precision { input.decode()? } else { 0 };
+
+            let semantic = data.get(name_offset as usize..)
+                .and_then(|s| CStr::from_bytes_until_nul(s).ok())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid semantic name offset"))?
+                .to_string_lossy()
+                .into_owned();
+
+            elements.push(SignatureElement {
+                stream,
+                semantic,
+                semantic_index,
+                system_value,
+                component_type: ComponentType::from(component_type),
+                register,
+                mask,
+                rw_mask,
+                min_precision,
+                interpolation: Interpolation::Undefined,
+            });
+        }
+
+        Ok(Signature { elements })
+    }
+
+    pub fn input(container: &Container) -> Option<io::Result<Self>> {
+        let signature = Signature::find(container, &[FourCC::ISG1, FourCC::ISGN])?;
+        Some(signature.map(|s| s.with_interpolation(container, true)))
+    }
+
+    pub fn output(container: &Container) -> Option<io::Result<Self>> {
+        let signature = Signature::find(container, &[FourCC::OSG1, FourCC(*b"OSG5"), FourCC::OSGN])?;
+        Some(signature.map(|s| s.with_interpolation(container, false)))
+    }
+
+    /// Interpolation modes are best effort, elements stay `Undefined` if they can't be read
+    fn with_interpolation(mut self, container: &Container, input: bool) -> Self {
+        if let Some(p) = container.part(FourCC::PSV0) {
+            for (semantic, rows, mode) in psv0_interpolation(&p.data, input).unwrap_or_default() {
+                let semantic = normalize_semantic(&semantic);
+                for e in self.elements.iter_mut() {
+                    // System values may be left unnamed, their kind identifies them
+                    let named = semantic.is_empty() || normalize_semantic(&e.semantic) == semantic;
+                    if named && rows.contains(&e.register) {
+                        e.interpolation = mode;
+                    }
+                }
+            }
+        }
+        else if input {
+            let program = container.part(FourCC::SHEX).or_else(|| container.part(FourCC::SHDR));
+            for (register, mask, mode) in program.map(|p| dxbc_interpolation(&p.data)).unwrap_or_default() {
+                for e in self.elements.iter_mut() {
+                    if e.register == register && e.mask & mask != 0 {
+                        e.interpolation = mode;
+                    }
+                }
+            }
+        }
+        self
+    }
+
+    fn find(container: &Container, fourccs: &[FourCC]) -> Option<io::Result<Self>> {
+        fourccs.iter()
+            .find_map(|f| container.part(*f))
+            .map(|p| Signature::parse(p.fourcc, &p.data))
+    }
+
+    /// Writes the signature as an HLSL struct, one field per element
+    pub fn to_hlsl(&self, name: &str) -> String {
+        let fields: Vec<(String, String, String)> = self.elements.iter()
+            .map(|e| (e.hlsl_type(), field_name(e, &self.elements), e.full_semantic()))
+            .collect();
+
+        let type_width = fields.iter().map(|f| f.0.len()).max().unwrap_or(0);
+        let name_width = fields.iter().map(|f| f.1.len()).max().unwrap_or(0);
+
+        let mut out = String::new();
+        writeln!(out, "struct {}", name).unwrap();
+        writeln!(out, "{{").unwrap();
+        for (kind, field, semantic) in fields {
+            writeln!(out, "    {:tw$} {:nw$} : {};", kind, field, semantic, tw = type_width, nw = name_width).unwrap();
+        }
+        writeln!(out, "}}").unwrap();
+        out
+    }
+}
+
+/// `TEXCOORD` → `Texcoord`, `SV_VertexID` → `VertexID`, suffixed with the index when
+/// the semantic is used more than once
+fn field_name(element: &SignatureElement, all: &[SignatureElement]) -> String {
+    let semantic = element.semantic.strip_prefix("SV_").unwrap_or(&element.semantic);
+
+    let mut name = String::new();
+    for part in semantic.split('_').filter(|p| !p.is_empty()) {
+        let mut chars = part.chars();
+        name.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+        if part.chars().all(|c| !c.is_ascii_lowercase()) {
+            name.extend(chars.map(|c| c.to_ascii_lowercase()));
+        } else {
+            name.extend(chars);
+        }
+    }
+
+    if all.iter().filter(|e| e.semantic == element.semantic).count() > 1 {
+        name += &element.semantic_index.to_string();
+    }
+    name
+}
+
+//------------------------------------------------------------------------------
+// Hand written layouts
+
+/// A field of an HLSL struct, e.g. `float3 Position : POSITION0;`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LayoutField {
+    pub kind: String,
+    pub components: u32,
+    pub name: String,
+    pub semantic: String,
+    pub semantic_index: u32,
+}
+
+impl LayoutField {
+    fn category(&self) -> &'static str {
+        match self.kind.as_str() {
+            "uint" | "min16uint" | "uint64_t" | "dword" => "uint",
+            "int" | "min16int" | "int64_t" => "int",
+            _ => "float",
+        }
+    }
+}
+
+static FIELD_RE: Lazy<Regex> = Lazy::new(|| Regex::new(
+    r"^\s*([A-Za-z_][A-Za-z0-9_]*?)([1-4])?\s+(\w+)\s*:\s*([A-Za-z_]+?)(\d*)\s*;"
+).unwrap());
+
+/// Parses the fields of an HLSL struct, ignoring any line that isn't a field
+pub fn parse_layout(hlsl: &str) -> Vec<LayoutField> {
+    hlsl.lines()
+        .filter_map(|line| FIELD_RE.captures(line))
+        .map(|c| LayoutField {
+            kind: c[1].to_string(),
+            components: c.get(2).map_or(1, |m| m.as_str().parse().unwrap()),
+            name: c[3].to_string(),
+            semantic: c[4].to_string(),
+            semantic_index: c.get(5).and_then(|m| m.as_str().parse().ok()).unwrap_or(0),
+        })
+        .collect()
+}
+
+/// Differences between a generated signature and a hand written layout
+pub fn compare_layout(signature: &Signature, layout: &[LayoutField]) -> Vec<String> {
+    let mut issues: Vec<String> = Vec::new();
+
+    for e in &signature.elements {
+        let field = layout.iter()
+            .find(|f| (normalize_semantic(&f.semantic), f.semantic_index) == e.key());
+
+        match field {
+            None => issues.push(format!("{} {} is missing from the layout", e.hlsl_type(), e.full_semantic())),
+            Some(f) if f.category() != e.component_type.category() || f.components != e.components() => {
+                issues.push(format!(
+                    "{} is {}{} in the layout but {} in the shaders",
+                    e.full_semantic(), f.kind, f.components, e.hlsl_type()
+                ));
+            },
+            Some(_) => {},
+        }
+    }
+
+    for f in layout {
+        let key = (normalize_semantic(&f.semantic), f.semantic_index);
+        if !signature.elements.iter().any(|e| e.key() == key) {
+            issues.push(format!("{} : {}{} isn't read by any shader", f.name, f.semantic, f.semantic_index));
+        }
+    }
+
+    issues
+}
+
+/// Elements `consumer` reads that `producer` doesn't write, or writes as a different type.
+///
+/// Between stages elements must also agree on register and components. Vertex
+/// inputs are matched by semantic alone, pass `registers = false` for those.
+pub fn link(producer: &Signature, consumer: &Signature, registers: bool) -> Vec<String> {
+    let mut issues: Vec<String> = Vec::new();
+
+    for e in consumer.elements.iter().filter(|e| !e.is_generated()) {
+        match producer.elements.iter().find(|p| p.key() == e.key()) {
+            None => issues.push(format!("{} {} isn't provided", e.hlsl_type(), e.full_semantic())),
+            Some(p) if p.component_type.category() != e.component_type.category() => {
+                issues.push(format!("{} is read as {} but provided as {}", e.full_semantic(), e.hlsl_type(), p.hlsl_type()));
+            },
+            Some(p) if registers && (p.register != e.register || e.mask & !p.mask & 0xF != 0) => {
+                issues.push(format!(
+                    "{} is read from v{}.{} but written to o{}.{}",
+                    e.full_semantic(), e.register, mask_string(e.mask), p.register, mask_string(p.mask)
+                ));
+            },
+            Some(p) if !registers && e.components() > p.components() => {
+                issues.push(format!("{} is read as {} but provided as {}", e.full_semantic(), e.hlsl_type(), p.hlsl_type()));
+            },
+            Some(_) => {},
+        }
+    }
+
+    issues
+}
+
+/// Interpolation and precision differences between linked elements. The stages
+/// still link, but the blobs weren't compiled against each other.
+pub fn link_modes(producer: &Signature, consumer: &Signature) -> Vec<String> {
+    let mut issues: Vec<String> = Vec::new();
+
+    for e in &consumer.elements {
+        let Some(p) = producer.elements.iter().find(|p| p.key() == e.key()) else {
+            continue;
+        };
+        let defined = |m: Interpolation| m != Interpolation::Undefined;
+        if defined(p.interpolation) && defined(e.interpolation) && p.interpolation != e.interpolation {
+            issues.push(format!(
+                "{} is interpolated as {:?} but written as {:?}", e.full_semantic(), e.interpolation, p.interpolation
+            ));
+        }
+        if p.min_precision != e.min_precision {
+            issues.push(format!(
+                "{} is read with min precision {} but written with {}", e.full_semantic(), e.min_precision, p.min_precision
+            ));
+        }
+    }
+
+    issues
+}
+
+fn mask_string(mask: u8) -> String {
+    "xyzw".chars().enumerate().filter(|(i, _)| mask & (1 << i) != 0).map(|(_, c)| c).collect()
+}
+
+//------------------------------------------------------------------------------
+// Vertex factories
+
+/// Vertex input of every vertex shader used with a vertex factory
+pub struct VertexLayout {
+    pub vertex_factory: EMaterialVertexFactory,
+    /// Union of the elements read by the shaders, in register order
+    pub signature: Signature,
+    pub techniques: usize,
+    /// Elements declared differently by different techniques
+    pub conflicts: Vec<String>,
+}
+
+/// Merges the input signatures of the vertex shaders, grouped by vertex factory.
+///
+/// Techniques often read only some of the inputs, so the layout is the union of
+/// every signature. Shaders without a readable signature are skipped.
+pub fn vertex_layouts(manager: &Manager) -> Vec<VertexLayout> {
+    let mut layouts: HashMap<EMaterialVertexFactory, VertexLayout> = HashMap::new();
+    // (factory, key) -> technique that first declared it
+    let mut declared_by: HashMap<(EMaterialVertexFactory, (String, u32)), String> = HashMap::new();
+
+    let mut materials: Vec<_> = manager.materials.values().collect();
+    materials.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for m in materials {
+        for t in &m.techniques {
+            let Some(vs) = &t.vs else {
+                continue;
+            };
+            let Some(Ok(signature)) = vs.container().ok().and_then(|c| Signature::input(&c)) else {
+                continue;
+            };
+
+            let vf = t.desc.vertex_factory;
+            let layout = layouts.entry(vf).or_insert_with(|| VertexLayout {
+                vertex_factory: vf,
+                signature: Signature::default(),
+                techniques: 0,
+                conflicts: Vec::new(),
+            });
+            layout.techniques += 1;
+
+            for e in signature.elements {
+                let existing = layout.signature.elements.iter().find(|x| x.key() == e.key());
+                match existing {
+                    None => {
+                        declared_by.insert((vf, e.key()), m.technique_name(&t.desc));
+                        layout.signature.elements.push(e);
+                    },
+                    Some(x) if x.component_type != e.component_type || x.components() != e.components() => {
+                        layout.conflicts.push(format!(
+                            "{} is {} in {} but {} in {}",
+                            e.full_semantic(), x.hlsl_type(), declared_by[&(vf, e.key())],
+                            e.hlsl_type(), m.technique_name(&t.desc)
+                        ));
+                    },
+                    Some(_) => {},
+                }
+            }
+        }
+    }
+
+    let mut layouts: Vec<VertexLayout> = layouts.into_values().collect();
+    for l in &mut layouts {
+        l.signature.elements.sort_by_key(|e| (e.register, e.mask.trailing_zeros()));
+    }
+    layouts.sort_by_key(|l| l.vertex_factory);
+    layouts
+}
+
+/// Parses and compares a hand written layout, failing if it has no fields
+pub fn check_layout(layout: &VertexLayout, hlsl: &str) -> Result<Vec<String>> {
+    let fields = parse_layout(hlsl);
+    if fields.is_empty() {
+        bail!("No hand written layout for {}", layout.vertex_factory);
+    }
+    Ok(compare_layout(&layout.signature, &fields))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::isg1;
+
+    use super::*;
+
+    fn signature() -> Signature {
+        let data = isg1(&[
+            ("POSITION", 0, 0, 3, 0, 0b0111),
+            ("TEXCOORD", 0, 0, 3, 1, 0b0011),
+            ("TEXCOORD", 1, 0, 3, 1, 0b1100),
+            ("SV_VertexID", 0, 6, 1, 2, 0b0001),
+        ]);
+        Signature::parse(FourCC::ISG1, &data).unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        let s = signature();
+
+        assert_eq!(s.elements.len(), 4);
+        assert_eq!(s.elements[0].semantic, "POSITION");
+        assert_eq!(s.elements[0].hlsl_type(), "float3");
+        assert_eq!(s.elements[2].full_semantic(), "TEXCOORD1");
+        assert_eq!(s.elements[2].components(), 2);
+        assert!(s.elements[3].is_system_value());
+        assert_eq!(s.elements[3].component_type, ComponentType::UInt32);
+    }
+
+    #[test]
+    fn corrupt_count() {
+        let mut data = isg1(&[("POSITION", 0, 0, 3, 0, 0b0111)]);
+        data[0..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Signature::parse(FourCC::ISG1, &data).is_err());
+    }
+
+    #[test]
+    fn hlsl() {
+        let hlsl = signature().to_hlsl("VS_INPUT");
+
+        assert!(hlsl.starts_with("struct VS_INPUT\n{\n"));
+        assert!(hlsl.contains("    float3 Position  : POSITION;\n"));
+        assert!(hlsl.contains("    float2 Texcoord1 : TEXCOORD1;\n"));
+        assert!(hlsl.contains("    uint   VertexID  : SV_VertexID;\n"));
+
+        // Reads back as a layout with no differences
+        assert!(compare_layout(&signature(), &parse_layout(&hlsl)).is_empty());
+    }
+
+    #[test]
+    fn compare() {
+        let layout = parse_layout("
+            struct VS_INPUT
+            {
+                float3 Position : POSITION0;
+                float4 UV       : TEXCOORD0;
+                uint   VertexID : SYS_VERTEX_ID;
+                float3 Normal   : NORMAL;
+            }
+        ");
+        assert_eq!(layout.len(), 4);
+
+        let issues = compare_layout(&signature(), &layout);
+        assert_eq!(issues, vec![
+            "TEXCOORD is float4 in the layout but float2 in the shaders",
+            "float2 TEXCOORD1 is missing from the layout",
+            "Normal : NORMAL0 isn't read by any shader",
+        ]);
+    }
+
+    #[test]
+    fn interpolation() {
+        // dcl_input_ps <mode> v<register>.<mask>
+        let dcl = |mode: u32, register: u32, mask: u32| [0x62 | mode << 11 | 3 << 24, 0x0010_1002 | mask << 4, register];
+        let tokens: Vec<u32> = [0x0000_0050, 9].into_iter()
+            .chain(dcl(1, 0, 0b0011))
+            .chain(dcl(4, 1, 0b1111))
+            .collect();
+
+        let mut container = Container::default();
+        container.set_part(FourCC::ISG1, isg1(&[("COLOR", 0, 0, 3, 0, 0b0011), ("TEXCOORD", 0, 0, 3, 1, 0b1111)]));
+        container.set_part(FourCC::SHEX, tokens.iter().flat_map(|t| t.to_le_bytes()).collect());
+
+        let ps = Signature::input(&container).unwrap().unwrap();
+        assert_eq!(ps.elements[0].interpolation, Interpolation::Constant);
+        assert_eq!(ps.elements[1].interpolation, Interpolation::LinearNoPerspective);
+
+        let mut vs = Signature::parse(FourCC::OSG1, &isg1(&[("COLOR", 0, 0, 3, 0, 0b0011), ("TEXCOORD", 0, 0, 3, 1, 0b1111)])).unwrap();
+        assert!(link(&vs, &ps, true).is_empty());
+        assert!(link_modes(&vs, &ps).is_empty());
+
+        vs.elements[1].interpolation = Interpolation::Linear;
+        assert_eq!(link_modes(&vs, &ps), vec!["TEXCOORD is interpolated as LinearNoPerspective but written as Linear"]);
+    }
+}
@@ -0,0 +1,657 @@
+use std::cell::RefCell;
+use std::io;
+
+use hashbrown::HashMap;
+
+use crate::reflection::{CBufferVariable, Reflection, ResourceBinding, ResourceClass, ResourceDimension};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExecutionModel {
+    Vertex,
+    TessellationControl,
+    TessellationEvaluation,
+    Geometry,
+    Fragment,
+    GLCompute,
+    Kernel,
+    Task,
+    Mesh,
+    Other(u32),
+}
+
+impl From<u32> for ExecutionModel {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => ExecutionModel::Vertex,
+            1 => ExecutionModel::TessellationControl,
+            2 => ExecutionModel::TessellationEvaluation,
+            3 => ExecutionModel::Geometry,
+            4 => ExecutionModel::Fragment,
+            5 => ExecutionModel::GLCompute,
+            6 => ExecutionModel::Kernel,
+            5267 | 5364 => ExecutionModel::Task,
+            5268 | 5365 => ExecutionModel::Mesh,
+            v => ExecutionModel::Other(v),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntryPoint {
+    pub name: String,
+    pub model: ExecutionModel,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DescriptorType {
+    UniformBuffer,
+    StorageBuffer,
+    /// Image and sampler combined
+    SampledImage,
+    SeparateImage,
+    StorageImage,
+    Sampler,
+    UniformTexelBuffer,
+    StorageTexelBuffer,
+    AccelerationStructure,
+}
+
+/// Offsets and sizes are in bytes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StructMember {
+    pub name: String,
+    pub type_name: String,
+    pub offset: u32,
+    pub size: u32,
+}
+
+/// Explicit layout of a block, names are empty when the module was stripped
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StructLayout {
+    pub name: String,
+    pub size: u32,
+    pub members: Vec<StructMember>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DescriptorBinding {
+    pub name: String,
+    pub set: u32,
+    pub binding: u32,
+    /// Array size, `u32::MAX` for runtime arrays
+    pub count: u32,
+    pub kind: DescriptorType,
+    pub dimension: ResourceDimension,
+    /// Buffers only
+    pub block: Option<StructLayout>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PushConstant {
+    pub name: String,
+    pub block: StructLayout,
+}
+
+/// Reflection of a SPIR-V module
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpirvModule {
+    /// (major, minor)
+    pub version: (u8, u8),
+    pub entry_points: Vec<EntryPoint>,
+    pub bindings: Vec<DescriptorBinding>,
+    pub push_constants: Vec<PushConstant>,
+}
+
+#[derive(Clone, Debug)]
+enum Type {
+    Void,
+    Bool,
+    Int { width: u32, signed: bool },
+    Float { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage { image: u32 },
+    Array { element: u32, length: u32 },
+    RuntimeArray { element: u32 },
+    Struct { members: Vec<u32> },
+    Pointer { pointee: u32 },
+    AccelerationStructure,
+}
+
+// Opcodes
+const OP_NAME: u16 = 5;
+const OP_MEMBER_NAME: u16 = 6;
+const OP_ENTRY_POINT: u16 = 15;
+const OP_TYPE_VOID: u16 = 19;
+const OP_TYPE_BOOL: u16 = 20;
+const OP_TYPE_INT: u16 = 21;
+const OP_TYPE_FLOAT: u16 = 22;
+const OP_TYPE_VECTOR: u16 = 23;
+const OP_TYPE_MATRIX: u16 = 24;
+const OP_TYPE_IMAGE: u16 = 25;
+const OP_TYPE_SAMPLER: u16 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u16 = 27;
+const OP_TYPE_ARRAY: u16 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u16 = 29;
+const OP_TYPE_STRUCT: u16 = 30;
+const OP_TYPE_POINTER: u16 = 32;
+const OP_CONSTANT: u16 = 43;
+const OP_VARIABLE: u16 = 59;
+const OP_DECORATE: u16 = 71;
+const OP_MEMBER_DECORATE: u16 = 72;
+const OP_TYPE_ACCELERATION_STRUCTURE: u16 = 5341;
+
+// Decorations
+const DEC_BLOCK: u32 = 2;
+const DEC_BUFFER_BLOCK: u32 = 3;
+const DEC_ARRAY_STRIDE: u32 = 6;
+const DEC_MATRIX_STRIDE: u32 = 7;
+const DEC_BINDING: u32 = 33;
+const DEC_DESCRIPTOR_SET: u32 = 34;
+const DEC_OFFSET: u32 = 35;
+
+// Storage classes
+const SC_UNIFORM_CONSTANT: u32 = 0;
+const SC_UNIFORM: u32 = 2;
+const SC_PUSH_CONSTANT: u32 = 9;
+const SC_STORAGE_BUFFER: u32 = 12;
+
+/// Nesting limit when walking the type graph, a malformed module can make a type refer
+/// to itself. Anything deeper counts as empty.
+const MAX_TYPE_DEPTH: u32 = 32;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Null terminated UTF-8 packed into words
+fn read_string(words: &[u32]) -> String {
+    let bytes: Vec<u8> = words.iter()
+        .flat_map(|w| w.to_le_bytes())
+        .take_while(|b| *b != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Everything collected in the single pass over the instructions
+#[derive(Default)]
+struct Ids {
+    names: HashMap<u32, String>,
+    member_names: HashMap<(u32, u32), String>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    /// (id, decoration) -> first literal
+    decorations: HashMap<(u32, u32), u32>,
+    /// (struct, member, decoration) -> first literal
+    member_decorations: HashMap<(u32, u32, u32), u32>,
+    /// (result type, id, storage class)
+    variables: Vec<(u32, u32, u32)>,
+    /// Struct sizes already laid out, so members sharing a type are only walked once
+    struct_sizes: RefCell<HashMap<u32, u32>>,
+}
+
+impl Ids {
+    fn name(&self, id: u32) -> String {
+        self.names.get(&id).cloned().unwrap_or_default()
+    }
+
+    fn has(&self, id: u32, decoration: u32) -> bool {
+        self.decorations.contains_key(&(id, decoration))
+    }
+
+    fn type_name(&self, id: u32, depth: u32) -> String {
+        if depth > MAX_TYPE_DEPTH {
+            return "unknown".to_string();
+        }
+
+        match self.types.get(&id) {
+            Some(Type::Bool) => "bool".to_string(),
+            Some(Type::Int { width: 32, signed: true }) => "int".to_string(),
+            Some(Type::Int { width: 32, signed: false }) => "uint".to_string(),
+            Some(Type::Int { width, signed: true }) => format!("int{}_t", width),
+            Some(Type::Int { width, signed: false }) => format!("uint{}_t", width),
+            Some(Type::Float { width: 16 }) => "half".to_string(),
+            Some(Type::Float { width: 64 }) => "double".to_string(),
+            Some(Type::Float { .. }) => "float".to_string(),
+            Some(Type::Vector { component, count }) => format!("{}{}", self.type_name(*component, depth + 1), count),
+            Some(Type::Matrix { column, count }) => match self.types.get(column) {
+                Some(Type::Vector { component, count: rows }) => format!("{}{}x{}", self.type_name(*component, depth + 1), rows, count),
+                _ => "matrix".to_string(),
+            },
+            Some(Type::Array { element, length }) => format!("{}[{}]", self.type_name(*element, depth + 1), length),
+            Some(Type::RuntimeArray { element }) => format!("{}[]", self.type_name(*element, depth + 1)),
+            Some(Type::Struct { .. }) => match self.names.get(&id) {
+                Some(n) if !n.is_empty() => n.clone(),
+                _ => "struct".to_string(),
+            },
+            _ => "unknown".to_string(),
+        }
+    }
+
+    /// Size under the explicit layout, `matrix_stride` comes from the member decoration.
+    /// Sizes of corrupt decorations saturate rather than overflow.
+    fn size(&self, id: u32, matrix_stride: Option<u32>, depth: u32) -> u32 {
+        if depth > MAX_TYPE_DEPTH {
+            return 0;
+        }
+
+        match self.types.get(&id) {
+            Some(Type::Bool) => 4,
+            Some(Type::Int { width, .. }) | Some(Type::Float { width }) => width / 8,
+            Some(Type::Vector { component, count }) => self.size(*component, None, depth + 1).saturating_mul(*count),
+            Some(Type::Matrix { column, count }) => match matrix_stride {
+                Some(stride) => stride.saturating_mul(*count),
+                None => self.size(*column, None, depth + 1).saturating_mul(*count),
+            },
+            Some(Type::Array { element, length }) => {
+                let stride = self.decorations.get(&(id, DEC_ARRAY_STRIDE)).copied()
+                    .unwrap_or_else(|| self.size(*element, matrix_stride, depth + 1));
+                stride.saturating_mul(*length)
+            },
+            Some(Type::Struct { .. }) => {
+                let cached = self.struct_sizes.borrow().get(&id).copied();
+                cached.unwrap_or_else(|| {
+                    let size = self.layout(id, depth + 1).size;
+                    self.struct_sizes.borrow_mut().insert(id, size);
+                    size
+                })
+            },
+            _ => 0,
+        }
+    }
+
+    fn layout(&self, id: u32, depth: u32) -> StructLayout {
+        let Some(Type::Struct { members }) = self.types.get(&id) else {
+            return StructLayout { name: String::new(), size: 0, members: Vec::new() };
+        };
+
+        let members: Vec<StructMember> = members.iter().enumerate()
+            .map(|(i, t)| {
+                let i = i as u32;
+                let stride = self.member_decorations.get(&(id, i, DEC_MATRIX_STRIDE)).copied();
+                StructMember {
+                    name: self.member_names.get(&(id, i)).cloned().unwrap_or_default(),
+                    type_name: self.type_name(*t, depth + 1),
+                    offset: self.member_decorations.get(&(id, i, DEC_OFFSET)).copied().unwrap_or(0),
+                    size: self.size(*t, stride, depth + 1),
+                }
+            })
+            .collect();
+
+        StructLayout {
+            name: self.name(id),
+            size: members.iter().map(|m| m.offset.saturating_add(m.size)).max().unwrap_or(0),
+            members,
+        }
+    }
+
+    /// Strips arrays off a type, returning the element and the array size
+    fn element(&self, id: u32) -> (u32, u32) {
+        match self.types.get(&id) {
+            Some(Type::Array { element, length }) => (*element, *length),
+            Some(Type::RuntimeArray { element }) => (*element, u32::MAX),
+            _ => (id, 1),
+        }
+    }
+}
+
+fn image_dimension(dim: u32) -> ResourceDimension {
+    match dim {
+        0 => ResourceDimension::Texture1D,
+        1 | 4 => ResourceDimension::Texture2D,
+        2 => ResourceDimension::Texture3D,
+        3 => ResourceDimension::TextureCube,
+        5 => ResourceDimension::Buffer,
+        _ => ResourceDimension::Unknown,
+    }
+}
+
+/// Checks the header and returns the module as words in native byte order
+pub(crate) fn words(data: &[u8]) -> io::Result<Vec<u32>> {
+    if data.len() < 20 || !data.len().is_multiple_of(4) || !SpirvModule::is_spirv(data) {
+        return Err(invalid("Not a SPIR-V module"));
+    }
+
+    let mut words: Vec<u32> = data.chunks_exact(4)
+        .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+        .collect();
+    if words[0] != SpirvModule::MAGIC {
+        words.iter_mut().for_each(|w| *w = w.swap_bytes());
+    }
+    Ok(words)
+}
+
+/// Splits the words after the header into opcodes and operands
+pub(crate) fn instructions(words: &[u32]) -> io::Result<Vec<(u16, &[u32])>> {
+    let mut out = Vec::new();
+    let mut pos = 5;
+    while pos < words.len() {
+        let count = (words[pos] >> 16) as usize;
+        if count == 0 || pos + count > words.len() {
+            return Err(invalid("Truncated SPIR-V instruction"));
+        }
+        out.push((words[pos] as u16, &words[pos + 1..pos + count]));
+        pos += count;
+    }
+    Ok(out)
+}
+
+impl SpirvModule {
+    pub const MAGIC: u32 = 0x07230203;
+
+    /// True if the data starts with the SPIR-V magic, in either byte order
+    pub fn is_spirv(data: &[u8]) -> bool {
+        data.get(0..4)
+            .map(|m| u32::from_le_bytes(m.try_into().unwrap()))
+            .is_some_and(|m| m == SpirvModule::MAGIC || m.swap_bytes() == SpirvModule::MAGIC)
+    }
+
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        let words = words(data)?;
+
+        let version = ((words[1] >> 16) as u8, (words[1] >> 8) as u8);
+        let mut module = SpirvModule { version, ..Default::default() };
+        let mut ids = Ids::default();
+
+        for (opcode, ops) in instructions(&words)? {
+            // Operand counts are checked per opcode, short instructions are skipped
+            let op = |i: usize| ops.get(i).copied().unwrap_or(0);
+            match opcode {
+                OP_NAME if !ops.is_empty() => {
+                    ids.names.insert(ops[0], read_string(&ops[1..]));
+                },
+                OP_MEMBER_NAME if ops.len() >= 2 => {
+                    ids.member_names.insert((ops[0], ops[1]), read_string(&ops[2..]));
+                },
+                OP_ENTRY_POINT if ops.len() >= 2 => {
+                    module.entry_points.push(EntryPoint {
+                        name: read_string(&ops[2..]),
+                        model: ExecutionModel::from(ops[0]),
+                    });
+                },
+                OP_TYPE_VOID => { ids.types.insert(op(0), Type::Void); },
+                OP_TYPE_BOOL => { ids.types.insert(op(0), Type::Bool); },
+                OP_TYPE_INT => { ids.types.insert(op(0), Type::Int { width: op(1), signed: op(2) != 0 }); },
+                OP_TYPE_FLOAT => { ids.types.insert(op(0), Type::Float { width: op(1) }); },
+                OP_TYPE_VECTOR => { ids.types.insert(op(0), Type::Vector { component: op(1), count: op(2) }); },
+                OP_TYPE_MATRIX => { ids.types.insert(op(0), Type::Matrix { column: op(1), count: op(2) }); },
+                OP_TYPE_IMAGE => { ids.types.insert(op(0), Type::Image { dim: op(2), sampled: op(6) }); },
+                OP_TYPE_SAMPLER => { ids.types.insert(op(0), Type::Sampler); },
+                OP_TYPE_SAMPLED_IMAGE => { ids.types.insert(op(0), Type::SampledImage { image: op(1) }); },
+                OP_TYPE_ARRAY => {
+                    // Constants are declared before the array types using them
+                    let length = ids.constants.get(&op(2)).copied().unwrap_or(0);
+                    ids.types.insert(op(0), Type::Array { element: op(1), length });
+                },
+                OP_TYPE_RUNTIME_ARRAY => { ids.types.insert(op(0), Type::RuntimeArray { element: op(1) }); },
+                OP_TYPE_STRUCT if !ops.is_empty() => {
+                    ids.types.insert(ops[0], Type::Struct { members: ops[1..].to_vec() });
+                },
+                OP_TYPE_POINTER => { ids.types.insert(op(0), Type::Pointer { pointee: op(2) }); },
+                OP_TYPE_ACCELERATION_STRUCTURE => { ids.types.insert(op(0), Type::AccelerationStructure); },
+                OP_CONSTANT if ops.len() >= 3 => { ids.constants.insert(ops[1], ops[2]); },
+                OP_VARIABLE if ops.len() >= 3 => { ids.variables.push((ops[0], ops[1], ops[2])); },
+                OP_DECORATE if ops.len() >= 2 => {
+                    ids.decorations.insert((ops[0], ops[1]), op(2));
+                },
+                OP_MEMBER_DECORATE if ops.len() >= 3 => {
+                    ids.member_decorations.insert((ops[0], ops[1], ops[2]), op(3));
+                },
+                _ => {},
+            }
+        }
+
+        for &(pointer, id, storage) in &ids.variables {
+            let Some(Type::Pointer { pointee }) = ids.types.get(&pointer) else {
+                continue;
+            };
+            let (element, count) = ids.element(*pointee);
+
+            if storage == SC_PUSH_CONSTANT {
+                module.push_constants.push(PushConstant { name: ids.name(id), block: ids.layout(element, 0) });
+                continue;
+            }
+
+            let (kind, dimension) = match (storage, ids.types.get(&element)) {
+                (SC_UNIFORM, Some(Type::Struct { .. })) if ids.has(element, DEC_BUFFER_BLOCK) => {
+                    (DescriptorType::StorageBuffer, ResourceDimension::StructuredBuffer)
+                },
+                (SC_UNIFORM, Some(Type::Struct { .. })) if ids.has(element, DEC_BLOCK) => {
+                    (DescriptorType::UniformBuffer, ResourceDimension::Unknown)
+                },
+                (SC_STORAGE_BUFFER, Some(Type::Struct { .. })) => {
+                    (DescriptorType::StorageBuffer, ResourceDimension::StructuredBuffer)
+                },
+                (SC_UNIFORM_CONSTANT, Some(Type::Sampler)) => (DescriptorType::Sampler, ResourceDimension::Unknown),
+                (SC_UNIFORM_CONSTANT, Some(Type::SampledImage { image })) => {
+                    let dim = match ids.types.get(image) {
+                        Some(Type::Image { dim, .. }) => image_dimension(*dim),
+                        _ => ResourceDimension::Unknown,
+                    };
+                    (DescriptorType::SampledImage, dim)
+                },
+                // Sampled 2 means read/write without a sampler
+                (SC_UNIFORM_CONSTANT, Some(Type::Image { dim: 5, sampled })) => match sampled {
+                    2 => (DescriptorType::StorageTexelBuffer, ResourceDimension::Buffer),
+                    _ => (DescriptorType::UniformTexelBuffer, ResourceDimension::Buffer),
+                },
+                (SC_UNIFORM_CONSTANT, Some(Type::Image { dim, sampled })) => match sampled {
+                    2 => (DescriptorType::StorageImage, image_dimension(*dim)),
+                    _ => (DescriptorType::SeparateImage, image_dimension(*dim)),
+                },
+                (SC_UNIFORM_CONSTANT, Some(Type::AccelerationStructure)) => {
+                    (DescriptorType::AccelerationStructure, ResourceDimension::AccelerationStructure)
+                },
+                // Inputs, outputs and private variables aren't descriptors
+                _ => continue,
+            };
+
+            let block = match kind {
+                DescriptorType::UniformBuffer | DescriptorType::StorageBuffer => Some(ids.layout(element, 0)),
+                _ => None,
+            };
+
+            module.bindings.push(DescriptorBinding {
+                name: ids.name(id),
+                set: ids.decorations.get(&(id, DEC_DESCRIPTOR_SET)).copied().unwrap_or(0),
+                binding: ids.decorations.get(&(id, DEC_BINDING)).copied().unwrap_or(0),
+                count,
+                kind,
+                dimension,
+                block,
+            });
+        }
+
+        module.bindings.sort_by_key(|b| (b.set, b.binding));
+        Ok(module)
+    }
+}
+
+impl From<&SpirvModule> for Reflection {
+    /// Descriptor sets become register spaces and bindings become registers. Combined
+    /// image samplers are listed as both an SRV and a sampler.
+    fn from(value: &SpirvModule) -> Self {
+        let mut bindings: Vec<ResourceBinding> = Vec::new();
+
+        for b in &value.bindings {
+            let class = match b.kind {
+                DescriptorType::UniformBuffer => ResourceClass::CBuffer,
+                DescriptorType::StorageBuffer | DescriptorType::StorageImage | DescriptorType::StorageTexelBuffer => ResourceClass::UAV,
+                DescriptorType::Sampler => ResourceClass::Sampler,
+                _ => ResourceClass::SRV,
+            };
+
+            let binding = ResourceBinding {
+                name: b.name.clone(),
+                class,
+                dimension: b.dimension,
+                space: b.set,
+                register: b.binding,
+                count: b.count,
+                size: b.block.as_ref().filter(|_| class == ResourceClass::CBuffer).map(|l| l.size),
+                variables: b.block.iter()
+                    .filter(|_| class == ResourceClass::CBuffer)
+                    .flat_map(|l| &l.members)
+                    .map(|m| CBufferVariable { name: m.name.clone(), offset: m.offset, size: m.size })
+                    .collect(),
+            };
+
+            if b.kind == DescriptorType::SampledImage {
+                bindings.push(ResourceBinding {
+                    class: ResourceClass::Sampler,
+                    dimension: ResourceDimension::Unknown,
+                    ..binding.clone()
+                });
+            }
+            bindings.push(binding);
+        }
+
+        Reflection { bindings }
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::shader::ShaderType;
+
+    use super::*;
+
+    fn string(s: &str) -> Vec<u32> {
+        let mut bytes = s.as_bytes().to_vec();
+        bytes.resize((bytes.len() / 4 + 1) * 4, 0);
+        bytes.chunks_exact(4).map(|w| u32::from_le_bytes(w.try_into().unwrap())).collect()
+    }
+
+    pub(crate) fn inst(out: &mut Vec<u32>, opcode: u16, ops: &[u32]) {
+        out.push(((ops.len() as u32 + 1) << 16) | opcode as u32);
+        out.extend_from_slice(ops);
+    }
+
+    fn named(out: &mut Vec<u32>, opcode: u16, ids: &[u32], name: &str) {
+        let mut ops = ids.to_vec();
+        ops.extend(string(name));
+        inst(out, opcode, &ops);
+    }
+
+    /// Fragment shader with a uniform block, a combined image sampler array and push constants
+    pub(crate) fn test_module() -> Vec<u8> {
+        let mut w: Vec<u32> = vec![ SpirvModule::MAGIC, 0x0001_0500, 0, 100, 0 ];
+
+        named(&mut w, OP_ENTRY_POINT, &[4, 1], "main");
+        named(&mut w, OP_NAME, &[10], "Material");
+        named(&mut w, OP_MEMBER_NAME, &[10, 0], "Color");
+        named(&mut w, OP_MEMBER_NAME, &[10, 1], "Transform");
+        named(&mut w, OP_NAME, &[12], "material");
+        named(&mut w, OP_NAME, &[22], "textures");
+
+        inst(&mut w, OP_DECORATE, &[10, DEC_BLOCK]);
+        inst(&mut w, OP_MEMBER_DECORATE, &[10, 0, DEC_OFFSET, 0]);
+        inst(&mut w, OP_MEMBER_DECORATE, &[10, 1, DEC_OFFSET, 16]);
+        inst(&mut w, OP_MEMBER_DECORATE, &[10, 1, DEC_MATRIX_STRIDE, 16]);
+        inst(&mut w, OP_DECORATE, &[12, DEC_DESCRIPTOR_SET, 1]);
+        inst(&mut w, OP_DECORATE, &[12, DEC_BINDING, 0]);
+        inst(&mut w, OP_DECORATE, &[22, DEC_DESCRIPTOR_SET, 1]);
+        inst(&mut w, OP_DECORATE, &[22, DEC_BINDING, 2]);
+        inst(&mut w, OP_DECORATE, &[30, DEC_BLOCK]);
+        inst(&mut w, OP_MEMBER_DECORATE, &[30, 0, DEC_OFFSET, 0]);
+
+        inst(&mut w, OP_TYPE_FLOAT, &[5, 32]);
+        inst(&mut w, OP_TYPE_VECTOR, &[6, 5, 4]);
+        inst(&mut w, OP_TYPE_MATRIX, &[7, 6, 4]);
+        inst(&mut w, OP_TYPE_INT, &[8, 32, 0]);
+        inst(&mut w, OP_CONSTANT, &[8, 9, 4]);
+        inst(&mut w, OP_TYPE_STRUCT, &[10, 6, 7]);
+        inst(&mut w, OP_TYPE_POINTER, &[11, SC_UNIFORM, 10]);
+        inst(&mut w, OP_VARIABLE, &[11, 12, SC_UNIFORM]);
+
+        inst(&mut w, OP_TYPE_IMAGE, &[18, 5, 1, 0, 0, 0, 1, 0]);
+        inst(&mut w, OP_TYPE_SAMPLED_IMAGE, &[19, 18]);
+        inst(&mut w, OP_TYPE_ARRAY, &[20, 19, 9]);
+        inst(&mut w, OP_TYPE_POINTER, &[21, SC_UNIFORM_CONSTANT, 20]);
+        inst(&mut w, OP_VARIABLE, &[21, 22, SC_UNIFORM_CONSTANT]);
+
+        inst(&mut w, OP_TYPE_STRUCT, &[30, 8]);
+        inst(&mut w, OP_TYPE_POINTER, &[31, SC_PUSH_CONSTANT, 30]);
+        inst(&mut w, OP_VARIABLE, &[31, 32, SC_PUSH_CONSTANT]);
+
+        w.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn parse() {
+        let data = test_module();
+        assert!(SpirvModule::is_spirv(&data));
+
+        let module = SpirvModule::parse(&data).unwrap();
+        assert_eq!(module.version, (1, 5));
+        assert_eq!(module.entry_points, vec![ EntryPoint { name: "main".to_string(), model: ExecutionModel::Fragment } ]);
+        assert_eq!(ShaderType::detect(&data), ShaderType::Pixel);
+
+        assert_eq!(module.bindings.len(), 2);
+        let ubo = &module.bindings[0];
+        assert_eq!((ubo.kind, ubo.set, ubo.binding, ubo.count), (DescriptorType::UniformBuffer, 1, 0, 1));
+        let block = ubo.block.as_ref().unwrap();
+        assert_eq!(block.size, 80);
+        assert_eq!(block.members[1], StructMember {
+            name: "Transform".to_string(), type_name: "float4x4".to_string(), offset: 16, size: 64
+        });
+
+        let textures = &module.bindings[1];
+        assert_eq!((textures.kind, textures.dimension, textures.count), (DescriptorType::SampledImage, ResourceDimension::Texture2D, 4));
+
+        // Nameless struct
+        assert_eq!(module.push_constants.len(), 1);
+        assert_eq!(module.push_constants[0].block.name, "");
+        assert_eq!(module.push_constants[0].block.size, 4);
+    }
+
+    #[test]
+    fn reflection() {
+        let data = test_module();
+        let reflection = Reflection::from_blob(&data).unwrap().unwrap();
+
+        assert_eq!(reflection.bindings.len(), 3);
+        let (cbuffer, variable) = reflection.find_variable("Color").unwrap();
+        assert_eq!((cbuffer.space, cbuffer.size), (1, Some(80)));
+        assert_eq!(variable.size, 16);
+        assert!(reflection.of_class(ResourceClass::Sampler).any(|b| b.register == 2 && b.space == 1));
+    }
+
+    #[test]
+    fn malformed_types() {
+        let mut w: Vec<u32> = vec![ SpirvModule::MAGIC, 0x0001_0500, 0, 100, 0 ];
+
+        inst(&mut w, OP_DECORATE, &[10, DEC_BLOCK]);
+        inst(&mut w, OP_MEMBER_DECORATE, &[10, 1, DEC_OFFSET, u32::MAX]);
+        inst(&mut w, OP_DECORATE, &[20, DEC_ARRAY_STRIDE, u32::MAX]);
+
+        inst(&mut w, OP_TYPE_INT, &[8, 32, 0]);
+        inst(&mut w, OP_CONSTANT, &[8, 9, 4]);
+        inst(&mut w, OP_TYPE_ARRAY, &[20, 8, 9]);
+        inst(&mut w, OP_TYPE_VECTOR, &[13, 13, 4]);
+        // A struct holding itself, twice, and a vector of itself
+        inst(&mut w, OP_TYPE_STRUCT, &[10, 10, 20, 10, 13]);
+        inst(&mut w, OP_TYPE_POINTER, &[11, SC_UNIFORM, 10]);
+        inst(&mut w, OP_VARIABLE, &[11, 12, SC_UNIFORM]);
+
+        let data: Vec<u8> = w.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let module = SpirvModule::parse(&data).unwrap();
+
+        let block = module.bindings[0].block.as_ref().unwrap();
+        assert_eq!(block.members.len(), 4);
+        assert_eq!(block.members[3].size, 0);
+        // Stride times length, and the offset past it, saturate
+        assert_eq!(block.members[1].size, u32::MAX);
+        assert_eq!(block.size, u32::MAX);
+        assert!(Reflection::from_blob(&data).unwrap().is_ok());
+    }
+
+    #[test]
+    fn big_endian() {
+        let data: Vec<u8> = test_module().chunks_exact(4).flat_map(|w| [w[3], w[2], w[1], w[0]]).collect();
+        assert_eq!(SpirvModule::parse(&data).unwrap(), SpirvModule::parse(&test_module()).unwrap());
+    }
+}
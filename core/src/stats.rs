@@ -0,0 +1,742 @@
+use std::io;
+
+use anyhow::Result;
+use hashbrown::{HashMap, HashSet};
+use serde::Serialize;
+
+use crate::analysis::ser_hash;
+use crate::bitcode::{self, Block, Item, Record};
+use crate::container::{Container, DxilProgram, FourCC};
+use crate::manager::Manager;
+use crate::shader::{BlobFormat, Shader};
+use crate::spirv;
+
+/// Instruction counts of a compiled shader.
+///
+/// DXBC shaders take these from the STAT part as written by the compiler, DXIL and
+/// SPIR-V shaders count them from the module itself, so the numbers are only
+/// comparable between shaders of the same format.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ShaderStats {
+    pub instructions: u32,
+    /// Arithmetic, comparison and conversion instructions
+    pub alu: u32,
+    /// Filtered samples, including the bias, gradient and comparison variants
+    pub texture_samples: u32,
+    /// Unfiltered loads and gathers
+    pub texture_loads: u32,
+    /// Conditional branches and switches
+    pub dynamic_flow_control: u32,
+    /// DXBC only, DXIL and SPIR-V are in SSA form
+    pub temp_registers: Option<u32>,
+}
+
+impl ShaderStats {
+    /// Rough cost used for ranking, texture fetches and divergent branches weigh more
+    pub fn cost(&self) -> u32 {
+        self.instructions
+            .saturating_add(self.texture_samples.saturating_mul(4))
+            .saturating_add(self.texture_loads.saturating_mul(2))
+            .saturating_add(self.dynamic_flow_control.saturating_mul(2))
+    }
+
+    /// None if the blob isn't a container or SPIR-V module, or has no program
+    pub fn from_blob(data: &[u8]) -> Option<io::Result<Self>> {
+        match BlobFormat::detect(data) {
+            BlobFormat::Spirv => Some(ShaderStats::from_spirv(data)),
+            BlobFormat::Container => match Container::parse(data) {
+                Ok(c) => ShaderStats::from_container(&c),
+                Err(e) => Some(Err(e)),
+            },
+            BlobFormat::Unknown => None,
+        }
+    }
+
+    /// DXIL programs prefer the STAT module, which keeps names stripped from the DXIL part
+    pub fn from_container(container: &Container) -> Option<io::Result<Self>> {
+        if container.part(FourCC::DXIL).is_some() {
+            let program = container.stat().or_else(|| container.dxil())?;
+            return Some(program.and_then(|p| ShaderStats::from_dxil(&p)));
+        }
+        container.part(FourCC::STAT).map(|p| ShaderStats::parse_dxbc_stat(&p.data))
+    }
+
+    /// The STAT part of DXBC shaders, a table of counters in D3D11_SHADER_DESC order
+    pub fn parse_dxbc_stat(data: &[u8]) -> io::Result<Self> {
+        if data.len() < 22 * 4 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "STAT part is too short"));
+        }
+        let v = |i: usize| u32::from_le_bytes(data[i * 4..i * 4 + 4].try_into().unwrap());
+
+        // 4 float, 5 int, 6 uint, 7 static flow, 8 dynamic flow, 14 to 18 texture
+        // normal, load, comparison, bias and gradient, 21 conversions
+        Ok(ShaderStats {
+            instructions: v(0),
+            alu: v(4).saturating_add(v(5)).saturating_add(v(6)).saturating_add(v(21)),
+            texture_samples: v(14).saturating_add(v(16)).saturating_add(v(17)).saturating_add(v(18)),
+            texture_loads: v(15),
+            dynamic_flow_control: v(8),
+            temp_registers: Some(v(1)),
+        })
+    }
+
+    pub fn from_dxil(program: &DxilProgram) -> io::Result<Self> {
+        let blocks = bitcode::parse(program.bitcode)?;
+        let module = blocks.iter().find(|b| b.id == MODULE_BLOCK)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Bitcode has no module block"))?;
+        Ok(DxilModule::new(module).stats())
+    }
+
+    pub fn from_spirv(data: &[u8]) -> io::Result<Self> {
+        let words = spirv::words(data)?;
+        let mut stats = ShaderStats::default();
+        let mut in_function = false;
+
+        for (opcode, _) in spirv::instructions(&words)? {
+            match opcode {
+                OP_FUNCTION => in_function = true,
+                OP_FUNCTION_END => in_function = false,
+                _ if !in_function => {},
+                // Labels, parameters and debug lines aren't executed
+                OP_LABEL | OP_FUNCTION_PARAMETER | OP_LINE | OP_NO_LINE => {},
+                _ => {
+                    stats.instructions += 1;
+                    match opcode {
+                        OP_IMAGE_SAMPLE_FIRST..=OP_IMAGE_SAMPLE_LAST => stats.texture_samples += 1,
+                        // Fetches, gathers and storage image reads
+                        OP_IMAGE_FETCH..=OP_IMAGE_READ => stats.texture_loads += 1,
+                        OP_BRANCH_CONDITIONAL | OP_SWITCH => stats.dynamic_flow_control += 1,
+                        OP_ALU_FIRST..=OP_ALU_LAST => stats.alu += 1,
+                        _ => {},
+                    }
+                },
+            }
+        }
+        Ok(stats)
+    }
+}
+
+// SPIR-V opcodes
+const OP_FUNCTION: u16 = 54;
+const OP_FUNCTION_PARAMETER: u16 = 55;
+const OP_FUNCTION_END: u16 = 56;
+const OP_LINE: u16 = 8;
+const OP_NO_LINE: u16 = 317;
+const OP_LABEL: u16 = 248;
+/// OpImageSampleImplicitLod to OpImageSampleProjDrefExplicitLod
+const OP_IMAGE_SAMPLE_FIRST: u16 = 87;
+const OP_IMAGE_SAMPLE_LAST: u16 = 94;
+const OP_IMAGE_FETCH: u16 = 95;
+const OP_IMAGE_READ: u16 = 98;
+/// OpConvertFToU to OpBitCount, conversions, arithmetic, bit operations and comparisons
+const OP_ALU_FIRST: u16 = 109;
+const OP_ALU_LAST: u16 = 205;
+const OP_BRANCH_CONDITIONAL: u16 = 250;
+const OP_SWITCH: u16 = 251;
+
+// LLVM 3.7 block ids and record codes, as used by DXIL
+const MODULE_BLOCK: u32 = 8;
+const CONSTANTS_BLOCK: u32 = 11;
+const FUNCTION_BLOCK: u32 = 12;
+const VALUE_SYMTAB_BLOCK: u32 = 14;
+const TYPE_BLOCK: u32 = 17;
+
+const MODULE_GLOBALVAR: u32 = 7;
+const MODULE_FUNCTION: u32 = 8;
+const MODULE_ALIAS_OLD: u32 = 9;
+const MODULE_ALIAS: u32 = 14;
+
+const TYPE_NUMENTRY: u32 = 1;
+const TYPE_VOID: u32 = 2;
+const TYPE_POINTER: u32 = 8;
+const TYPE_FUNCTION_OLD: u32 = 9;
+const TYPE_STRUCT_NAME: u32 = 19;
+const TYPE_FUNCTION: u32 = 21;
+
+const CST_SETTYPE: u32 = 1;
+
+const VST_ENTRY: u32 = 1;
+const VST_FNENTRY: u32 = 3;
+
+const INST_DECLAREBLOCKS: u32 = 1;
+const INST_BINOP: u32 = 2;
+const INST_CAST: u32 = 3;
+const INST_SELECT: u32 = 5;
+const INST_CMP: u32 = 9;
+const INST_BR: u32 = 11;
+const INST_SWITCH: u32 = 12;
+const INST_INVOKE: u32 = 13;
+const INST_CMP2: u32 = 28;
+const INST_VSELECT: u32 = 29;
+const INST_DEBUG_LOC_AGAIN: u32 = 33;
+const INST_CALL: u32 = 34;
+const INST_DEBUG_LOC: u32 = 35;
+
+/// Instructions that don't define a value, calls depend on their return type
+const INST_NO_VALUE: [u32; 12] = [10, 11, 12, 15, 24, 31, 36, 39, 42, 44, 45, INST_DECLAREBLOCKS];
+
+/// Call operand flags
+const CALL_EXPLICIT_TYPE: u64 = 1 << 15;
+const CALL_FMF: u64 = 1 << 17;
+
+const MAX_POINTER_DEPTH: u32 = 8;
+
+#[derive(Clone, Copy)]
+enum Type {
+    Void,
+    Function { ret: u32, params: u32 },
+    Pointer { pointee: u32 },
+    Other,
+}
+
+/// Value numbering of a module, just enough to name the callee of every call
+struct DxilModule<'a> {
+    module: &'a Block,
+    types: Vec<Type>,
+    /// Type of each module level value, functions and globals
+    value_types: Vec<u32>,
+    /// Function bodies, in order, are matched to the functions that aren't prototypes
+    bodies: Vec<u32>,
+    names: HashMap<u32, String>,
+    module_values: u32,
+}
+
+fn record_string(ops: &[u64]) -> String {
+    ops.iter().map(|c| *c as u8 as char).collect()
+}
+
+impl<'a> DxilModule<'a> {
+    fn new(module: &'a Block) -> Self {
+        let mut types = Vec::new();
+        for r in module.blocks(TYPE_BLOCK).flat_map(Block::records) {
+            let op = |i: usize| r.ops.get(i).copied().unwrap_or(0) as u32;
+            types.push(match r.code {
+                TYPE_NUMENTRY | TYPE_STRUCT_NAME => continue,
+                TYPE_VOID => Type::Void,
+                TYPE_POINTER => Type::Pointer { pointee: op(0) },
+                TYPE_FUNCTION => Type::Function { ret: op(1), params: r.ops.len().saturating_sub(2) as u32 },
+                TYPE_FUNCTION_OLD => Type::Function { ret: op(2), params: r.ops.len().saturating_sub(3) as u32 },
+                _ => Type::Other,
+            });
+        }
+
+        let mut dxil = DxilModule {
+            module,
+            types,
+            value_types: Vec::new(),
+            bodies: Vec::new(),
+            names: HashMap::new(),
+            module_values: 0,
+        };
+
+        for r in module.records() {
+            let ty = r.ops.first().copied().unwrap_or(0) as u32;
+            match r.code {
+                MODULE_FUNCTION => {
+                    if r.ops.get(2) == Some(&0) {
+                        dxil.bodies.push(dxil.value_types.len() as u32);
+                    }
+                    dxil.value_types.push(ty);
+                },
+                MODULE_GLOBALVAR | MODULE_ALIAS | MODULE_ALIAS_OLD => dxil.value_types.push(ty),
+                _ => {},
+            }
+        }
+
+        let constants = module.blocks(CONSTANTS_BLOCK).flat_map(Block::records).filter(|r| r.code != CST_SETTYPE).count();
+        dxil.module_values = (dxil.value_types.len() + constants) as u32;
+
+        for r in module.blocks(VALUE_SYMTAB_BLOCK).flat_map(Block::records) {
+            match r.code {
+                VST_ENTRY if !r.ops.is_empty() => { dxil.names.insert(r.ops[0] as u32, record_string(&r.ops[1..])); },
+                VST_FNENTRY if r.ops.len() >= 2 => { dxil.names.insert(r.ops[0] as u32, record_string(&r.ops[2..])); },
+                _ => {},
+            }
+        }
+        dxil
+    }
+
+    /// Follows pointers to the function type, giving up on pointer cycles of a corrupt table
+    fn function_type(&self, mut ty: u32) -> Option<(u32, u32)> {
+        for _ in 0..MAX_POINTER_DEPTH {
+            match self.types.get(ty as usize)? {
+                Type::Function { ret, params } => return Some((*ret, *params)),
+                Type::Pointer { pointee } => ty = *pointee,
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    fn is_void(&self, ty: u32) -> bool {
+        matches!(self.types.get(ty as usize), Some(Type::Void))
+    }
+
+    /// Callee value id and whether the call defines a value
+    fn call(&self, r: &Record, next_value: u32) -> Option<(u32, bool)> {
+        let flags = *r.ops.get(1)?;
+        let mut i = 2;
+        if flags & CALL_FMF != 0 {
+            i += 1;
+        }
+        let explicit = if flags & CALL_EXPLICIT_TYPE != 0 {
+            i += 1;
+            Some(*r.ops.get(i - 1)? as u32)
+        }
+        else {
+            None
+        };
+
+        // Operands are relative to the value the instruction would define
+        let callee = next_value.wrapping_sub(*r.ops.get(i)? as u32);
+        let ty = explicit.or_else(|| self.value_types.get(callee as usize).copied())?;
+        let (ret, _) = self.function_type(ty)?;
+        Some((callee, !self.is_void(ret)))
+    }
+
+    fn stats(&self) -> ShaderStats {
+        let mut stats = ShaderStats::default();
+
+        for (body, function) in self.module.blocks(FUNCTION_BLOCK).zip(&self.bodies) {
+            let params = self.function_type(self.value_types[*function as usize]).map(|(_, p)| p).unwrap_or(0);
+            let mut next_value = self.module_values + params;
+
+            for item in &body.items {
+                let r = match item {
+                    Item::Block(b) if b.id == CONSTANTS_BLOCK => {
+                        next_value += b.records().filter(|r| r.code != CST_SETTYPE).count() as u32;
+                        continue;
+                    },
+                    Item::Block(_) => continue,
+                    Item::Record(r) => r,
+                };
+
+                match r.code {
+                    INST_DECLAREBLOCKS | INST_DEBUG_LOC | INST_DEBUG_LOC_AGAIN => continue,
+                    INST_CALL | INST_INVOKE => {
+                        let Some((callee, has_value)) = self.call(r, next_value) else {
+                            stats.instructions += 1;
+                            continue;
+                        };
+                        if has_value {
+                            next_value += 1;
+                        }
+
+                        let name = self.names.get(&callee).map(String::as_str).unwrap_or("");
+                        if name.starts_with("llvm.dbg.") {
+                            continue;
+                        }
+                        stats.instructions += 1;
+                        count_dx_op(&mut stats, name);
+                    },
+                    code => {
+                        stats.instructions += 1;
+                        if !INST_NO_VALUE.contains(&code) {
+                            next_value += 1;
+                        }
+                        match code {
+                            INST_BINOP | INST_CAST | INST_SELECT | INST_VSELECT | INST_CMP | INST_CMP2 => stats.alu += 1,
+                            INST_BR if r.ops.len() >= 3 => stats.dynamic_flow_control += 1,
+                            INST_SWITCH => stats.dynamic_flow_control += 1,
+                            _ => {},
+                        }
+                    },
+                }
+            }
+        }
+        stats
+    }
+}
+
+/// DXIL operations are calls to overloads named `dx.op.<class>.<type>`
+fn count_dx_op(stats: &mut ShaderStats, name: &str) {
+    let Some(class) = name.strip_prefix("dx.op.") else {
+        return;
+    };
+    let class = class.split('.').next().unwrap_or_default();
+
+    if class.starts_with("sample") {
+        stats.texture_samples += 1;
+    }
+    else if ["textureLoad", "textureGather", "textureGatherCmp", "bufferLoad", "rawBufferLoad"].contains(&class) {
+        stats.texture_loads += 1;
+    }
+    else if ["unary", "binary", "tertiary", "quaternary", "dot"].iter().any(|p| class.starts_with(p)) {
+        stats.alu += 1;
+    }
+}
+
+
+#[derive(Clone, Serialize)]
+pub struct ShaderCost {
+    #[serde(serialize_with = "ser_hash")]
+    pub hash: u64,
+    pub stage: String,
+    pub cost: u32,
+    pub stats: ShaderStats,
+}
+
+impl ShaderCost {
+    fn new(shader: &Shader) -> Option<Self> {
+        let stats = shader.stats.clone()?;
+        Some(ShaderCost { hash: shader.hash, stage: shader.kind.to_string(), cost: stats.cost(), stats })
+    }
+}
+
+/// Shaders used by the techniques of a pass or material, most expensive first
+#[derive(Serialize)]
+pub struct GroupCost {
+    pub name: String,
+    pub techniques: usize,
+    /// Distinct shaders with stats
+    pub shader_count: usize,
+    pub total_cost: u64,
+    /// Truncated to the report's `top`
+    pub shaders: Vec<ShaderCost>,
+}
+
+impl GroupCost {
+    pub fn peak_cost(&self) -> u32 {
+        self.shaders.first().map(|s| s.cost).unwrap_or(0)
+    }
+}
+
+#[derive(Serialize)]
+pub struct StatsReport {
+    /// Shaders with readable stats
+    pub shaders: usize,
+    /// Shaders whose blobs couldn't be read
+    pub without_stats: usize,
+    pub most_expensive: Vec<ShaderCost>,
+    pub passes: Vec<GroupCost>,
+    pub materials: Vec<GroupCost>,
+    /// Rows printed per table, groups in the JSON output aren't limited
+    #[serde(skip)]
+    pub top: usize,
+}
+
+impl StatsReport {
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+fn by_cost(a: &ShaderCost, b: &ShaderCost) -> std::cmp::Ordering {
+    b.cost.cmp(&a.cost).then(a.hash.cmp(&b.hash))
+}
+
+#[derive(Default)]
+struct GroupAcc {
+    techniques: usize,
+    shaders: HashSet<u64>,
+}
+
+/// Most expensive group first, ties broken by name
+fn into_groups(groups: HashMap<String, GroupAcc>, costs: &HashMap<u64, ShaderCost>, top: usize) -> Vec<GroupCost> {
+    let mut groups: Vec<GroupCost> = groups.into_iter()
+        .map(|(name, acc)| {
+            let mut shaders: Vec<ShaderCost> = acc.shaders.iter().filter_map(|h| costs.get(h)).cloned().collect();
+            shaders.sort_by(by_cost);
+            let total_cost = shaders.iter().map(|s| u64::from(s.cost)).sum();
+            let shader_count = shaders.len();
+            shaders.truncate(top);
+            GroupCost { name, techniques: acc.techniques, shader_count, total_cost, shaders }
+        })
+        .collect();
+
+    groups.sort_by(|a, b| b.peak_cost().cmp(&a.peak_cost()).then_with(|| a.name.cmp(&b.name)));
+    groups
+}
+
+/// Ranks shaders by `ShaderStats::cost`, overall and within each pass and material
+pub fn stats_report(manager: &Manager, top: usize) -> StatsReport {
+    let costs: HashMap<u64, ShaderCost> = manager.shaders.values()
+        .filter_map(|s| ShaderCost::new(s))
+        .map(|c| (c.hash, c))
+        .collect();
+
+    let mut passes: HashMap<String, GroupAcc> = HashMap::new();
+    let mut materials: HashMap<String, GroupAcc> = HashMap::new();
+
+    for m in manager.materials.values() {
+        for t in &m.techniques {
+            let shaders: Vec<u64> = [&t.vs, &t.ps].into_iter().flatten().map(|s| s.hash).collect();
+            for acc in [passes.entry(t.desc.pass.to_string()).or_default(), materials.entry_ref(&m.name).or_default()] {
+                acc.techniques += 1;
+                acc.shaders.extend(&shaders);
+            }
+        }
+    }
+
+    let mut most_expensive: Vec<ShaderCost> = costs.values().cloned().collect();
+    most_expensive.sort_by(by_cost);
+    most_expensive.truncate(top);
+
+    StatsReport {
+        shaders: costs.len(),
+        without_stats: manager.shaders.len() - costs.len(),
+        most_expensive,
+        passes: into_groups(passes, &costs, top),
+        materials: into_groups(materials, &costs, top),
+        top,
+    }
+}
+
+fn fmt_shader(f: &mut std::fmt::Formatter<'_>, indent: &str, s: &ShaderCost) -> std::fmt::Result {
+    let temps = s.stats.temp_registers.map(|t| t.to_string()).unwrap_or_else(|| "-".to_string());
+    writeln!(
+        f, "{}[{:016X}] {:<8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>6}",
+        indent, s.hash, s.stage, s.cost, s.stats.instructions, s.stats.alu,
+        s.stats.texture_samples, s.stats.texture_loads, s.stats.dynamic_flow_control, temps
+    )
+}
+
+fn fmt_header(f: &mut std::fmt::Formatter<'_>, indent: &str) -> std::fmt::Result {
+    writeln!(
+        f, "{}{:<18} {:<8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>6}",
+        indent, "Shader", "Stage", "Cost", "Instr", "ALU", "Samples", "Loads", "Branches", "Temps"
+    )
+}
+
+impl std::fmt::Display for StatsReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} shaders with stats, {} without", self.shaders, self.without_stats)?;
+
+        writeln!(f)?;
+        fmt_header(f, "")?;
+        for s in &self.most_expensive {
+            fmt_shader(f, "", s)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Most expensive shaders per pass")?;
+        for g in &self.passes {
+            writeln!(f)?;
+            writeln!(f, "{} ({} techniques, {} shaders, total cost {})", g.name, g.techniques, g.shader_count, g.total_cost)?;
+            fmt_header(f, "  ")?;
+            for s in &g.shaders {
+                fmt_shader(f, "  ", s)?;
+            }
+        }
+
+        writeln!(f)?;
+        writeln!(f, "{:<48} {:>10} {:>8} {:>10} {:>12} {:<18}", "Material", "Techniques", "Shaders", "Peak cost", "Total cost", "Most expensive")?;
+        for g in self.materials.iter().take(self.top) {
+            let hash = g.shaders.first().map(|s| format!("[{:016X}]", s.hash)).unwrap_or_default();
+            writeln!(
+                f, "{:<48} {:>10} {:>8} {:>10} {:>12} {:<18}",
+                g.name, g.techniques, g.shader_count, g.peak_cost(), g.total_cost, hash
+            )?;
+        }
+        if self.materials.len() > self.top {
+            writeln!(f, "  ... {} more", self.materials.len() - self.top)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::bitcode::tests::Writer;
+    use crate::container::Part;
+    use crate::spirv::tests::inst;
+
+    use super::*;
+
+    /// DXIL part around a bitcode module
+    fn dxil_part(bitcode: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        // Pixel shader 6.0, size in words, then the program header
+        data.extend((6u32 << 4).to_le_bytes());
+        data.extend((((24 + bitcode.len()) / 4) as u32).to_le_bytes());
+        data.extend(DxilProgram::MAGIC.to_le_bytes());
+        data.extend(0x100u32.to_le_bytes());
+        data.extend(16u32.to_le_bytes());
+        data.extend((bitcode.len() as u32).to_le_bytes());
+        data.extend_from_slice(bitcode);
+        data
+    }
+
+    /// main() samples twice, loads once and branches on a comparison
+    fn dxil_module() -> Vec<u8> {
+        let mut w = Writer::new();
+        w.enter(MODULE_BLOCK);
+
+        w.enter(TYPE_BLOCK);
+        w.record(TYPE_NUMENTRY, &[4]);
+        // 0 void, 1 float, 2 void(), 3 float(i32)
+        w.record(TYPE_VOID, &[]);
+        w.record(3, &[]);
+        w.record(TYPE_FUNCTION, &[0, 0]);
+        w.record(TYPE_FUNCTION, &[0, 1, 1]);
+        w.end();
+
+        // Values 0 main, 1 dx.op.sample.f32, 2 dx.op.textureLoad.f32, 3 llvm.dbg.value
+        w.record(MODULE_FUNCTION, &[2, 0, 0]);
+        w.record(MODULE_FUNCTION, &[3, 0, 1]);
+        w.record(MODULE_FUNCTION, &[3, 0, 1]);
+        w.record(MODULE_FUNCTION, &[2, 0, 1]);
+
+        // Value 4
+        w.enter(CONSTANTS_BLOCK);
+        w.record(CST_SETTYPE, &[1]);
+        w.record(4, &[2]);
+        w.end();
+
+        w.enter(FUNCTION_BLOCK);
+        w.record(INST_DECLAREBLOCKS, &[3]);
+        // Values 5 and 6, the callee is relative to the defined value
+        w.record(INST_CALL, &[0, 0, 4, 4]);
+        w.record(INST_CALL, &[0, 0, 4, 5]);
+        // Void call, no value
+        w.record(INST_CALL, &[0, CALL_EXPLICIT_TYPE, 2, 4]);
+        w.record(INST_CALL, &[0, 0, 6, 7]);
+        w.record(INST_DEBUG_LOC, &[1, 1, 0, 0]);
+        w.record(INST_BINOP, &[3, 2, 0]);
+        w.record(INST_CMP2, &[1, 2, 1]);
+        w.record(INST_BR, &[1, 2, 1]);
+        w.record(INST_BR, &[2]);
+        w.record(10, &[]);
+        w.end();
+
+        w.enter(VALUE_SYMTAB_BLOCK);
+        for (id, name) in ["main", "dx.op.sample.f32", "dx.op.textureLoad.f32", "llvm.dbg.value"].iter().enumerate() {
+            let ops: Vec<u64> = [id as u64, 0].into_iter().chain(name.bytes().map(u64::from)).collect();
+            w.record(VST_FNENTRY, &ops);
+        }
+        w.end();
+
+        w.end();
+        w.finish()
+    }
+
+    #[test]
+    fn dxil() {
+        let container = Container {
+            parts: vec![ Part { fourcc: FourCC::DXIL, data: dxil_part(&dxil_module()) } ],
+            ..Default::default()
+        };
+        let stats = ShaderStats::from_blob(&container.to_bytes()).unwrap().unwrap();
+
+        assert_eq!(stats, ShaderStats {
+            instructions: 8,
+            alu: 2,
+            texture_samples: 2,
+            texture_loads: 1,
+            dynamic_flow_control: 1,
+            temp_registers: None,
+        });
+    }
+
+    #[test]
+    fn pointer_cycle() {
+        let mut w = Writer::new();
+        w.enter(MODULE_BLOCK);
+        w.enter(TYPE_BLOCK);
+        // 0 and 1 point at each other, 3 points at the void() at 2
+        w.record(TYPE_POINTER, &[1]);
+        w.record(TYPE_POINTER, &[0]);
+        w.record(TYPE_FUNCTION, &[0, 4]);
+        w.record(TYPE_POINTER, &[2]);
+        w.end();
+        w.end();
+
+        let blocks = bitcode::parse(&w.finish()).unwrap();
+        let dxil = DxilModule::new(&blocks[0]);
+        assert_eq!(dxil.function_type(0), None);
+        assert_eq!(dxil.function_type(3), Some((4, 0)));
+    }
+
+    #[test]
+    fn dxbc() {
+        let mut table = [0u32; 37];
+        table[0] = 40;
+        table[1] = 6;
+        table[4] = 20;
+        table[8] = 2;
+        table[14] = 3;
+        table[17] = 1;
+        table[15] = 4;
+
+        let container = Container {
+            parts: vec![
+                Part { fourcc: FourCC::SHEX, data: 0x0000_0050u32.to_le_bytes().to_vec() },
+                Part { fourcc: FourCC::STAT, data: table.iter().flat_map(|v| v.to_le_bytes()).collect() },
+            ],
+            ..Default::default()
+        };
+        let stats = ShaderStats::from_blob(&container.to_bytes()).unwrap().unwrap();
+        assert_eq!(stats.instructions, 40);
+        assert_eq!(stats.temp_registers, Some(6));
+        assert_eq!((stats.alu, stats.texture_samples, stats.texture_loads, stats.dynamic_flow_control), (20, 4, 4, 2));
+        assert_eq!(stats.cost(), 40 + 16 + 8 + 4);
+
+        assert!(ShaderStats::parse_dxbc_stat(&[0; 16]).is_err());
+
+        // Corrupt counters saturate rather than overflow
+        let corrupt: Vec<u8> = [u32::MAX; 22].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let stats = ShaderStats::parse_dxbc_stat(&corrupt).unwrap();
+        assert_eq!((stats.alu, stats.cost()), (u32::MAX, u32::MAX));
+        assert!(ShaderStats::from_blob(b"not a shader").is_none());
+    }
+
+    #[test]
+    fn spirv() {
+        let mut w: Vec<u32> = vec![ spirv::SpirvModule::MAGIC, 0x0001_0000, 0, 20, 0 ];
+        inst(&mut w, OP_FUNCTION, &[1, 2, 0, 3]);
+        inst(&mut w, OP_LABEL, &[4]);
+        inst(&mut w, 87, &[5, 6, 7, 8]);
+        inst(&mut w, OP_IMAGE_FETCH, &[5, 9, 7, 8]);
+        inst(&mut w, 129, &[5, 10, 9, 9]);
+        inst(&mut w, OP_BRANCH_CONDITIONAL, &[11, 12, 13]);
+        inst(&mut w, OP_LABEL, &[12]);
+        inst(&mut w, 253, &[]);
+        inst(&mut w, OP_FUNCTION_END, &[]);
+        let data: Vec<u8> = w.iter().flat_map(|v| v.to_le_bytes()).collect();
+
+        let stats = ShaderStats::from_blob(&data).unwrap().unwrap();
+        assert_eq!(stats, ShaderStats {
+            instructions: 5,
+            alu: 1,
+            texture_samples: 1,
+            texture_loads: 1,
+            dynamic_flow_control: 1,
+            temp_registers: None,
+        });
+    }
+
+    #[test]
+    fn report() {
+        let mut cache = crate::test_util::test_cache();
+        let stat = |instructions: u32| {
+            let mut table = [0u32; 22];
+            table[0] = instructions;
+            Container {
+                parts: vec![ Part { fourcc: FourCC::STAT, data: table.iter().flat_map(|v| v.to_le_bytes()).collect() } ],
+                ..Default::default()
+            }.to_bytes()
+        };
+        for (i, s) in cache.shaders.iter_mut().enumerate() {
+            s.compiled = stat(10 * (i as u32 + 1));
+        }
+        cache.info = cache.layout();
+
+        let manager = Manager::from_dyn_cache(cache).unwrap();
+        let report = stats_report(&manager, 1);
+        assert_eq!(report.without_stats, 0);
+        assert_eq!(report.most_expensive.len(), 1);
+
+        let top = &report.most_expensive[0];
+        assert!(manager.shaders.values().all(|s| s.stats.as_ref().unwrap().cost() <= top.cost));
+        assert!(report.passes.iter().all(|g| g.shaders.len() <= 1 && g.shader_count >= g.shaders.len()));
+
+        let shader = manager.shaders.values().find(|s| s.hash == top.hash).map(Rc::clone).unwrap();
+        assert_eq!(shader.stats.as_ref().unwrap().instructions, top.stats.instructions);
+
+        assert!(report.to_json().unwrap().contains("\"most_expensive\""));
+        assert!(report.to_string().contains("Most expensive shaders per pass"));
+    }
+}
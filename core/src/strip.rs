@@ -0,0 +1,161 @@
+use anyhow::Result;
+
+use crate::bundle::dyn_cache::DynamicCacheFile;
+use crate::gc::{collect_garbage, GcReport};
+use crate::material::TechniqueDesc;
+use crate::renderstage::{check_passes, unknown_stages, RenderStage};
+use crate::rtti_types::enums::EMaterialVertexFactory;
+
+pub type TechniquePredicate = Box<dyn Fn(&TechniqueDesc) -> bool>;
+
+/// Selects the techniques to strip, a technique is removed if any of the rules match
+#[derive(Default)]
+pub struct StripRules {
+    pub passes: Vec<RenderStage>,
+    pub vertex_factories: Vec<EMaterialVertexFactory>,
+    /// Extra test, e.g. on the technique flags
+    pub predicate: Option<TechniquePredicate>,
+}
+
+impl StripRules {
+    /// Passes and vertex factories that are only used by debug views and the editor
+    pub fn debug() -> Self {
+        StripRules {
+            passes: vec![
+                RenderStage::Wireframe,
+                RenderStage::WireframeSolid,
+                RenderStage::Overdraw,
+                RenderStage::ParticleOverdraw,
+                RenderStage::Debugdraw,
+                RenderStage::DebugdrawWireframe,
+                RenderStage::TopDownCarProxyDebug,
+            ],
+            vertex_factories: vec![ EMaterialVertexFactory::Debug ],
+            predicate: None,
+        }
+    }
+
+    pub fn matches(&self, desc: &TechniqueDesc) -> bool {
+        self.passes.contains(&desc.pass)
+        || self.vertex_factories.contains(&desc.vertex_factory)
+        || self.predicate.as_ref().is_some_and(|f| f(desc))
+    }
+}
+
+pub struct StripReport {
+    pub techniques_removed: usize,
+    pub gc: GcReport,
+    /// Stripped techniques that a remaining technique may fall back to
+    pub warnings: Vec<String>,
+}
+
+impl std::fmt::Display for StripReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Techniques removed: {}", self.techniques_removed)?;
+        write!(f, "{}", self.gc)?;
+        for w in &self.warnings {
+            writeln!(f, "WARNING: {}", w)?;
+        }
+        Ok(())
+    }
+}
+
+/// Removes every technique matching the rules, then garbage collects the orphaned chunks.
+///
+/// A non-zero `fallback_index` is treated as the `index` of another technique in the
+/// same material, any stripped technique that is still referenced that way is reported.
+pub fn strip_techniques(cache: &mut DynamicCacheFile, rules: &StripRules) -> Result<StripReport> {
+    check_passes(&rules.passes, || unknown_stages(cache))?;
+
+    let mut descs: Vec<(String, TechniqueDesc)> = Vec::with_capacity(cache.materials.len());
+    for m in &cache.materials {
+        descs.push((m.material_name().to_string(), m.decode_desc()?));
+    }
+
+    let strip: Vec<bool> = descs.iter().map(|(_, d)| rules.matches(d)).collect();
+
+    let mut warnings: Vec<String> = Vec::new();
+    for (i, (mat, desc)) in descs.iter().enumerate() {
+        if !strip[i] {
+            continue;
+        }
+
+        let users = descs.iter().zip(&strip)
+            .filter(|((m, d), s)| !**s && m == mat && d.fallback_index != 0 && u32::from(d.fallback_index) == desc.index)
+            .count();
+
+        if users > 0 {
+            warnings.push(format!(
+                "{} {} may be reached through the fallback of {} remaining techniques",
+                mat, desc, users
+            ));
+        }
+    }
+
+    let count = cache.materials.len();
+    let mut strip = strip.into_iter();
+    cache.materials.retain(|_| !strip.next().unwrap());
+
+    Ok(StripReport {
+        techniques_removed: count - cache.materials.len(),
+        gc: collect_garbage(cache, false),
+        warnings,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::bundle::dyn_cache::*;
+    use crate::test_util::{desc, shader, technique_with, test_cache};
+
+    use super::*;
+
+    fn technique(index: u32, pass: RenderStage, fallback_index: u8, vs_hash: u64) -> MaterialChunk {
+        technique_with("metal_base", &TechniqueDesc { pass, fallback_index, ..desc(index) }, vs_hash, 0)
+    }
+
+    /// The shared fixture with a third shader and techniques in debug passes
+    fn debug_cache() -> DynamicCacheFile {
+        let mut cache = test_cache();
+        cache.shaders.push(shader(3));
+        cache.materials = vec![
+            technique(0, RenderStage::GbufferRegular, 0, 1),
+            technique(1, RenderStage::Wireframe, 0, 2),
+            technique(2, RenderStage::Overdraw, 0, 3),
+            technique(3, RenderStage::DepthPrepass, 2, 1),
+        ];
+        cache
+    }
+
+    #[test]
+    fn strip_debug() {
+        let mut cache = debug_cache();
+        let report = strip_techniques(&mut cache, &StripRules::debug()).unwrap();
+
+        assert_eq!(report.techniques_removed, 2);
+        assert_eq!(cache.materials.len(), 2);
+        assert_eq!(report.gc.shader_count, 2);
+        assert_eq!(cache.shaders.len(), 1);
+
+        // Overdraw (index 2) is the fallback of the depth prepass
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].contains("renderstage_overdraw"));
+    }
+
+    #[test]
+    fn strip_predicate() {
+        let mut cache = debug_cache();
+        let rules = StripRules {
+            predicate: Some(Box::new(|d: &TechniqueDesc| d.index == 0)),
+            ..Default::default()
+        };
+
+        let report = strip_techniques(&mut cache, &rules).unwrap();
+
+        assert_eq!(report.techniques_removed, 1);
+        // Shader 1 is still used by the depth prepass
+        assert_eq!(report.gc.shader_count, 0);
+        assert!(report.warnings.is_empty());
+    }
+}
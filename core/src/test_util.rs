@@ -0,0 +1,155 @@
+use crate::bundle::dyn_cache::{DynamicCacheFile, InfoBlock, MaterialChunk, ParamsChunk, ShaderChunk};
+use crate::material::TechniqueDesc;
+use crate::renderstage::RenderStage;
+use crate::rtti_types::cname::CName;
+use crate::rtti_types::enums::*;
+use crate::rtti_types::structs::SampleStateInfo;
+use crate::rtti_types::timestamp::TimestampTD;
+
+/// A GbufferRegular MeshStatic technique without fallback
+pub(crate) fn desc(index: u32) -> TechniqueDesc {
+    TechniqueDesc {
+        index,
+        pass: RenderStage::GbufferRegular,
+        pass_index: 0,
+        fallback_index: 0,
+        vertex_factory: EMaterialVertexFactory::MeshStatic,
+        is_dismembered: false,
+        is_discarded: false,
+        is_preskinned: false,
+    }
+}
+
+pub(crate) fn technique_with(material: &str, desc: &TechniqueDesc, vs_hash: u64, ps_hash: u64) -> MaterialChunk {
+    MaterialChunk {
+        hash: desc.encode_material_hash(material),
+        name: CName::new(&desc.encode_material_name(material)),
+        vs_hash,
+        ps_hash,
+        timestamp: TimestampTD::default(),
+        vs_samplers: Vec::new(),
+        ps_samplers: Vec::new(),
+    }
+}
+
+pub(crate) fn technique(material: &str, index: u32, vs_hash: u64, ps_hash: u64) -> MaterialChunk {
+    technique_with(material, &desc(index), vs_hash, ps_hash)
+}
+
+pub(crate) fn shader(hash: u64) -> ShaderChunk {
+    ShaderChunk { hash, params: 0xA, compiled: vec![hash as u8; 4] }
+}
+
+pub(crate) fn params(hash: u64) -> ParamsChunk {
+    ParamsChunk { hash, mat_mod_mask: 0, params: Vec::new() }
+}
+
+pub(crate) fn sampler(register: u8) -> SampleStateInfo {
+    SampleStateInfo {
+        filteringMin: ETextureFilteringMin::Linear,
+        filteringMag: ETextureFilteringMag::Linear,
+        filteringMip: ETextureFilteringMip::Linear,
+        addressU: ETextureAddressing::Wrap,
+        addressV: ETextureAddressing::Wrap,
+        addressW: ETextureAddressing::Wrap,
+        comparisonFunc: ETextureComparisonFunction::None,
+        register,
+    }
+}
+
+/// Shaders 1 and 2 sharing params 0xA, used by two `metal_base` techniques,
+/// with a footer matching the chunks
+pub(crate) fn test_cache() -> DynamicCacheFile {
+    let mut cache = DynamicCacheFile {
+        info: InfoBlock::default(),
+        shaders: vec![ shader(1), shader(2) ],
+        materials: vec![ technique("metal_base", 0, 1, 2), technique("metal_base", 1, 1, 0) ],
+        params: vec![ params(0xA) ],
+        timestamps: Vec::new(),
+        includes: Vec::new(),
+    };
+    cache.info = cache.layout();
+    cache
+}
+
+/// Builds an ISG1 part from (semantic, index, system value, type, register, mask)
+pub(crate) fn isg1(elements: &[(&str, u32, u32, u32, u32, u8)]) -> Vec<u8> {
+    let names_offset = 8 + 32 * elements.len();
+    let mut names: Vec<u8> = Vec::new();
+    let mut data: Vec<u8> = Vec::new();
+    data.extend_from_slice(&(elements.len() as u32).to_le_bytes());
+    data.extend_from_slice(&8u32.to_le_bytes());
+
+    for (semantic, index, sv, kind, register, mask) in elements {
+        let offset = (names_offset + names.len()) as u32;
+        names.extend_from_slice(semantic.as_bytes());
+        names.push(0);
+
+        for v in [0, offset, *index, *sv, *kind, *register] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        data.extend_from_slice(&[*mask, *mask, 0, 0]);
+        data.extend_from_slice(&0u32.to_le_bytes());
+    }
+
+    data.extend_from_slice(&names);
+    data
+}
+
+pub(crate) fn push(data: &mut Vec<u8>, values: &[u32]) {
+    for v in values {
+        data.extend_from_slice(&v.to_le_bytes());
+    }
+}
+
+/// Shader model 5.1 RDEF with one cbuffer holding the variables, a texture, and the samplers
+pub(crate) fn rdef(variables: &[(&str, u32, u32)], samplers: &[u32]) -> Vec<u8> {
+    rdef_model(1, variables, samplers)
+}
+
+/// RDEF of shader model 5.`minor`, bindings only have a register space from 5.1
+pub(crate) fn rdef_model(minor: u8, variables: &[(&str, u32, u32)], samplers: &[u32]) -> Vec<u8> {
+    let binding_size = if minor >= 1 { 40 } else { 32 };
+    let bindings = 2 + samplers.len() as u32;
+    let cbuffer_offset = 60;
+    let var_offset = cbuffer_offset + 24;
+    let binding_offset = var_offset + 40 * variables.len() as u32;
+    let mut names_offset = binding_offset + binding_size * bindings;
+
+    let mut names: Vec<u8> = Vec::new();
+    let mut name = |s: &str| {
+        let offset = names_offset;
+        names.extend_from_slice(s.as_bytes());
+        names.push(0);
+        names_offset += s.len() as u32 + 1;
+        offset
+    };
+
+    let mut data: Vec<u8> = Vec::new();
+    push(&mut data, &[1, cbuffer_offset, bindings, binding_offset]);
+    data.extend_from_slice(&[minor, 5, 0xFF, 0xFF]);
+    push(&mut data, &[0, 0]);
+    data.extend_from_slice(b"RD11");
+    push(&mut data, &[60, 24, binding_size, 40, 36, 12, 0]);
+
+    let size = variables.iter().map(|v| v.1 + v.2).max().unwrap_or(0).next_multiple_of(16);
+    push(&mut data, &[name("Material"), variables.len() as u32, var_offset, size, 0, 0]);
+    for (n, offset, size) in variables {
+        push(&mut data, &[name(n), *offset, *size, 2, 0, 0, 0, 0, 0, 0]);
+    }
+
+    let mut binding = |values: [u32; 8], id: u32| {
+        push(&mut data, &values);
+        if minor >= 1 {
+            push(&mut data, &[0, id]);
+        }
+    };
+    binding([name("Material"), 0, 0, 0, 0, 1, 1, 0], 0);
+    binding([name("Albedo"), 2, 5, 4, 0, 0, 1, 0], 1);
+    for s in samplers {
+        binding([name("Sampler"), 3, 0, 0, 0, *s, 1, 0], 2);
+    }
+
+    data.extend_from_slice(&names);
+    data
+}
@@ -0,0 +1,584 @@
+use hashbrown::{HashMap, HashSet};
+use serde::Serialize;
+
+use crate::bundle::dyn_cache::{DynamicCacheFile, ParamsChunk, ShaderChunk};
+use crate::container::Container;
+use crate::digest;
+use crate::reflection::{Reflection, ResourceClass};
+use crate::renderstage;
+use crate::signature::{self, Signature};
+use crate::rtti_types::structs::SampleStateInfo;
+use crate::shader::ShaderType;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Info    => write!(f, "INFO"),
+            Severity::Warning => write!(f, "WARNING"),
+            Severity::Error   => write!(f, "ERROR"),
+        }
+    }
+}
+
+/// The invariant a finding breaks
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+pub enum Check {
+    /// A technique's `vs_hash`/`ps_hash` has no shader
+    MissingShader,
+    /// A shader's params hash has no params chunk
+    MissingParams,
+    DuplicateShader,
+    DuplicateTechnique,
+    DuplicateParams,
+    DuplicateTimestamp,
+    DuplicateInclude,
+    /// Footer counts, sizes or offsets don't describe the chunks
+    Footer,
+    /// Two samplers of the same stage bound to one register
+    SamplerRegister,
+    /// The technique string can't be parsed
+    TechniqueName,
+    /// A technique uses a render stage missing from the registry, new in a patch or a typo
+    UnknownPass,
+    /// The composite hash doesn't match the material name and technique
+    TechniqueHash,
+    /// A technique's sampler states and the samplers its shader declares disagree
+    SamplerBinding,
+    /// A param slot doesn't match the constant buffer variable of the same name
+    ParamBinding,
+    /// A shader container's digest doesn't match its contents
+    ShaderDigest,
+    /// A technique uses a shader as a stage other than the one its blob was compiled for
+    ShaderStage,
+    /// A technique's VS outputs don't line up with its PS inputs, or a replacement's
+    /// signatures don't line up with the stages around it
+    Signature,
+    /// A replacement binds resources the original doesn't
+    ResourceBinding,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    pub check: Check,
+    pub message: String,
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {:?}: {}", self.severity, self.check, self.message)
+    }
+}
+
+#[derive(Default, Serialize)]
+pub struct ValidationReport {
+    pub findings: Vec<Finding>,
+}
+
+impl ValidationReport {
+    fn push(&mut self, severity: Severity, check: Check, message: String) {
+        self.findings.push(Finding { severity, check, message });
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings.iter().filter(|f| f.severity == severity).count()
+    }
+
+    /// No errors, warnings are allowed
+    pub fn is_ok(&self) -> bool {
+        self.count(Severity::Error) == 0
+    }
+
+    pub fn has(&self, check: Check) -> bool {
+        self.findings.iter().any(|f| f.check == check)
+    }
+}
+
+impl std::fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for finding in &self.findings {
+            writeln!(f, "{}", finding)?;
+        }
+        writeln!(
+            f, "{} errors, {} warnings",
+            self.count(Severity::Error), self.count(Severity::Warning)
+        )
+    }
+}
+
+/// Checks every cross-reference invariant of the cache.
+///
+/// Findings are grouped by check, in the file order of the chunks involved.
+pub fn validate(cache: &DynamicCacheFile) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    check_footer(cache, &mut report);
+    check_duplicates(cache, &mut report);
+
+    //--------------------------------------------------------------------------
+    // References
+
+    let shaders: HashSet<u64> = cache.shaders.iter().map(|s| s.hash).collect();
+    let params: HashSet<u64> = cache.params.iter().map(|p| p.hash).collect();
+
+    for m in &cache.materials {
+        for (stage, hash) in [("VS", m.vs_hash), ("PS", m.ps_hash)] {
+            if hash != 0 && !shaders.contains(&hash) {
+                report.push(Severity::Error, Check::MissingShader, format!("{} references missing {} [{:016X}]", m.name, stage, hash));
+            }
+        }
+    }
+
+    for s in &cache.shaders {
+        if !params.contains(&s.params) {
+            report.push(Severity::Error, Check::MissingParams, format!("shader [{:016X}] references missing params [{:016X}]", s.hash, s.params));
+        }
+    }
+
+    let stages: HashMap<u64, ShaderType> = cache.shaders.iter()
+        .map(|s| (s.hash, ShaderType::detect(&s.compiled)))
+        .filter(|(_, t)| *t != ShaderType::Unknown)
+        .collect();
+
+    for m in &cache.materials {
+        for (stage, hash, expected) in [("VS", m.vs_hash, ShaderType::Vertex), ("PS", m.ps_hash, ShaderType::Pixel)] {
+            match stages.get(&hash) {
+                Some(kind) if *kind != expected => report.push(Severity::Error, Check::ShaderStage, format!(
+                    "{} uses [{:016X}] as {}, but it is a {} shader", m.name, hash, stage, kind
+                )),
+                _ => {},
+            }
+        }
+    }
+
+    // The runtime rejects containers with a stale digest
+    for s in &cache.shaders {
+        if digest::compute_digest(&s.compiled).is_some() && !digest::verify_digest(&s.compiled) {
+            report.push(Severity::Error, Check::ShaderDigest, format!("shader [{:016X}] container digest doesn't match its contents", s.hash));
+        }
+    }
+
+    //--------------------------------------------------------------------------
+    // Techniques
+
+    for m in &cache.materials {
+        for (stage, samplers) in [("VS", &m.vs_samplers), ("PS", &m.ps_samplers)] {
+            for register in duplicate_registers(samplers) {
+                report.push(Severity::Error, Check::SamplerRegister, format!("{} binds {} register {} more than once", m.name, stage, register));
+            }
+        }
+
+        match m.decode_desc() {
+            Ok(desc) => {
+                let expected = desc.encode_material_hash(m.material_name());
+                if expected != m.hash {
+                    report.push(Severity::Error, Check::TechniqueHash, format!(
+                        "{} has hash [{:016X}], expected [{:016X}]", m.name, m.hash, expected
+                    ));
+                }
+            },
+            Err(e) => report.push(Severity::Error, Check::TechniqueName, format!("{}: {}", m.name, e)),
+        }
+    }
+
+    // Unparsable techniques are already reported above
+    for (stage, count) in renderstage::unknown_stages(cache).unwrap_or_default() {
+        report.push(Severity::Info, Check::UnknownPass, format!("{} techniques use the unregistered render stage {}", count, stage));
+    }
+
+    check_bindings(cache, &mut report);
+    check_linkage(cache, &mut report);
+
+    report
+}
+
+/// Only the VS to PS interface check of [`validate`], cheap enough to run before every save
+pub fn linkage(cache: &DynamicCacheFile) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    check_linkage(cache, &mut report);
+    report
+}
+
+/// Compares the output signature of each technique's VS with the input signature of
+/// its PS. Pairs shared by several techniques are reported once, under the first.
+/// Shaders without signature parts are skipped.
+fn check_linkage(cache: &DynamicCacheFile, report: &mut ValidationReport) {
+    let parse = |s: &ShaderChunk, input: bool| -> Option<Signature> {
+        let container = Container::parse(&s.compiled).ok()?;
+        let signature = if input { Signature::input(&container) } else { Signature::output(&container) };
+        signature?.ok()
+    };
+    let outputs: HashMap<u64, Signature> = cache.shaders.iter()
+        .filter_map(|s| Some((s.hash, parse(s, false)?)))
+        .collect();
+    let inputs: HashMap<u64, Signature> = cache.shaders.iter()
+        .filter_map(|s| Some((s.hash, parse(s, true)?)))
+        .collect();
+
+    let mut seen: HashSet<(u64, u64)> = HashSet::new();
+    for m in &cache.materials {
+        if !seen.insert((m.vs_hash, m.ps_hash)) {
+            continue;
+        }
+        let (Some(vs), Some(ps)) = (outputs.get(&m.vs_hash), inputs.get(&m.ps_hash)) else {
+            continue;
+        };
+
+        for issue in signature::link(vs, ps, true) {
+            report.push(Severity::Error, Check::Signature, format!(
+                "{} VS [{:016X}] to PS [{:016X}]: {}", m.name, m.vs_hash, m.ps_hash, issue
+            ));
+        }
+        for issue in signature::link_modes(vs, ps) {
+            report.push(Severity::Warning, Check::Signature, format!(
+                "{} VS [{:016X}] to PS [{:016X}]: {}", m.name, m.vs_hash, m.ps_hash, issue
+            ));
+        }
+    }
+}
+
+/// Compares the reflected bindings of each shader with the sampler states of the techniques
+/// using it, and with its params. Shaders without reflection data are skipped.
+fn check_bindings(cache: &DynamicCacheFile, report: &mut ValidationReport) {
+    let reflections: HashMap<u64, Reflection> = cache.shaders.iter()
+        .filter_map(|s| Some((s.hash, Reflection::from_blob(&s.compiled)?.ok()?)))
+        .collect();
+
+    for m in &cache.materials {
+        for (stage, hash, samplers) in [("VS", m.vs_hash, &m.vs_samplers), ("PS", m.ps_hash, &m.ps_samplers)] {
+            let Some(reflection) = reflections.get(&hash) else {
+                continue;
+            };
+
+            for s in samplers {
+                if reflection.sampler(u32::from(s.register)).is_none() {
+                    report.push(Severity::Warning, Check::SamplerBinding, format!(
+                        "{} has a {} sampler state for register s{}, which [{:016X}] doesn't declare",
+                        m.name, stage, s.register, hash
+                    ));
+                }
+            }
+
+            for b in reflection.of_class(ResourceClass::Sampler).filter(|b| b.space == 0) {
+                let covered = samplers.iter().any(|s| u32::from(s.register) >= b.register && u32::from(s.register) - b.register < b.count);
+                if !covered {
+                    report.push(Severity::Warning, Check::SamplerBinding, format!(
+                        "{} has no {} sampler state for register s{} declared by [{:016X}]",
+                        m.name, stage, b.register, hash
+                    ));
+                }
+            }
+        }
+    }
+
+    let params: HashMap<u64, &ParamsChunk> = cache.params.iter().map(|p| (p.hash, p)).collect();
+    for s in &cache.shaders {
+        let (Some(reflection), Some(chunk)) = (reflections.get(&s.hash), params.get(&s.params)) else {
+            continue;
+        };
+        // PSV0 has no variable names to match against
+        if reflection.of_class(ResourceClass::CBuffer).all(|b| b.variables.is_empty()) {
+            continue;
+        }
+
+        for p in &chunk.params {
+            match reflection.find_variable(p.name.as_str()) {
+                None => report.push(Severity::Warning, Check::ParamBinding, format!(
+                    "param {} of shader [{:016X}] isn't in any constant buffer", p.name, s.hash
+                )),
+                Some((b, v)) if v.offset != u32::from(p.value) * 16 => report.push(Severity::Error, Check::ParamBinding, format!(
+                    "param {} of shader [{:016X}] has slot {} but is at offset {} of {}", p.name, s.hash, p.value, v.offset, b.name
+                )),
+                Some((b, v)) if v.size > u32::from(p.size) * 16 => report.push(Severity::Warning, Check::ParamBinding, format!(
+                    "param {} of shader [{:016X}] spans {} registers but is {} bytes in {}", p.name, s.hash, p.size, v.size, b.name
+                )),
+                Some(_) => {},
+            }
+        }
+    }
+}
+
+fn duplicate_registers(samplers: &[SampleStateInfo]) -> Vec<u8> {
+    let mut seen: HashSet<u8> = HashSet::new();
+    let mut dupes: Vec<u8> = Vec::new();
+    for s in samplers {
+        if !seen.insert(s.register) && !dupes.contains(&s.register) {
+            dupes.push(s.register);
+        }
+    }
+    dupes
+}
+
+fn check_footer(cache: &DynamicCacheFile, report: &mut ValidationReport) {
+    let info = &cache.info;
+    let layout = cache.layout();
+
+    let fields: [(&str, u64, u64); 14] = [
+        ("shader_count",    info.shader_count.into(),   layout.shader_count.into()),
+        ("shader_size",     info.shader_size,           layout.shader_size),
+        ("material_count",  info.material_count.into(), layout.material_count.into()),
+        ("material_size",   info.material_size,         layout.material_size),
+        ("material_offset", info.material_offset,       layout.material_offset),
+        ("param_count",     info.param_count.into(),    layout.param_count.into()),
+        ("param_size",      info.param_size,            layout.param_size),
+        ("param_offset",    info.param_offset,          layout.param_offset),
+        ("include_count",   info.include_count.into(),  layout.include_count.into()),
+        ("include_size",    info.include_size,          layout.include_size),
+        ("include_offset",  info.include_offset,        layout.include_offset),
+        ("time_size",       info.time_size,             layout.time_size),
+        ("time_offset",     info.time_offset,           layout.time_offset),
+        // Every section must end before the footer starts
+        ("end",             info.include_offset + info.include_size, layout.include_offset + layout.include_size),
+    ];
+
+    for (name, stored, expected) in fields {
+        if stored != expected {
+            report.push(Severity::Error, Check::Footer, format!("footer {} is {}, expected {}", name, stored, expected));
+        }
+    }
+}
+
+fn check_duplicates(cache: &DynamicCacheFile, report: &mut ValidationReport) {
+    // Identical copies are only wasteful, differing copies make the lookup ambiguous
+    let mut shaders: HashMap<u64, &ShaderChunk> = HashMap::new();
+    for s in &cache.shaders {
+        match shaders.get(&s.hash) {
+            None => { shaders.insert(s.hash, s); },
+            Some(first) if first.compiled == s.compiled && first.params == s.params => {
+                report.push(Severity::Warning, Check::DuplicateShader, format!("shader [{:016X}] is stored more than once", s.hash));
+            },
+            Some(_) => {
+                report.push(Severity::Error, Check::DuplicateShader, format!("shader [{:016X}] is stored more than once with different contents", s.hash));
+            },
+        }
+    }
+
+    let mut techniques: HashSet<u64> = HashSet::new();
+    for m in &cache.materials {
+        if !techniques.insert(m.hash) {
+            report.push(Severity::Error, Check::DuplicateTechnique, format!("{} [{:016X}] is stored more than once", m.name, m.hash));
+        }
+    }
+
+    let mut params: HashMap<u64, &ParamsChunk> = HashMap::new();
+    for p in &cache.params {
+        match params.get(&p.hash) {
+            None => { params.insert(p.hash, p); },
+            Some(first) if first.same_layout(p) => {
+                report.push(Severity::Warning, Check::DuplicateParams, format!("params [{:016X}] is stored more than once", p.hash));
+            },
+            Some(_) => {
+                report.push(Severity::Error, Check::DuplicateParams, format!("params [{:016X}] is stored more than once with different layouts", p.hash));
+            },
+        }
+    }
+
+    let mut timestamps: HashSet<u32> = HashSet::new();
+    for t in &cache.timestamps {
+        if !timestamps.insert(t.hash) {
+            report.push(Severity::Warning, Check::DuplicateTimestamp, format!("timestamp [{:08X}] is stored more than once", t.hash));
+        }
+    }
+
+    let mut includes: HashSet<&str> = HashSet::new();
+    for i in &cache.includes {
+        if !includes.insert(i.path.as_str()) {
+            report.push(Severity::Warning, Check::DuplicateInclude, format!("include {} is stored more than once", i.path));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::bundle::dyn_cache::*;
+    use crate::container::{Container, FourCC};
+    use crate::rtti_types::cname::CName;
+    use crate::test_util::{isg1, rdef, sampler, test_cache};
+
+    use super::*;
+
+    #[test]
+    fn valid() {
+        let report = validate(&test_cache());
+        assert!(report.findings.is_empty(), "{}", report);
+    }
+
+    #[test]
+    fn loaded_footer() {
+        let mut cache = test_cache();
+        let mut data = std::io::Cursor::new(Vec::new());
+        cache.save(&mut data).unwrap();
+        data.set_position(0);
+
+        let loaded = DynamicCacheFile::load(&mut data).unwrap();
+        assert!(!validate(&loaded).has(Check::Footer));
+    }
+
+    #[test]
+    fn references() {
+        let mut cache = test_cache();
+        cache.materials[1].ps_hash = 3;
+        cache.shaders[1].params = 0xB;
+        cache.info = cache.layout();
+
+        let report = validate(&cache);
+        assert_eq!(report.count(Severity::Error), 2);
+        assert!(report.has(Check::MissingShader));
+        assert!(report.has(Check::MissingParams));
+    }
+
+    #[test]
+    fn duplicates() {
+        let mut cache = test_cache();
+        cache.shaders.push(cache.shaders[0].clone());
+        cache.materials.push(cache.materials[0].clone());
+        cache.info = cache.layout();
+
+        let report = validate(&cache);
+        assert_eq!(report.count(Severity::Warning), 1);
+        assert_eq!(report.count(Severity::Error), 1);
+        assert!(report.has(Check::DuplicateShader));
+        assert!(report.has(Check::DuplicateTechnique));
+    }
+
+    #[test]
+    fn footer_and_techniques() {
+        let mut cache = test_cache();
+        cache.info.param_count = 2;
+        cache.materials[0].hash ^= 1;
+        cache.materials[0].ps_samplers = vec![ sampler(0), sampler(1), sampler(0) ];
+
+        let report = validate(&cache);
+        assert!(report.has(Check::Footer));
+        assert!(report.has(Check::TechniqueHash));
+        assert!(report.has(Check::SamplerRegister));
+        assert!(!report.is_ok());
+    }
+
+    #[test]
+    fn garbage_name() {
+        let mut cache = test_cache();
+        cache.materials[1].name = CName::new("metal_base not a technique");
+
+        let report = validate(&cache);
+        assert!(report.has(Check::TechniqueName));
+        assert!(!report.has(Check::UnknownPass));
+        assert!(!report.is_ok());
+    }
+
+    #[test]
+    fn unknown_pass() {
+        let mut cache = test_cache();
+        let name = cache.materials[1].name.as_str().replace("renderstage_gbuffer_regular", "renderstage_new_in_patch");
+        cache.materials[1].name = CName::new(&name);
+        cache.materials[1].hash = cache.materials[1].decode_desc().unwrap().encode_material_hash("metal_base");
+        cache.info = cache.layout();
+
+        let report = validate(&cache);
+        assert_eq!(report.findings.len(), 1, "{}", report);
+        assert_eq!(report.findings[0].severity, Severity::Info);
+        assert_eq!(report.findings[0].message, "1 techniques use the unregistered render stage renderstage_new_in_patch");
+        assert!(report.is_ok());
+    }
+
+    #[test]
+    fn shader_stage() {
+        // SHEX version tokens, shader model 5.0
+        let blob = |token: u32| {
+            let mut container = Container::default();
+            container.set_part(FourCC::SHEX, token.to_le_bytes().to_vec());
+            let mut data = container.to_bytes();
+            digest::resign(&mut data);
+            data
+        };
+
+        let mut cache = test_cache();
+        cache.shaders[0].compiled = blob(0x0001_0050);
+        cache.shaders[1].compiled = blob(0x0005_0050);
+        cache.info = cache.layout();
+
+        let report = validate(&cache);
+        assert_eq!(report.findings.len(), 1, "{}", report);
+        assert!(report.findings[0].message.ends_with("uses [0000000000000002] as PS, but it is a Compute shader"));
+    }
+
+    #[test]
+    fn shader_digest() {
+        let mut cache = test_cache();
+        cache.shaders[0].compiled = Container::default().to_bytes();
+        cache.info = cache.layout();
+
+        let report = validate(&cache);
+        assert!(report.has(Check::ShaderDigest));
+
+        digest::resign(&mut cache.shaders[0].compiled);
+        assert!(validate(&cache).findings.is_empty());
+    }
+
+    #[test]
+    fn bindings() {
+        let mut container = Container::default();
+        container.set_part(FourCC::RDEF, rdef(&[("Color", 0, 16), ("Tint", 32, 16)], &[0, 3]));
+
+        let mut cache = test_cache();
+        cache.shaders[1].compiled = container.to_bytes();
+        cache.materials[0].ps_samplers = vec![ sampler(0), sampler(5) ];
+        cache.params[0].params = vec![
+            ParamChunk { name: CName::new("Color"), value: 0, size: 1 },
+            ParamChunk { name: CName::new("Tint"), value: 1, size: 1 },
+            ParamChunk { name: CName::new("Missing"), value: 3, size: 1 },
+        ];
+        cache.info = cache.layout();
+        digest::resign(&mut cache.shaders[1].compiled);
+
+        let report = validate(&cache);
+        let messages: Vec<&str> = report.findings.iter().map(|f| f.message.as_str()).collect();
+        assert_eq!(messages.len(), 4, "{}", report);
+        assert!(messages[0].ends_with("has a PS sampler state for register s5, which [0000000000000002] doesn't declare"));
+        assert!(messages[1].ends_with("has no PS sampler state for register s3 declared by [0000000000000002]"));
+        assert_eq!(messages[2], "param Tint of shader [0000000000000002] has slot 1 but is at offset 32 of Material");
+        assert_eq!(messages[3], "param Missing of shader [0000000000000002] isn't in any constant buffer");
+        assert_eq!(report.count(Severity::Error), 1);
+    }
+
+    #[test]
+    fn linkage() {
+        let blob = |fourcc: FourCC, part: Vec<u8>| {
+            let mut container = Container::default();
+            container.set_part(fourcc, part);
+            let mut data = container.to_bytes();
+            digest::resign(&mut data);
+            data
+        };
+
+        let mut cache = test_cache();
+        cache.shaders[0].compiled = blob(FourCC::OSG1, isg1(&[("SV_Position", 0, 1, 3, 0, 0b1111), ("TEXCOORD", 0, 0, 3, 1, 0b0011)]));
+        cache.shaders[1].compiled = blob(FourCC::ISG1, isg1(&[("SV_Position", 0, 1, 3, 0, 0b1111), ("TEXCOORD", 0, 0, 3, 1, 0b0011)]));
+        cache.info = cache.layout();
+        assert!(validate(&cache).findings.is_empty());
+
+        cache.shaders[1].compiled = blob(FourCC::ISG1, isg1(&[("SV_Position", 0, 1, 3, 0, 0b1111), ("TEXCOORD", 0, 0, 3, 2, 0b0011)]));
+        let report = validate(&cache);
+        assert_eq!(report.findings.len(), 1, "{}", report);
+        assert_eq!(report.findings[0].check, Check::Signature);
+        assert!(report.findings[0].message.starts_with(&format!("{} VS [0000000000000001] to PS [0000000000000002]: ", cache.materials[0].name)));
+        assert_eq!(super::linkage(&cache).findings.len(), 1);
+
+        // Saving refuses to write anything unless validation is turned off
+        let mut data = std::io::Cursor::new(Vec::new());
+        assert!(cache.save(&mut data).is_err());
+        assert!(data.get_ref().is_empty());
+
+        let options = SaveOptions { validate: false, ..Default::default() };
+        cache.save_with(&mut data, &options).unwrap();
+        assert!(!data.get_ref().is_empty());
+    }
+}
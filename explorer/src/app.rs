@@ -10,8 +10,7 @@ use shaderpunk::hashmap::CNameKey32;
 use shaderpunk::manager::Manager;
 use shaderpunk::material::Material;
 use shaderpunk::bundle::dyn_cache::DynamicCacheFile;
-
-use crate::optimize::optimize_cache;
+use shaderpunk::optimize::dedup_shaders;
 
 pub struct App {
     run_once: bool,
@@ -64,7 +63,7 @@ impl App {
 
     fn save_cache(&mut self, to: PathBuf) -> Result<()> {
         
-        let cache = {
+        let mut cache = {
             let (map_read, _) = vmap::Map::with_options()
                 .open(self.cache_path.clone().unwrap())
                 .context("VMAP: Failed to open shader cache")?;
@@ -75,7 +74,8 @@ impl App {
         };
 
         // Poke the cache file
-        let opt_cache = optimize_cache(&cache)?;
+        let report = dedup_shaders(&mut cache);
+        print!("{}", report);
 
         
         let map_write = File::create(to)
@@ -83,7 +83,7 @@ impl App {
         
         let mut writer = std::io::BufWriter::new(map_write);
 
-        opt_cache.save(&mut writer).context("Failed to save shader cache")?;
+        cache.save(&mut writer).context("Failed to save shader cache")?;
 
         writer.flush()?;
 
@@ -172,7 +172,7 @@ impl App {
 
                         body.row(30.0, |mut row| {
                             row.col(|ui| { ui.label(format!("{}", t.desc.index)); });
-                            row.col(|ui| { ui.label(t.desc.pass.clone()); });
+                            row.col(|ui| { ui.label(t.desc.pass.to_string()); });
                             row.col(|ui| { ui.label(vfs.join(" ")); });
                             row.col(|ui| { ui.label(match &t.vs {
                                 Some(vs) => { format!("{:016x}", vs.hash) },
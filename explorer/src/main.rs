@@ -2,7 +2,6 @@
 use app::App;
 
 pub mod app;
-pub mod optimize;
 
 fn main() -> eframe::Result {
 